@@ -1,256 +1,1774 @@
-use crate::terminal::Position;
-use std::{fs::File, io::Write};
+use crate::encoding;
+use crate::swap;
+use crate::undo_file::{self, Reader, Writer};
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use ropey::{Rope, RopeSlice};
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::fs::Metadata;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use std::{fs::File, io::BufWriter, io::Write};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// A position in the document, counted in lines and grapheme clusters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub x: usize,
+    pub y: usize,
+}
+
+impl Location {
+    pub const fn new(x: usize, y: usize) -> Self {
+        Self { x, y }
+    }
+
+    pub fn left(&mut self) {
+        self.x = self.x.saturating_sub(1);
+    }
+
+    pub fn right(&mut self) {
+        self.x += 1;
+    }
+
+    pub fn down(&mut self) {
+        self.y += 1;
+    }
+}
+
+impl Ord for Location {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.y, self.x).cmp(&(other.y, other.x))
+    }
+}
+
+impl PartialOrd for Location {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A span of the document for an operator to act on.
+#[derive(Debug, Clone)]
+pub struct Range {
+    pub start: Location,
+    /// The end of the range, exclusive. Ignored apart from its line for linewise ranges.
+    pub end: Location,
+    /// Whether the range covers whole lines, from the start's line to the end's line.
+    pub linewise: bool,
+}
+
+impl Range {
+    /// Creates a range between two locations in either order.
+    pub fn new(a: Location, b: Location, linewise: bool) -> Self {
+        let (start, end) = if a <= b { (a, b) } else { (b, a) };
+        Self {
+            start,
+            end,
+            linewise,
+        }
+    }
+}
 
 pub struct Buffer {
-    pub lines: Vec<String>,
+    text: Rope,
     pub history: History,
     pub file: String,
+    /// Whether saving keeps a copy of the file as it was before, with `~` after its name.
+    pub backup: bool,
+    /// Whether saving is refused, as it is for files that can't be written or read.
+    pub readonly: bool,
+    /// Whether the file is binary data, which is read and saved byte for byte.
+    pub binary: bool,
+    /// How lines end in the file.
+    pub format: FileFormat,
+    /// The encoding the file is read and saved in.
+    pub encoding: &'static Encoding,
+    /// How lines ended and the encoding when the file was last read or saved, as changing
+    /// either is a change to save too.
+    saved_format: FileFormat,
+    saved_encoding: &'static Encoding,
+    /// Whether the file starts with a byte order mark.
+    pub bom: bool,
+    /// Whether the file had bytes that weren't valid in its encoding, which were replaced
+    /// when it was read.
+    pub malformed: bool,
+    /// Whether the file ended with a line break.
+    pub final_newline: bool,
+    /// Whether the file was empty, rather than one empty line, so saving it before any text
+    /// is added leaves it empty.
+    empty_file: bool,
+    /// Whether saving always ends the file with a line break, even if it didn't have one.
+    pub fix_end_of_line: bool,
+    /// Lines marked for `:g` to visit, kept sorted. They follow their text as lines are
+    /// added and removed above them, and are dropped when their line is deleted.
+    marks: Vec<usize>,
+    /// The swap file kept up to date with the text, if this editor has one.
+    swap: Option<PathBuf>,
+    /// Whether the text has changed since the swap file was written.
+    swap_stale: bool,
 }
 
 impl Buffer {
-    pub fn new(lines: Vec<String>, file: String) -> Self {
+    pub fn new(lines: &[String], file: String) -> Self {
+        let text = lines.join("\n");
+
         Self {
-            lines: lines.clone(),
+            empty_file: text.is_empty(),
+            text: Rope::from(text),
             file,
-            history: History {
-                states: vec![lines],
-                cursors: vec![Position::new(0, 0)],
-                index: 0,
-            },
+            saved_format: FileFormat::Unix,
+            saved_encoding: UTF_8,
+            backup: false,
+            readonly: false,
+            binary: false,
+            format: FileFormat::Unix,
+            encoding: UTF_8,
+            bom: false,
+            malformed: false,
+            final_newline: true,
+            fix_end_of_line: false,
+            marks: Vec::new(),
+            swap: None,
+            swap_stale: false,
+            history: History::new(),
         }
     }
 
-    pub fn len(&self) -> u16 {
-        self.lines
-            .len()
-            .try_into()
-            .expect("Couldn't cast length...")
+    /// Creates a buffer for the contents of a file, decoding them in the given encoding or
+    /// else the one they look to be in. How its lines end, whether it has a byte order mark
+    /// and whether it ends with a line break are noted, so saving writes them back. Binary
+    /// files are left as they are.
+    pub fn open(bytes: &[u8], encoding: Option<&'static Encoding>, file: String) -> Self {
+        let binary = encoding.is_none() && encoding::is_binary(bytes);
+
+        // Latin-1 gives every byte a character of its own that is saved as the same byte
+        let encoding = encoding.or_else(|| binary.then_some(WINDOWS_1252));
+        let (contents, encoding, bom, malformed) = encoding::decode(bytes, encoding);
+        let contents = contents.as_str();
+
+        // A file is DOS if most of its lines end with CRLF, and the lines in it that end with
+        // a bare LF are given a CRLF when it is saved. Otherwise any CRs are kept as text.
+        let line_breaks = contents.matches('\n').count();
+        let dos_line_breaks = contents.matches("\r\n").count();
+        let format = if !binary && dos_line_breaks * 2 > line_breaks {
+            FileFormat::Dos
+        } else {
+            FileFormat::Unix
+        };
+        let final_newline = contents.is_empty() || contents.ends_with('\n');
+
+        let lines: Vec<String> = contents
+            .strip_suffix('\n')
+            .unwrap_or(contents)
+            .split('\n')
+            .map(|line| {
+                if format == FileFormat::Dos {
+                    line.strip_suffix('\r').unwrap_or(line).to_string()
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect();
+
+        Self {
+            binary,
+            format,
+            encoding,
+            saved_format: format,
+            saved_encoding: encoding,
+            bom,
+            malformed,
+            final_newline,
+            empty_file: contents.is_empty(),
+            ..Self::new(&lines, file)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.text.len_lines()
+    }
+
+    /// Whether there are changes that haven't been saved. Undoing back to the text as it was
+    /// saved leaves none.
+    pub fn is_modified(&self) -> bool {
+        self.history.is_recording()
+            || !self.history.is_saved()
+            || self.format != self.saved_format
+            || self.encoding != self.saved_encoding
+    }
+
+    pub const fn text(&self) -> &Rope {
+        &self.text
     }
 
     pub fn is_empty(&self) -> bool {
-        self.lines.is_empty()
+        self.text.len_chars() == 0
+    }
+
+    /// Returns the nth line without its line break.
+    pub fn line(&self, n: usize) -> Option<RopeSlice<'_>> {
+        if n >= self.text.len_lines() {
+            return None;
+        }
+
+        let line = self.text.line(n);
+        let len = line.len_chars();
+
+        if len > 0 && line.char(len - 1) == '\n' {
+            Some(line.slice(..len - 1))
+        } else {
+            Some(line)
+        }
     }
 
-    pub fn write(&mut self, pos: &Position, char: char) {
-        let len: u16 = self.lines.len().try_into().unwrap();
+    /// Returns the grapheme clusters of the nth line.
+    pub fn graphemes(&self, n: usize) -> Vec<String> {
+        self.line(n).map_or_else(Vec::new, |line| {
+            line.to_string()
+                .graphemes(true)
+                .map(std::string::ToString::to_string)
+                .collect()
+        })
+    }
 
-        if pos.y < len {
-            let split = self
-                .lines
-                .get(pos.y as usize)
-                .unwrap()
-                .split_at(pos.x as usize);
+    /// Converts a grapheme index on the nth line into a char offset from the start of that line.
+    fn grapheme_to_char(&self, n: usize, x: usize) -> usize {
+        self.graphemes(n)
+            .iter()
+            .take(x)
+            .map(|grapheme| grapheme.chars().count())
+            .sum()
+    }
+
+    /// Converts a location into a char index into the rope, clamping it to the document.
+    pub fn index_of(&self, pos: &Location) -> usize {
+        let y = pos.y.min(self.len() - 1);
+        self.text.line_to_char(y) + self.grapheme_to_char(y, pos.x)
+    }
 
-            let line = format!("{}{}{}", split.0, char, split.1);
-            let mut split_lines = <[&[String]; 2]>::from(self.lines.split_at(pos.y as usize));
-            split_lines[1] = &split_lines[1][1..];
+    /// Converts a char index into the rope into a location.
+    pub fn location_of(&self, index: usize) -> Location {
+        let index = index.min(self.text.len_chars());
+        let y = self.text.char_to_line(index);
+        let x = self
+            .text
+            .slice(self.text.line_to_char(y)..index)
+            .to_string()
+            .graphemes(true)
+            .count();
 
-            self.lines = [
-                split_lines.first().unwrap(),
-                vec![line].as_slice(),
-                split_lines.last().unwrap(),
-            ]
-            .concat();
+        Location::new(x, y)
+    }
+
+    /// Returns the grapheme at a location, or `None` at or past the end of its line.
+    pub fn grapheme(&self, pos: &Location) -> Option<String> {
+        self.graphemes(pos.y).get(pos.x).cloned()
+    }
+
+    /// Returns the char indices a range covers. Linewise ranges include their final line break.
+    fn indices(&self, range: &Range) -> std::ops::Range<usize> {
+        if range.linewise {
+            let end = range.end.y + 1;
+            let end = if end < self.len() {
+                self.text.line_to_char(end)
+            } else {
+                self.text.len_chars()
+            };
+
+            self.text.line_to_char(range.start.y.min(self.len() - 1))..end
         } else {
-            // Add lines to end of document
-            let difference: u16 = pos.y - len;
+            self.index_of(&range.start)..self.index_of(&range.end)
+        }
+    }
 
-            if difference > 0 {
-                for _ in 0..difference {
-                    self.lines.push(String::new());
-                }
+    /// Returns the text in a range. Linewise ranges leave out their final line break.
+    pub fn slice(&self, range: &Range) -> String {
+        let mut text = self.text.slice(self.indices(range)).to_string();
+
+        if range.linewise && text.ends_with('\n') {
+            text.pop();
+        }
+        text
+    }
+
+    /// Inserts text at a location and returns the location just after it.
+    pub fn insert(&mut self, pos: &Location, text: &str) -> Location {
+        let index = self.char_index(pos);
+        self.insert_at(index, text);
+
+        self.location_of(index + text.chars().count())
+    }
+
+    /// Removes the text in a range. Removing the last lines of the document also removes the
+    /// line break before them, so no empty line is left behind.
+    pub fn delete(&mut self, range: &Range) {
+        let mut indices = self.indices(range);
+
+        if range.linewise && indices.end == self.text.len_chars() && indices.start > 0 {
+            indices.start -= 1;
+        }
+        self.remove_at(indices);
+    }
+
+    /// Replaces the text in a range with the result of a function on it.
+    pub fn transform(&mut self, range: &Range, f: impl Fn(&str) -> String) {
+        let indices = self.indices(range);
+        let original = self.text.slice(indices.clone()).to_string();
+        let text = f(&original);
+
+        if text == original {
+            return;
+        }
+        self.remove_at(indices.clone());
+        self.insert_at(indices.start, &text);
+    }
+
+    /// Returns the number of graphemes of leading whitespace on the nth line.
+    pub fn indentation(&self, n: usize) -> usize {
+        self.graphemes(n)
+            .iter()
+            .take_while(|grapheme| grapheme.trim().is_empty())
+            .count()
+    }
+
+    /// Returns how many display columns the leading whitespace of the nth line takes up.
+    pub fn indentation_width(&self, n: usize) -> usize {
+        self.display_column(&Location::new(self.indentation(n), n))
+    }
+
+    /// Replaces the leading whitespace of the nth line with `width` spaces.
+    pub fn set_indentation(&mut self, n: usize, width: usize) {
+        let start = self.text.line_to_char(n);
+        let end = start + self.grapheme_to_char(n, self.indentation(n));
+        let indentation = " ".repeat(width);
+
+        if self.text.slice(start..end) == indentation.as_str() {
+            return;
+        }
+        self.remove_at(start..end);
+        self.insert_at(start, &indentation);
+    }
+
+    /// Joins the lines from `first` to `last` into one, replacing each line break and the
+    /// indentation after it with a space. Returns where the last join happened.
+    pub fn join(&mut self, first: usize, last: usize) -> Location {
+        let mut pos = Location::new(self.nth_line_len(first), first);
+
+        for _ in first..last.min(self.len() - 1) {
+            let x = self.nth_line_len(first);
+            let line_break = self.text.line_to_char(first + 1) - 1;
+            let indentation = self.grapheme_to_char(first + 1, self.indentation(first + 1));
+
+            self.remove_at(line_break..line_break + indentation + 1);
+
+            // No space is needed next to an empty line or existing whitespace
+            let ends_blank = x == 0 || self.text.char(line_break - 1).is_whitespace();
+            if !ends_blank && self.nth_line_len(first) > x {
+                self.insert_at(line_break, " ");
             }
-            self.lines.push(String::from(char));
+            pos = Location::new(x, first);
         }
+        pos
     }
 
-    pub fn backspace(&mut self, pos: &Position) -> Backspace {
-        if pos.y < self.lines.len().try_into().unwrap() && pos.x > 0 {
-            let split = self
-                .lines
-                .get(pos.y as usize)
-                .unwrap()
-                .split_at(pos.x as usize);
+    /// Pads the nth line with spaces until it is at least `column` display columns wide, and
+    /// returns the location at that column.
+    pub fn pad_to(&mut self, n: usize, column: usize) -> Location {
+        let mut end = Location::new(self.nth_line_len(n), n);
 
-            let line = format!(
-                "{}{}",
-                split.0.strip_suffix(|_: char| true).unwrap(),
-                split.1
-            );
-            let mut split_lines = <[&[String]; 2]>::from(self.lines.split_at(pos.y as usize));
-            split_lines[1] = &split_lines[1][1..];
-
-            self.lines = [
-                split_lines.first().unwrap(),
-                vec![line].as_slice(),
-                split_lines.last().unwrap(),
-            ]
-            .concat();
+        while self.display_column(&end) < column {
+            end = self.write(&end, ' ');
+        }
+        Location::new(self.column_to_grapheme(n, column), n)
+    }
+
+    /// Converts a cursor position into a char index into the rope, padding the document
+    /// with empty lines if the position lies past its end.
+    fn char_index(&mut self, pos: &Location) -> usize {
+        let len = self.text.len_lines();
+
+        if pos.y >= len {
+            let end = self.text.len_chars();
+            self.insert_at(end, &"\n".repeat(pos.y - len + 1));
+        }
+
+        self.text.line_to_char(pos.y) + self.grapheme_to_char(pos.y, pos.x)
+    }
+
+    /// Inserts a char and returns the cursor position after it. Combining marks merge into the
+    /// preceding grapheme, so the cursor doesn't always advance.
+    pub fn write(&mut self, pos: &Location, char: char) -> Location {
+        let index = self.char_index(pos);
+        self.insert_at(index, char.encode_utf8(&mut [0; 4]));
+
+        let line_start = self.text.line_to_char(pos.y);
+        let x = self
+            .text
+            .slice(line_start..=index)
+            .to_string()
+            .graphemes(true)
+            .count();
+
+        Location::new(x, pos.y)
+    }
+
+    pub fn backspace(&mut self, pos: &Location) -> Backspace {
+        if pos.y >= self.len() {
             return Backspace::SameLine;
-        } else if pos.x <= 1 && pos.y < self.lines.len().try_into().unwrap() {
-            let mut split = self.lines.split_at(pos.y as usize);
-
-            if self.nth_line_len(pos.y.into()) == 0 && pos.y > 0 {
-                // Delete a line
-                split.1 = &split.1[1..];
-
-                self.lines = <[&[String]; 2]>::from(split).concat();
-
-                return Backspace::WrapLines(Position::new(
-                    self.nth_line_len((pos.y - 1) as usize),
-                    pos.y - 1,
-                ));
-            } else if pos.y > 0 {
-                // Wrap line onto line above
-                let wrapped_line_len = self.nth_line_len(pos.y.into());
-                self.lines = [
-                    &split.0[..split.0.len() - 1],
-                    vec![split.0.last().unwrap().to_owned() + split.1.first().unwrap()].as_slice(),
-                    &split.1[1..],
-                ]
-                .concat();
-                return Backspace::WrapLines(Position::new(
-                    self.nth_line_len((pos.y - 1) as usize) - wrapped_line_len,
-                    pos.y - 1,
-                ));
-            };
+        }
+
+        let index = self.char_index(pos);
+
+        if pos.x > 0 {
+            let previous = self.text.line_to_char(pos.y) + self.grapheme_to_char(pos.y, pos.x - 1);
+            self.remove_at(previous..index);
+        } else if pos.y > 0 {
+            // Wrap line onto line above
+            let above = pos.y - 1;
+            let above_len = self.nth_line_len(above);
+            self.remove_at(index - 1..index);
+
+            return Backspace::WrapLines(Location::new(above_len, above));
         }
         Backspace::SameLine
     }
 
-    pub fn enter(&mut self, pos: &Position) {
-        if pos.y >= self.lines.len().try_into().unwrap() {
-            self.new_line(pos);
-        } else if pos.x
-            < self
-                .lines
-                .get(pos.y as usize)
-                .unwrap()
-                .len()
-                .try_into()
-                .unwrap()
-        {
-            let split = self.lines.split_at(pos.y as usize);
-            let split_lines = split.1.first().unwrap().split_at(pos.x as usize);
-            self.lines = [
-                split.0,
-                vec![split_lines.0.to_string()].as_slice(),
-                vec![split_lines.1.to_string()].as_slice(),
-                &split.1[1..],
-            ]
-            .concat();
+    pub fn enter(&mut self, pos: &Location) {
+        let index = self.char_index(pos);
+        self.insert_at(index, "\n");
+    }
+
+    pub fn new_line(&mut self, pos: &Location) {
+        if pos.y < self.len() {
+            let index = self.text.line_to_char(pos.y);
+            self.insert_at(index, "\n");
+        } else {
+            self.char_index(pos);
         }
     }
 
-    pub fn new_line(&mut self, pos: &Position) {
-        let len = self.lines.len().try_into().unwrap();
+    /// Inserts text at a char index, recording it for undo and moving the marks on the lines
+    /// after it down.
+    fn insert_at(&mut self, index: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if !self.history.is_recording() {
+            self.history.start(self.location_of(index));
+        }
+        self.swap_stale = true;
+        self.empty_file = false;
+        self.history.record(Edit {
+            index,
+            removed: String::new(),
+            inserted: text.to_string(),
+        });
 
-        if pos.y < len {
-            let split = self.lines.split_at(pos.y as usize);
-            self.lines = [split.0, vec![String::new()].as_slice(), split.1].concat();
-        } else {
-            for _ in 0..=(pos.y - len) {
-                self.lines.push(String::new());
+        let added = text.matches('\n').count();
+
+        if added > 0 {
+            let line = self.text.char_to_line(index);
+
+            // Text put at the start of a line pushes that line down too
+            let moved = if index == self.text.line_to_char(line) {
+                line
+            } else {
+                line + 1
+            };
+            for mark in self.marks.iter_mut().filter(|mark| **mark >= moved) {
+                *mark += added;
             }
         }
+        self.text.insert(index, text);
     }
 
-    pub fn nth_line_len(&self, n: usize) -> u16 {
-        self.lines
-            .get(n)
-            .map_or(0, |str| str.len().try_into().unwrap())
+    /// Removes the chars in a range, recording it for undo, dropping the marks on the lines
+    /// removed and moving the ones after them up.
+    fn remove_at(&mut self, indices: std::ops::Range<usize>) {
+        if indices.is_empty() {
+            return;
+        }
+        if !self.history.is_recording() {
+            self.history.start(self.location_of(indices.start));
+        }
+        self.swap_stale = true;
+        self.history.record(Edit {
+            index: indices.start,
+            removed: self.text.slice(indices.clone()).to_string(),
+            inserted: String::new(),
+        });
+
+        let first = self.text.char_to_line(indices.start);
+        let last = self.text.char_to_line(indices.end);
+
+        if last > first {
+            // Whole lines go if the range runs from the start of one line to the start of
+            // another, otherwise the lines after the first are joined onto it
+            let whole = indices.start == self.text.line_to_char(first)
+                && indices.end == self.text.line_to_char(last);
+            let removed = if whole {
+                first..last
+            } else {
+                first + 1..last + 1
+            };
+
+            self.marks.retain(|mark| !removed.contains(mark));
+            for mark in self.marks.iter_mut().filter(|mark| **mark >= removed.end) {
+                *mark -= last - first;
+            }
+        }
+        self.text.remove(indices);
+    }
+
+    /// Marks lines for `:g` to visit, replacing any marked before.
+    pub fn mark_lines(&mut self, lines: Vec<usize>) {
+        self.marks = lines;
     }
 
-    pub fn save(&mut self) -> Result<(), std::io::Error> {
-        let mut file = File::create(self.file.clone())?;
-        File::set_len(&file, 0)?;
+    /// Unmarks and returns the first marked line.
+    pub fn next_mark(&mut self) -> Option<usize> {
+        (!self.marks.is_empty()).then(|| self.marks.remove(0))
+    }
+
+    /// Returns the length of the nth line in grapheme clusters.
+    pub fn nth_line_len(&self, n: usize) -> usize {
+        self.graphemes(n).len()
+    }
 
-        for line in &self.lines {
-            writeln!(file, "{line}")?;
+    /// Returns the display column at which the grapheme at `pos` starts.
+    pub fn display_column(&self, pos: &Location) -> usize {
+        let columns = columns(&self.graphemes(pos.y));
+        columns[pos.x.min(columns.len() - 1)]
+    }
+
+    /// Returns the index of the grapheme on the nth line covering the given display column,
+    /// or the line's length if the column lies past its end.
+    pub fn column_to_grapheme(&self, n: usize, column: usize) -> usize {
+        let columns = columns(&self.graphemes(n));
+
+        columns[1..]
+            .iter()
+            .position(|end| *end > column)
+            .unwrap_or(columns.len() - 1)
+    }
+
+    /// Writes the buffer to its file, returning warnings about anything that went wrong
+    /// after the text itself was saved.
+    pub fn save(&mut self) -> Result<Vec<String>, Error> {
+        if self.readonly {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "'readonly' is set (:set noreadonly to override)",
+            ));
         }
-        Ok(())
+
+        let warnings = self.write_to(&self.file)?;
+        self.saved_format = self.format;
+        self.saved_encoding = self.encoding;
+        self.history.mark_saved();
+
+        // Failing to keep the history shouldn't stop the file being saved
+        self.write_history().ok();
+        Ok(warnings)
     }
 
-    pub fn update_history(&mut self, cursor: Position) {
-        if self.history.is_in_past() {
-            self.history.decapitate();
+    /// Writes the undo history to the file's undo file, for `read_history` to load when the
+    /// file is opened again.
+    fn write_history(&self) -> Result<(), std::io::Error> {
+        let path = undo_file::state_path("undo", &self.file).ok_or_else(undo_file::invalid)?;
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
         }
-        self.history.update(self.lines.clone(), cursor);
+        std::fs::write(path, self.history.encode(undo_file::hash(&self.text)))
     }
 
-    pub fn undo(&mut self) -> Option<Position> {
-        if self.history.index > 0 {
-            self.history.rollback();
-            self.lines.clone_from(
-                self.history
-                    .states
-                    .get(self.history.index as usize)
-                    .unwrap(),
-            );
-            return Some(
-                self.history
-                    .cursors
-                    .get(self.history.index as usize)
-                    .unwrap()
-                    .clone(),
-            );
+    /// Loads the undo history saved with the file, if the file hasn't changed since then.
+    pub fn read_history(&mut self) {
+        let history = undo_file::state_path("undo", &self.file)
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|bytes| History::decode(&bytes, undo_file::hash(&self.text)).ok())
+            .filter(|history| history.fits(&self.text));
+
+        if let Some(history) = history {
+            self.history = history;
         }
-        None
     }
 
-    pub fn redo(&mut self) -> Option<Position> {
-        if self.history.is_in_past() {
-            self.history.rollforward();
-            self.lines.clone_from(
-                self.history
-                    .states
-                    .get(self.history.index as usize)
-                    .unwrap(),
-            );
-            return Some(
-                self.history
-                    .cursors
-                    .get(self.history.index as usize)
-                    .unwrap()
-                    .clone(),
-            );
+    /// Writes the buffer to a file without changing which file the buffer belongs to. The
+    /// text goes to a temporary file next to it, which only replaces the file once it is
+    /// safely on disk, so a crash or a full disk never leaves the file half written. Returns
+    /// warnings about anything that went wrong after the text was saved.
+    pub fn write_to(&self, path: &str) -> Result<Vec<String>, Error> {
+        // Write through symlinks to the file they point to, even one that doesn't exist yet
+        let target = resolve_links(Path::new(path));
+        let original = std::fs::metadata(&target).ok();
+
+        // Permission bits don't say whether this user owns the file, so try opening it
+        if original.is_some() {
+            File::options()
+                .write(true)
+                .open(&target)
+                .map_err(|err| match err.kind() {
+                    ErrorKind::PermissionDenied => {
+                        Error::new(ErrorKind::PermissionDenied, format!("{path} is read-only"))
+                    }
+                    _ => err,
+                })?;
+        }
+
+        let mut name = target.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".beditor-{}", std::process::id()));
+        let temporary = target.with_file_name(name);
+
+        let mut warnings = match self.write_temporary(&temporary, original.as_ref()) {
+            Ok(warnings) => warnings,
+            Err(err) => {
+                std::fs::remove_file(&temporary).ok();
+                return Err(err);
+            }
+        };
+
+        if self.backup && original.is_some() {
+            let mut backup = target.clone().into_os_string();
+            backup.push("~");
+
+            if let Err(err) = std::fs::copy(&target, backup) {
+                warnings.push(format!("couldn't write backup: {err}"));
+            }
+        }
+
+        if let Err(err) = std::fs::rename(&temporary, &target) {
+            std::fs::remove_file(&temporary).ok();
+            return Err(err);
+        }
+
+        // Make sure the rename is on disk too. Not every platform can sync a directory.
+        if let Some(dir) = target.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            File::open(dir).and_then(|dir| dir.sync_all()).ok();
+        }
+        Ok(warnings)
+    }
+
+    /// Writes the text to a new file and syncs it to disk, giving it the permissions and
+    /// owner of the file it is going to replace.
+    fn write_temporary(
+        &self,
+        path: &Path,
+        original: Option<&Metadata>,
+    ) -> Result<Vec<String>, Error> {
+        let line_break = match self.format {
+            FileFormat::Unix => "\n",
+            FileFormat::Dos => "\r\n",
+        };
+        let mut text = String::new();
+
+        // Only Unicode encodings have a byte order mark
+        if self.bom && encoding::is_unicode(self.encoding) {
+            text.push('\u{feff}');
+        }
+
+        // A file with one empty line still has its line break
+        if !(self.empty_file && self.is_empty()) {
+            for chunk in self.text.chunks() {
+                text.push_str(&chunk.replace('\n', line_break));
+            }
+
+            if self.fix_end_of_line || self.final_newline {
+                text.push_str(line_break);
+            }
+        }
+
+        let bytes = encoding::encode(&text, self.encoding).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Cannot save as {}, which can't represent every character",
+                    encoding::name(self.encoding)
+                ),
+            )
+        })?;
+
+        let mut file = BufWriter::new(File::options().write(true).create_new(true).open(path)?);
+        file.write_all(&bytes)?;
+        file.flush()?;
+        file.get_ref().sync_all()?;
+
+        let mut warnings = Vec::new();
+        let Some(original) = original else {
+            return Ok(warnings);
+        };
+
+        if let Err(err) = std::fs::set_permissions(path, original.permissions()) {
+            warnings.push(format!("couldn't keep permissions: {err}"));
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            if let Err(err) =
+                std::os::unix::fs::chown(path, Some(original.uid()), Some(original.gid()))
+            {
+                warnings.push(format!("couldn't keep owner: {err}"));
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// Ends the change being recorded, so that it is undone in one step, and notes where
+    /// the cursor ended up for redoing it.
+    pub fn update_history(&mut self, cursor: Location) {
+        self.history.finish(cursor);
+    }
+
+    /// Starts keeping a swap file for the buffer, replacing any left over.
+    pub fn claim_swap(&mut self) {
+        self.swap = swap::path(&self.file);
+        self.swap_stale = true;
+        self.update_swap();
+    }
+
+    /// Starts keeping a swap file for a buffer with no file of its own, which no other buffer
+    /// has.
+    pub fn claim_unnamed_swap(&mut self) {
+        self.swap = swap::unnamed_path();
+        self.swap_stale = true;
+        self.update_swap();
+    }
+
+    /// Writes the text to the swap file if it has changed since it was last written.
+    pub fn update_swap(&mut self) {
+        if let Some(path) = self.swap.as_ref().filter(|_| self.swap_stale) {
+            // Failing to write it leaves the last one written, which is the best there is
+            self.swap_stale = swap::write(path, &self.text).is_err();
+        }
+    }
+
+    /// Deletes the swap file, when the buffer is closed normally.
+    pub fn remove_swap(&mut self) {
+        if let Some(path) = self.swap.take() {
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    /// Replaces the text with the text recovered from a swap file, as one change that can be
+    /// undone to get back to the file as it was saved.
+    pub fn recover(&mut self, text: &str) {
+        self.remove_at(0..self.text.len_chars());
+        self.insert_at(0, text);
+        self.update_history(Location::new(0, 0));
+    }
+
+    /// Reverts the last change and returns where it started.
+    pub fn undo(&mut self) -> Option<Location> {
+        self.history.interrupt();
+        let parent = self.history.parent()?;
+        self.travel(parent)
+    }
+
+    /// Applies the last change undone again and returns where it left the cursor.
+    pub fn redo(&mut self) -> Option<Location> {
+        self.history.interrupt();
+        let child = self.history.child()?;
+        self.travel(child)
+    }
+
+    /// Goes back or forward through the undo tree, across branches, in the order the changes
+    /// were made, for `g-`, `g+`, `:earlier` and `:later`.
+    pub fn time_travel(&mut self, step: Step, forward: bool) -> Option<Location> {
+        self.history.interrupt();
+        let target = self.history.target(step, forward);
+        self.travel(target)
+    }
+
+    /// Undoes and redoes changes to get to a state in the undo tree, and returns where the
+    /// cursor goes, or `None` if the buffer is already in that state.
+    fn travel(&mut self, target: usize) -> Option<Location> {
+        let (undo, redo) = self.history.path(target);
+        let mut cursor = None;
+
+        for node in undo {
+            let change = self.history.change(node);
+
+            for edit in change.edits.iter().rev() {
+                edit.revert(&mut self.text);
+            }
+            cursor = Some(change.before.clone());
+            self.history.leave(node);
+        }
+
+        for node in redo {
+            let change = self.history.change(node);
+
+            for edit in &change.edits {
+                edit.apply(&mut self.text);
+            }
+            cursor = Some(change.after.clone());
+            self.history.enter(node);
+        }
+
+        self.swap_stale |= cursor.is_some();
+        cursor
+    }
+}
+
+/// How many columns apart tab stops are.
+const TAB_STOP: usize = 8;
+
+/// Returns the number of columns a grapheme takes up when drawn at a display column. A tab
+/// reaches to the next tab stop.
+pub fn grapheme_width(grapheme: &str, column: usize) -> usize {
+    if grapheme == "\t" {
+        TAB_STOP - column % TAB_STOP
+    } else {
+        grapheme.width()
+    }
+}
+
+/// Returns the display column each grapheme of a line starts at, followed by the one the line
+/// ends at.
+pub fn columns(graphemes: &[String]) -> Vec<usize> {
+    let mut columns = vec![0];
+    let mut column = 0;
+
+    for grapheme in graphemes {
+        column += grapheme_width(grapheme, column);
+        columns.push(column);
+    }
+    columns
+}
+
+/// Follows a symlink, and any it points to in turn, to the path at the end of the chain,
+/// whether or not there is a file there. Links are relative to the directory they are in.
+fn resolve_links(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+
+    // Give up on links that loop, as the system does after following a few dozen
+    for _ in 0..40 {
+        let Ok(link) = std::fs::read_link(&path) else {
+            break;
+        };
+        path = path
+            .parent()
+            .map_or_else(|| link.clone(), |dir| dir.join(&link));
+    }
+    path
+}
+
+/// How lines end in a file, which `:set fileformat` changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    /// `\n`
+    Unix,
+    /// `\r\n`
+    Dos,
+}
+
+impl FileFormat {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Unix => "unix",
+            Self::Dos => "dos",
         }
-        None
     }
 }
 
 pub enum Backspace {
-    WrapLines(Position),
+    WrapLines(Location),
     SameLine,
 }
 
+/// Text removed and inserted at a char index.
+struct Edit {
+    index: usize,
+    removed: String,
+    inserted: String,
+}
+
+impl Edit {
+    fn apply(&self, text: &mut Rope) {
+        text.remove(self.index..self.index + self.removed.chars().count());
+        text.insert(self.index, &self.inserted);
+    }
+
+    fn revert(&self, text: &mut Rope) {
+        text.remove(self.index..self.index + self.inserted.chars().count());
+        text.insert(self.index, &self.removed);
+    }
+
+    /// Whether the text has `expected` where the edit was made, as it does before the edit
+    /// if that's the text removed, or after it if that's the text inserted.
+    fn finds(&self, text: &Rope, expected: &str) -> bool {
+        let end = self.index + expected.chars().count();
+        end <= text.len_chars() && text.slice(self.index..end) == expected
+    }
+}
+
+/// The edits made by one command, which are undone and redone together.
+struct Change {
+    edits: Vec<Edit>,
+    /// Where the change started, for the cursor to go back to when it is undone.
+    before: Location,
+    /// Where the cursor was after the change, for it to go back to when it is redone.
+    after: Location,
+}
+
+/// A state of the text in the undo tree, made by applying its change to its parent's state.
+struct Node {
+    change: Change,
+    parent: usize,
+    /// The child redo goes to, which is the one last made or undone.
+    redo: Option<usize>,
+    time: SystemTime,
+}
+
+/// What an undo file starts with, to tell it apart from other files and older formats.
+const MAGIC: &[u8] = b"beditor undo 1\n";
+
+/// How far `:earlier` and `:later` go through the undo tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Changes(usize),
+    Seconds(u64),
+    /// A number of states the file was saved in.
+    Saves(usize),
+}
+
+/// The changes made to the buffer, as a tree so that undoing and then making a new change
+/// starts a branch rather than losing the changes undone. Each change only stores the text
+/// it removed and inserted, so the history grows with the size of the edits rather than the
+/// size of the file.
 pub struct History {
-    pub states: Vec<Vec<String>>,
-    pub cursors: Vec<Position>,
-    pub index: u32,
+    /// The states in the order they were made, starting with the text as it was opened.
+    nodes: Vec<Node>,
+    /// The state the text is in.
+    current: usize,
+    /// The states the file was saved in.
+    saves: BTreeSet<usize>,
+    /// The state the text was in when the file was last read or saved, or `None` if that
+    /// state has been merged away.
+    saved: Option<usize>,
+    /// The change being made, until the command making it finishes.
+    recording: Option<Change>,
 }
 
 impl History {
-    pub fn update(&mut self, new: Vec<String>, cursor: Position) {
-        self.states.push(new);
-        self.cursors.push(cursor);
-        self.index += 1;
+    fn new() -> Self {
+        let start = Location::new(0, 0);
+
+        Self {
+            nodes: vec![Node {
+                change: Change {
+                    edits: Vec::new(),
+                    before: start.clone(),
+                    after: start,
+                },
+                parent: 0,
+                redo: None,
+                time: SystemTime::now(),
+            }],
+            current: 0,
+            saves: BTreeSet::new(),
+            saved: Some(0),
+            recording: None,
+        }
+    }
+
+    fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Starts recording a change at a location.
+    fn start(&mut self, before: Location) {
+        self.recording = Some(Change {
+            edits: Vec::new(),
+            before: before.clone(),
+            after: before,
+        });
+    }
+
+    /// Adds an edit to the change being recorded. Text typed straight after the last text
+    /// inserted is added to the same edit.
+    fn record(&mut self, edit: Edit) {
+        let Some(change) = &mut self.recording else {
+            return;
+        };
+
+        if let Some(last) = change.edits.last_mut() {
+            let end = last.index + last.inserted.chars().count();
+
+            if edit.removed.is_empty() && last.removed.is_empty() && edit.index == end {
+                last.inserted.push_str(&edit.inserted);
+                return;
+            }
+        }
+        change.edits.push(edit);
+    }
+
+    /// Adds the change being recorded to the tree as a child of the current state. Nothing is
+    /// added if nothing was edited.
+    fn finish(&mut self, after: Location) {
+        if let Some(mut change) = self.recording.take() {
+            change.after = after;
+            self.nodes.push(Node {
+                change,
+                parent: self.current,
+                redo: None,
+                time: SystemTime::now(),
+            });
+            self.enter(self.nodes.len() - 1);
+        }
+    }
+
+    /// Finishes a change that is still being recorded, so it is undone before the ones
+    /// before it.
+    fn interrupt(&mut self) {
+        if let Some(before) = self.recording.as_ref().map(|change| change.before.clone()) {
+            self.finish(before);
+        }
+    }
+
+    fn mark_saved(&mut self) {
+        self.interrupt();
+        self.saves.insert(self.current);
+        self.saved = Some(self.current);
+    }
+
+    /// Whether the text is in the state it was in when the file was last read or saved.
+    fn is_saved(&self) -> bool {
+        self.saved == Some(self.current)
+    }
+
+    fn change(&self, node: usize) -> &Change {
+        &self.nodes[node].change
+    }
+
+    /// Returns the state before the current one, or `None` at the oldest state.
+    fn parent(&self) -> Option<usize> {
+        (self.current > 0).then(|| self.nodes[self.current].parent)
+    }
+
+    /// Returns the state redo goes to from the current one.
+    fn child(&self) -> Option<usize> {
+        self.nodes[self.current].redo
+    }
+
+    /// Moves to a child of the current state, which redo then goes to from here.
+    fn enter(&mut self, node: usize) {
+        let parent = self.nodes[node].parent;
+
+        self.nodes[parent].redo = Some(node);
+        self.current = node;
+    }
+
+    /// Moves from a state to its parent, keeping the state for redo to go back to.
+    fn leave(&mut self, node: usize) {
+        let parent = self.nodes[node].parent;
+
+        self.nodes[parent].redo = Some(node);
+        self.current = parent;
+    }
+
+    /// Returns the states whose changes need undoing, then redoing, to get from the current
+    /// state to another, which are those up to the state they both come from and then down
+    /// from it.
+    fn path(&self, target: usize) -> (Vec<usize>, Vec<usize>) {
+        let ancestors = |mut node: usize| {
+            let mut nodes = vec![node];
+
+            while node != 0 {
+                node = self.nodes[node].parent;
+                nodes.push(node);
+            }
+            nodes
+        };
+        let (mut undo, mut redo) = (ancestors(self.current), ancestors(target));
+
+        while undo.last().is_some() && undo.last() == redo.last() {
+            undo.pop();
+            redo.pop();
+        }
+        redo.reverse();
+        (undo, redo)
+    }
+
+    /// Returns the state a step back or forward from the current one.
+    fn target(&self, step: Step, forward: bool) -> usize {
+        let newest = self.nodes.len() - 1;
+
+        match step {
+            Step::Changes(n) if forward => self.current.saturating_add(n).min(newest),
+            Step::Changes(n) => self.current.saturating_sub(n),
+            Step::Seconds(seconds) => {
+                let now = self.nodes[self.current].time;
+                let time = if forward {
+                    // Past the end of time is after every state
+                    match now.checked_add(Duration::from_secs(seconds)) {
+                        Some(time) => time,
+                        None => return newest,
+                    }
+                } else {
+                    now.checked_sub(Duration::from_secs(seconds))
+                        .unwrap_or(SystemTime::UNIX_EPOCH)
+                };
+
+                // The last state made by that time
+                self.nodes
+                    .iter()
+                    .rposition(|node| node.time <= time)
+                    .unwrap_or(0)
+            }
+            // Going back from changes made since a save goes to that save first, and
+            // going forward past the last save goes to the newest state
+            Step::Saves(n) if forward => self
+                .saves
+                .range(self.current + 1..)
+                .nth(n.saturating_sub(1))
+                .copied()
+                .unwrap_or(newest),
+            Step::Saves(n) => self
+                .saves
+                .range(..self.current)
+                .nth_back(n.saturating_sub(1))
+                .copied()
+                .unwrap_or(0),
+        }
+    }
+
+    /// Returns the number the next state made will have, for `merge_since`.
+    pub fn checkpoint(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Merges the changes made one after another since a checkpoint into one, so they are
+    /// undone together.
+    pub fn merge_since(&mut self, checkpoint: usize) {
+        let newest = self.nodes.len() - 1;
+        let linear = self.current == newest
+            && (checkpoint + 1..=newest).all(|node| self.nodes[node].parent == node - 1);
+
+        if newest > checkpoint && linear {
+            // Only the last of the states merged is left, in place of the first
+            self.saved = match self.saved {
+                Some(saved) if saved == newest => Some(checkpoint),
+                Some(saved) if saved >= checkpoint => None,
+                saved => saved,
+            };
+            let merged_saves = self.saves.split_off(&checkpoint);
+
+            if merged_saves.contains(&newest) {
+                self.saves.insert(checkpoint);
+            }
+
+            let mut merged = self.nodes.drain(checkpoint..);
+            let mut node = merged.next().unwrap();
+
+            for next in merged {
+                node.change.edits.extend(next.change.edits);
+                node.change.after = next.change.after;
+                node.time = next.time;
+            }
+            self.nodes.push(node);
+            self.enter(checkpoint);
+        }
+    }
+
+    /// Encodes the tree for an undo file, along with a hash of the text it is for.
+    fn encode(&self, hash: u64) -> Vec<u8> {
+        let mut writer = Writer::default();
+
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.bytes.extend_from_slice(&hash.to_le_bytes());
+        writer.number(self.current);
+        writer.number(self.nodes.len());
+
+        for node in &self.nodes {
+            writer.number(node.parent);
+            writer.number(node.redo.map_or(0, |redo| redo + 1));
+            writer.number(
+                node.time
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs() as usize,
+            );
+            writer.location(&node.change.before);
+            writer.location(&node.change.after);
+            writer.number(node.change.edits.len());
+
+            for edit in &node.change.edits {
+                writer.number(edit.index);
+                writer.text(&edit.removed);
+                writer.text(&edit.inserted);
+            }
+        }
+
+        writer.number(self.saves.len());
+        for save in &self.saves {
+            writer.number(*save);
+        }
+        writer.bytes
+    }
+
+    /// Decodes a tree from an undo file, failing if it was saved for text with another hash.
+    fn decode(bytes: &[u8], hash: u64) -> Result<Self, std::io::Error> {
+        let header = [MAGIC, &hash.to_le_bytes()].concat();
+        let Some(rest) = bytes.strip_prefix(header.as_slice()) else {
+            return Err(undo_file::invalid());
+        };
+        let mut reader = Reader::new(rest);
+        let node = |n: usize, len: usize| (n < len).then_some(n).ok_or_else(undo_file::invalid);
+
+        let current = reader.number()?;
+        let len = reader.number()?;
+        let mut nodes = Vec::new();
+
+        for i in 0..len {
+            // Every state comes after the one it was made from, so the tree has no cycles
+            let parent = node(reader.number()?, i.max(1))?;
+            let redo = match reader.number()? {
+                0 => None,
+                redo => Some(node(redo - 1, len)?),
+            };
+            let time = SystemTime::UNIX_EPOCH
+                .checked_add(Duration::from_secs(reader.number()? as u64))
+                .ok_or_else(undo_file::invalid)?;
+            let before = reader.location()?;
+            let after = reader.location()?;
+            let mut edits = Vec::new();
+
+            for _ in 0..reader.number()? {
+                edits.push(Edit {
+                    index: reader.number()?,
+                    removed: reader.text()?,
+                    inserted: reader.text()?,
+                });
+            }
+
+            nodes.push(Node {
+                change: Change {
+                    edits,
+                    before,
+                    after,
+                },
+                parent,
+                redo,
+                time,
+            });
+        }
+
+        // Redo goes to a state made from this one
+        let children = nodes.iter().enumerate().all(|(i, node)| {
+            node.redo
+                .is_none_or(|redo| redo != 0 && nodes[redo].parent == i)
+        });
+        if !children {
+            return Err(undo_file::invalid());
+        }
+
+        let mut saves = BTreeSet::new();
+        for _ in 0..reader.number()? {
+            saves.insert(node(reader.number()?, len)?);
+        }
+
+        let current = node(current, len)?;
+
+        Ok(Self {
+            current,
+            nodes,
+            saves,
+            saved: Some(current),
+            recording: None,
+        })
     }
 
-    pub fn decapitate(&mut self) {
-        self.states.truncate(self.index.try_into().unwrap());
-        self.cursors.truncate(self.index.try_into().unwrap());
+    /// Whether every change in the tree can be undone and redone on the text, which is in the
+    /// current state, so a damaged undo file can't have edits outside it.
+    fn fits(&self, text: &Rope) -> bool {
+        let mut text = text.clone();
+        let mut node = self.current;
+
+        // Back to the text as it was opened
+        while node != 0 {
+            for edit in self.nodes[node].change.edits.iter().rev() {
+                if !edit.finds(&text, &edit.inserted) {
+                    return false;
+                }
+                edit.revert(&mut text);
+            }
+            node = self.nodes[node].parent;
+        }
+
+        // Then forward along every branch, each state after the one it was made from
+        let mut texts = vec![text];
+        for node in &self.nodes[1..] {
+            let mut text = texts[node.parent].clone();
+
+            for edit in &node.change.edits {
+                if !edit.finds(&text, &edit.removed) {
+                    return false;
+                }
+                edit.apply(&mut text);
+            }
+            texts.push(text);
+        }
+        true
     }
 
-    pub fn is_in_past(&self) -> bool {
-        self.states.len() - 1 > self.index.try_into().unwrap()
+    /// Describes the newest state on each branch of the tree for `:undolist`: its number, how
+    /// many changes it is from the text as it was opened, and how long ago it was made.
+    pub fn list(&self) -> String {
+        let leaves: Vec<String> = (1..self.nodes.len())
+            .filter(|node| self.nodes.iter().all(|other| other.parent != *node))
+            .map(|node| {
+                let mut changes = 0;
+                let mut ancestor = node;
+
+                while ancestor != 0 {
+                    ancestor = self.nodes[ancestor].parent;
+                    changes += 1;
+                }
+
+                let seconds = self.nodes[node]
+                    .time
+                    .elapsed()
+                    .unwrap_or_default()
+                    .as_secs();
+                let ago = match seconds {
+                    0..60 => format!("{seconds}s"),
+                    60..3600 => format!("{}m", seconds / 60),
+                    _ => format!("{}h", seconds / 3600),
+                };
+                let saved = if self.saves.contains(&node) {
+                    ", saved"
+                } else {
+                    ""
+                };
+
+                let plural = if changes == 1 { "" } else { "s" };
+
+                format!("{node}: {changes} change{plural}, {ago} ago{saved}")
+            })
+            .collect();
+
+        if leaves.is_empty() {
+            String::from("Nothing to undo")
+        } else {
+            leaves.join(" | ")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str) -> Buffer {
+        let lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        Buffer::new(&lines, String::new())
     }
 
-    pub fn rollback(&mut self) {
-        self.index -= 1;
+    /// Inserts text as one command, which is one step to undo.
+    fn type_text(buffer: &mut Buffer, x: usize, y: usize, text: &str) {
+        let end = buffer.insert(&Location::new(x, y), text);
+        buffer.update_history(end);
     }
 
-    pub fn rollforward(&mut self) {
-        self.index += 1;
+    #[test]
+    fn undo_and_redo() {
+        let mut buffer = buffer("one\ntwo");
+        type_text(&mut buffer, 3, 0, "!");
+        buffer.delete(&Range::new(Location::new(0, 1), Location::new(0, 1), true));
+        buffer.update_history(Location::new(0, 0));
+        assert_eq!(buffer.text().to_string(), "one!");
+
+        // Deleting the last line starts at the line break before it
+        assert_eq!(buffer.undo(), Some(Location::new(4, 0)));
+        assert_eq!(buffer.text().to_string(), "one!\ntwo");
+        assert_eq!(buffer.undo(), Some(Location::new(3, 0)));
+        assert_eq!(buffer.text().to_string(), "one\ntwo");
+        assert_eq!(buffer.undo(), None);
+
+        assert_eq!(buffer.redo(), Some(Location::new(4, 0)));
+        assert_eq!(buffer.redo(), Some(Location::new(0, 0)));
+        assert_eq!(buffer.text().to_string(), "one!");
+        assert_eq!(buffer.redo(), None);
+    }
+
+    #[test]
+    fn typing_is_undone_in_one_step() {
+        let mut buffer = buffer("");
+
+        for (x, c) in "abc".chars().enumerate() {
+            buffer.insert(&Location::new(x, 0), &c.to_string());
+        }
+        buffer.update_history(Location::new(3, 0));
+
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "");
+        assert_eq!(buffer.history.checkpoint(), 2);
+    }
+
+    #[test]
+    fn merged_changes_are_undone_together() {
+        let mut buffer = buffer("");
+        let checkpoint = buffer.history.checkpoint();
+        type_text(&mut buffer, 0, 0, "a");
+        type_text(&mut buffer, 1, 0, "b");
+        buffer.history.merge_since(checkpoint);
+
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "");
+        buffer.redo();
+        assert_eq!(buffer.text().to_string(), "ab");
+    }
+
+    #[test]
+    fn merging_keeps_the_last_save() {
+        let mut buffer = buffer("");
+        let checkpoint = buffer.history.checkpoint();
+        type_text(&mut buffer, 0, 0, "a");
+        buffer.history.mark_saved();
+        type_text(&mut buffer, 1, 0, "b");
+        buffer.history.mark_saved();
+        buffer.history.merge_since(checkpoint);
+
+        assert_eq!(buffer.history.saves, BTreeSet::from([checkpoint]));
+        assert!(!buffer.is_modified());
+        buffer.time_travel(Step::Saves(1), false);
+        assert_eq!(buffer.text().to_string(), "");
+
+        // A save in the middle of the merged changes is gone along with its state
+        let checkpoint = buffer.history.checkpoint();
+        type_text(&mut buffer, 0, 0, "c");
+        buffer.history.mark_saved();
+        type_text(&mut buffer, 1, 0, "d");
+        buffer.history.merge_since(checkpoint);
+        assert_eq!(buffer.history.saves, BTreeSet::from([1]));
+        assert!(buffer.is_modified());
+    }
+
+    #[test]
+    fn changes_after_undo_start_a_branch() {
+        let mut buffer = buffer("");
+        type_text(&mut buffer, 0, 0, "a");
+        type_text(&mut buffer, 1, 0, "b");
+        buffer.undo();
+        type_text(&mut buffer, 1, 0, "c");
+        assert_eq!(buffer.text().to_string(), "ac");
+
+        // Redo follows the newest branch, while going back in time reaches the other one
+        buffer.undo();
+        buffer.redo();
+        assert_eq!(buffer.text().to_string(), "ac");
+        buffer.time_travel(Step::Changes(1), false);
+        assert_eq!(buffer.text().to_string(), "ab");
+        buffer.time_travel(Step::Changes(2), false);
+        assert_eq!(buffer.text().to_string(), "");
+        buffer.time_travel(Step::Changes(5), true);
+        assert_eq!(buffer.text().to_string(), "ac");
+        assert_eq!(buffer.time_travel(Step::Changes(1), true), None);
+    }
+
+    #[test]
+    fn travel_far_in_time() {
+        let mut buffer = buffer("");
+        type_text(&mut buffer, 0, 0, "a");
+        type_text(&mut buffer, 1, 0, "b");
+        buffer.undo();
+        buffer.undo();
+
+        buffer.time_travel(Step::Changes(usize::MAX), true);
+        assert_eq!(buffer.text().to_string(), "ab");
+        buffer.time_travel(Step::Seconds(u64::MAX), false);
+        assert_eq!(buffer.text().to_string(), "");
+        buffer.time_travel(Step::Seconds(u64::MAX), true);
+        assert_eq!(buffer.text().to_string(), "ab");
+    }
+
+    #[test]
+    fn travel_by_saves() {
+        let mut buffer = buffer("");
+        type_text(&mut buffer, 0, 0, "a");
+        buffer.history.mark_saved();
+        type_text(&mut buffer, 1, 0, "b");
+        buffer.history.mark_saved();
+        type_text(&mut buffer, 2, 0, "c");
+
+        // Changes since the last save go back to it first
+        buffer.time_travel(Step::Saves(1), false);
+        assert_eq!(buffer.text().to_string(), "ab");
+        buffer.time_travel(Step::Saves(1), false);
+        assert_eq!(buffer.text().to_string(), "a");
+        buffer.time_travel(Step::Saves(1), false);
+        assert_eq!(buffer.text().to_string(), "");
+        buffer.time_travel(Step::Saves(2), true);
+        assert_eq!(buffer.text().to_string(), "ab");
+        buffer.time_travel(Step::Saves(1), true);
+        assert_eq!(buffer.text().to_string(), "abc");
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let mut buffer = buffer("one");
+        type_text(&mut buffer, 3, 0, "\ntwo");
+        buffer.history.mark_saved();
+        type_text(&mut buffer, 0, 0, "é");
+        buffer.undo();
+        type_text(&mut buffer, 0, 1, "2 ");
+        let bytes = buffer.history.encode(7);
+
+        let history = History::decode(&bytes, 7).unwrap();
+        assert!(history.fits(buffer.text()));
+        assert_eq!(history.current, buffer.history.current);
+        assert_eq!(history.saves, buffer.history.saves);
+        assert_eq!(history.encode(7), bytes);
+
+        // The history works on the text as it was saved with it
+        let mut reopened =
+            Buffer::new(&[String::from("one"), String::from("2 two")], String::new());
+        reopened.history = history;
+        assert!(!reopened.is_modified());
+        reopened.undo();
+        assert_eq!(reopened.text().to_string(), "one\ntwo");
+        reopened.time_travel(Step::Changes(1), true);
+        assert_eq!(reopened.text().to_string(), "éone\ntwo");
+        reopened.time_travel(Step::Changes(3), false);
+        assert_eq!(reopened.text().to_string(), "one");
+    }
+
+    #[test]
+    fn decode_rejects_other_files() {
+        let mut buffer = buffer("one");
+        type_text(&mut buffer, 0, 0, "a");
+        let bytes = buffer.history.encode(7);
+
+        assert!(History::decode(&bytes, 8).is_err());
+        assert!(History::decode(&bytes[..bytes.len() - 1], 7).is_err());
+        assert!(History::decode(b"not an undo file", 7).is_err());
+
+        // A time too far in the future to hold
+        let mut bad_time = bytes.clone();
+        let time = MAGIC.len() + 8 * 5;
+        bad_time[time..time + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(History::decode(&bad_time, 7).is_err());
+
+        // An undo file for the same hash but other text has edits that don't fit it
+        let history = History::decode(&bytes, 7).unwrap();
+        assert!(!history.fits(&Rope::from("bone")));
+        assert!(!history.fits(&Rope::from("")));
+    }
+
+    #[test]
+    fn edits_that_change_nothing_are_not_changes() {
+        let mut buffer = buffer("abc");
+        type_text(&mut buffer, 1, 0, "");
+        buffer.delete(&Range::new(Location::new(1, 0), Location::new(1, 0), false));
+        buffer.transform(
+            &Range::new(Location::new(0, 0), Location::new(2, 0), false),
+            |text| text.to_string(),
+        );
+        buffer.update_history(Location::new(0, 0));
+
+        assert!(!buffer.is_modified());
+        assert_eq!(buffer.undo(), None);
+    }
+
+    #[test]
+    fn undoing_to_the_saved_text_is_unmodified() {
+        let mut buffer = buffer("text");
+        assert!(!buffer.is_modified());
+
+        type_text(&mut buffer, 0, 0, "more ");
+        assert!(buffer.is_modified());
+        buffer.undo();
+        assert!(!buffer.is_modified());
+        buffer.redo();
+        buffer.history.mark_saved();
+        assert!(!buffer.is_modified());
+        buffer.undo();
+        assert!(buffer.is_modified());
+
+        buffer.redo();
+        buffer.format = FileFormat::Dos;
+        assert!(buffer.is_modified());
+    }
+
+    #[test]
+    fn lines() {
+        let buffer = buffer("one\ntwo\n");
+
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.line(1).unwrap(), "two");
+        assert_eq!(buffer.line(2).unwrap(), "");
+        assert!(buffer.line(3).is_none());
+        assert_eq!(buffer.index_of(&Location::new(1, 1)), 5);
+        assert_eq!(buffer.location_of(5), Location::new(1, 1));
+        // Past the end of the document is clamped to it
+        assert_eq!(buffer.location_of(100), Location::new(0, 2));
+    }
+
+    #[test]
+    fn inserting_and_deleting() {
+        let mut buffer = buffer("one\nthree");
+
+        let end = buffer.insert(&Location::new(3, 0), "\ntwo");
+        assert_eq!(end, Location::new(3, 1));
+        assert_eq!(buffer.text().to_string(), "one\ntwo\nthree");
+
+        let lines = Range::new(Location::new(0, 0), Location::new(0, 1), true);
+        assert_eq!(buffer.slice(&lines), "one\ntwo");
+        buffer.delete(&Range::new(Location::new(1, 0), Location::new(1, 1), false));
+        assert_eq!(buffer.text().to_string(), "owo\nthree");
+
+        // Deleting the last line takes the line break before it
+        buffer.delete(&Range::new(Location::new(0, 1), Location::new(0, 1), true));
+        assert_eq!(buffer.text().to_string(), "owo");
+    }
+
+    #[test]
+    fn splitting_and_joining_lines() {
+        let mut buffer = buffer("onetwo\n    three");
+
+        buffer.enter(&Location::new(3, 0));
+        assert_eq!(buffer.text().to_string(), "one\ntwo\n    three");
+
+        assert_eq!(buffer.join(0, 2), Location::new(7, 0));
+        assert_eq!(buffer.text().to_string(), "one two three");
+
+        buffer.new_line(&Location::new(0, 0));
+        assert_eq!(buffer.text().to_string(), "\none two three");
+        assert!(matches!(
+            buffer.backspace(&Location::new(0, 1)),
+            Backspace::WrapLines(pos) if pos == Location::new(0, 0)
+        ));
+        assert_eq!(buffer.text().to_string(), "one two three");
+    }
+
+    #[test]
+    fn indentation() {
+        let mut buffer = buffer("  \t x");
+
+        assert_eq!(buffer.indentation(0), 4);
+        buffer.set_indentation(0, 2);
+        assert_eq!(buffer.text().to_string(), "  x");
+    }
+
+    #[test]
+    fn graphemes() {
+        let buffer = buffer("e\u{301}👍🏽日\tx");
+
+        assert_eq!(buffer.graphemes(0), ["e\u{301}", "👍🏽", "日", "\t", "x"]);
+        assert_eq!(buffer.nth_line_len(0), 5);
+        assert_eq!(buffer.index_of(&Location::new(2, 0)), 4);
+        assert_eq!(buffer.location_of(4), Location::new(2, 0));
+    }
+
+    #[test]
+    fn display_columns() {
+        let buffer = buffer("a日\tb\n\t\tc");
+
+        assert_eq!(columns(&buffer.graphemes(0)), [0, 1, 3, 8, 9]);
+        assert_eq!(buffer.display_column(&Location::new(2, 0)), 3);
+        assert_eq!(buffer.display_column(&Location::new(9, 0)), 9);
+        assert_eq!(buffer.display_column(&Location::new(2, 1)), 16);
+
+        // A column inside a wide character or tab is on that grapheme
+        assert_eq!(buffer.column_to_grapheme(0, 2), 1);
+        assert_eq!(buffer.column_to_grapheme(0, 7), 2);
+        assert_eq!(buffer.column_to_grapheme(0, 8), 3);
+        assert_eq!(buffer.column_to_grapheme(0, 50), 4);
+        assert_eq!(grapheme_width("\t", 5), 3);
+        assert_eq!(grapheme_width("日", 5), 2);
+    }
+
+    #[test]
+    fn editing_graphemes() {
+        let mut buffer = buffer("ae");
+
+        // A combining mark joins the grapheme before it, so the cursor stays after it
+        assert_eq!(
+            buffer.write(&Location::new(2, 0), '\u{301}'),
+            Location::new(2, 0)
+        );
+        assert_eq!(buffer.nth_line_len(0), 2);
+
+        // Backspace removes the whole grapheme
+        buffer.backspace(&Location::new(2, 0));
+        assert_eq!(buffer.text().to_string(), "a");
+    }
+
+    /// Opens a file's contents and saves them again, after changing the buffer's options.
+    fn round_trip(bytes: &[u8], options: impl FnOnce(&mut Buffer)) -> Vec<u8> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static FILES: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "beditor-round-trip-{}-{}",
+            std::process::id(),
+            FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let path = path.to_str().unwrap();
+        let mut buffer = Buffer::open(bytes, None, path.to_string());
+
+        options(&mut buffer);
+        buffer.write_to(path).unwrap();
+        let saved = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        saved
+    }
+
+    #[test]
+    fn saving_keeps_the_end_of_the_file() {
+        assert_eq!(round_trip(b"a\nb", |_| {}), b"a\nb");
+        assert_eq!(round_trip(b"a\r\nb\r\n\r\n", |_| {}), b"a\r\nb\r\n\r\n");
+        assert_eq!(round_trip(b"", |_| {}), b"");
+        assert_eq!(
+            round_trip(b"a\nbc", |buffer| buffer.fix_end_of_line = true),
+            b"a\nbc\n"
+        );
+    }
+
+    #[test]
+    fn saving_keeps_mixed_line_endings() {
+        let unix = b"a\nb\r\nc\nd\n";
+        assert_eq!(
+            Buffer::open(unix, None, String::new()).format,
+            FileFormat::Unix
+        );
+        assert_eq!(round_trip(unix, |_| {}), unix);
+
+        // Lines that end in LF in a mostly DOS file are the odd ones out
+        let dos = b"a\r\nb\nc\r\nd\r\n";
+        assert_eq!(
+            Buffer::open(dos, None, String::new()).format,
+            FileFormat::Dos
+        );
+        assert_eq!(round_trip(dos, |_| {}), b"a\r\nb\r\nc\r\nd\r\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saving_through_symlinks() {
+        let dir = std::env::temp_dir().join(format!("beditor-links-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let link = dir.join("link");
+        let path = link.to_str().unwrap();
+
+        // A link to a file that doesn't exist yet creates it
+        std::os::unix::fs::symlink("missing", &link).unwrap();
+        buffer("new").write_to(path).unwrap();
+        assert_eq!(std::fs::read(dir.join("missing")).unwrap(), b"new\n");
+
+        std::fs::remove_file(&link).unwrap();
+        std::os::unix::fs::symlink(dir.join("missing"), &link).unwrap();
+        buffer("again").write_to(path).unwrap();
+        assert!(std::fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(std::fs::read(dir.join("missing")).unwrap(), b"again\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,317 @@
+use crate::buffer::Location;
+use crate::editor::Cursor;
+use crate::view::View;
+
+/// A buffer that is open but not being shown, with where its cursor was left.
+pub struct Hidden {
+    pub number: usize,
+    pub view: View,
+    pub cursor: Cursor,
+}
+
+/// The buffers open besides the one being shown, numbered in the order they were opened.
+pub struct BufferList {
+    /// The number of the buffer being shown.
+    pub current: usize,
+    /// The number of the buffer shown before it, for Ctrl-^.
+    pub alternate: Option<usize>,
+    /// Kept in order of their numbers.
+    hidden: Vec<Hidden>,
+    next_number: usize,
+}
+
+impl BufferList {
+    pub const fn new() -> Self {
+        Self {
+            current: 1,
+            alternate: None,
+            hidden: Vec::new(),
+            next_number: 2,
+        }
+    }
+
+    /// Adds a buffer without showing it, and returns its number.
+    pub fn add(&mut self, view: View) -> usize {
+        let number = self.next_number;
+        self.next_number += 1;
+        self.hidden.push(Hidden {
+            number,
+            view,
+            cursor: Cursor::new(Location::new(0, 0)),
+        });
+        number
+    }
+
+    /// Shows a hidden buffer in place of the one being shown, which is hidden with its cursor.
+    /// Returns false if no hidden buffer has the number.
+    pub fn switch(&mut self, number: usize, view: &mut View, cursor: &mut Cursor) -> bool {
+        let Some(hidden) = self
+            .hidden
+            .iter_mut()
+            .find(|hidden| hidden.number == number)
+        else {
+            return false;
+        };
+
+        std::mem::swap(&mut hidden.view, view);
+        std::mem::swap(&mut hidden.cursor, cursor);
+        hidden.number = self.current;
+        self.hidden.sort_by_key(|hidden| hidden.number);
+
+        self.alternate = Some(self.current);
+        self.current = number;
+        true
+    }
+
+    /// Makes a buffer current when the cursor moves to a window showing it, keeping the one
+    /// that was current with the given view and cursor.
+    pub fn make_current(&mut self, number: usize, view: View, cursor: Cursor) {
+        if number == self.current {
+            return;
+        }
+
+        self.hidden.retain(|hidden| hidden.number != number);
+        self.hidden.push(Hidden {
+            number: self.current,
+            view,
+            cursor,
+        });
+        self.hidden.sort_by_key(|hidden| hidden.number);
+
+        self.alternate = Some(self.current);
+        self.current = number;
+    }
+
+    /// Takes a hidden buffer out of the list, returning its view.
+    pub fn remove(&mut self, number: usize) -> Option<View> {
+        let i = self
+            .hidden
+            .iter()
+            .position(|hidden| hidden.number == number)?;
+
+        if self.alternate == Some(number) {
+            self.alternate = None;
+        }
+        Some(self.hidden.remove(i).view)
+    }
+
+    /// Gives the buffer being shown a new number, for when it replaces one that was deleted.
+    pub fn renumber_current(&mut self) {
+        self.current = self.next_number;
+        self.next_number += 1;
+    }
+
+    /// Returns the number of the buffer `count` places after the one being shown, or before
+    /// it, wrapping around the ends of the list.
+    pub fn cycle(&self, count: usize, forward: bool) -> usize {
+        let mut numbers: Vec<usize> = self.hidden.iter().map(|hidden| hidden.number).collect();
+        let i = numbers.partition_point(|number| *number < self.current);
+        numbers.insert(i, self.current);
+
+        let len = numbers.len();
+        let step = count % len;
+        numbers[if forward {
+            (i + step) % len
+        } else {
+            (i + len - step) % len
+        }]
+    }
+
+    /// Returns the number of the buffer editing a file, given `current`, the file the buffer
+    /// being shown is editing.
+    pub fn find_file(&self, file: &str, current: &str) -> Option<usize> {
+        if file == current {
+            return Some(self.current);
+        }
+        self.hidden
+            .iter()
+            .find(|hidden| hidden.view.file() == file)
+            .map(|hidden| hidden.number)
+    }
+
+    /// Returns the number of the buffer whose file matches a name, or has it as part of its
+    /// path if none matches it exactly, or the message to show if there isn't exactly one.
+    pub fn find(&self, name: &str, current: &str) -> Result<usize, String> {
+        if let Some(number) = self.find_file(name, current) {
+            return Ok(number);
+        }
+
+        let files = std::iter::once((self.current, current.to_string())).chain(
+            self.hidden
+                .iter()
+                .map(|hidden| (hidden.number, hidden.view.file())),
+        );
+        let mut matches = files.filter(|(_, file)| file.contains(name));
+
+        match (matches.next(), matches.next()) {
+            (Some((number, _)), None) => Ok(number),
+            (Some(_), Some(_)) => Err(format!("More than one match for {name}")),
+            (None, _) => Err(format!("No matching buffer for {name}")),
+        }
+    }
+
+    pub fn get(&self, number: usize) -> Option<&Hidden> {
+        self.hidden.iter().find(|hidden| hidden.number == number)
+    }
+
+    /// Whether a buffer with the number is open.
+    pub fn contains(&self, number: usize) -> bool {
+        number == self.current || self.hidden.iter().any(|hidden| hidden.number == number)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hidden.is_empty()
+    }
+
+    /// Returns the first hidden buffer with changes that haven't been saved.
+    pub fn modified(&self) -> Option<&Hidden> {
+        self.hidden.iter().find(|hidden| hidden.view.is_modified())
+    }
+
+    pub fn hidden_mut(&mut self) -> impl Iterator<Item = &mut Hidden> {
+        self.hidden.iter_mut()
+    }
+
+    /// Lists the buffers for `:ls`, marking the one being shown with `%`, the alternate one
+    /// with `#` and those with unsaved changes with `+`, along with the line the cursor is on.
+    pub fn list(&self, view: &View, cursor: &Cursor) -> String {
+        let mut buffers: Vec<(usize, &View, &Cursor)> = self
+            .hidden
+            .iter()
+            .map(|hidden| (hidden.number, &hidden.view, &hidden.cursor))
+            .collect();
+        let i = buffers.partition_point(|(number, ..)| *number < self.current);
+        buffers.insert(i, (self.current, view, cursor));
+
+        buffers
+            .iter()
+            .map(|(number, view, cursor)| {
+                let flag = if *number == self.current {
+                    "%"
+                } else if Some(*number) == self.alternate {
+                    "#"
+                } else {
+                    " "
+                };
+                let modified = if view.is_modified() { "+" } else { " " };
+
+                format!(
+                    "{number} {flag}{modified} \"{}\" line {}",
+                    view.file(),
+                    cursor.position().y + 1
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::view::{tests::view, Wrap};
+
+    fn named(file: &str) -> View {
+        let mut view = view("", 80, 23, Wrap::Scroll);
+        view.buffer_mut().file = file.to_string();
+        view
+    }
+
+    /// A list with `src/main.rs` shown and `src/lib.rs` and `README.md` hidden.
+    fn buffers() -> (BufferList, View, Cursor) {
+        let mut list = BufferList::new();
+        assert_eq!(list.add(named("src/lib.rs")), 2);
+        assert_eq!(list.add(named("README.md")), 3);
+        (list, named("src/main.rs"), Cursor::new(Location::new(0, 0)))
+    }
+
+    #[test]
+    fn switching() {
+        let (mut list, mut view, mut cursor) = buffers();
+
+        assert!(list.switch(3, &mut view, &mut cursor));
+        assert_eq!(view.file(), "README.md");
+        assert_eq!(list.current, 3);
+        assert_eq!(list.alternate, Some(1));
+        assert_eq!(list.get(1).unwrap().view.file(), "src/main.rs");
+
+        // Ctrl-^ goes back
+        assert!(list.switch(1, &mut view, &mut cursor));
+        assert_eq!(view.file(), "src/main.rs");
+        assert_eq!(list.alternate, Some(3));
+        assert!(!list.switch(7, &mut view, &mut cursor));
+    }
+
+    #[test]
+    fn cycling() {
+        let (mut list, mut view, mut cursor) = buffers();
+
+        assert_eq!(list.cycle(1, true), 2);
+        assert_eq!(list.cycle(1, false), 3);
+        assert_eq!(list.cycle(5, true), 3);
+
+        list.switch(2, &mut view, &mut cursor);
+        assert_eq!(list.cycle(1, true), 3);
+        assert_eq!(list.cycle(1, false), 1);
+    }
+
+    #[test]
+    fn finding() {
+        let (list, view, _) = buffers();
+        let current = view.file();
+
+        assert_eq!(list.find("README.md", &current), Ok(3));
+        assert_eq!(list.find("main", &current), Ok(1));
+        assert_eq!(list.find_file("src/lib.rs", &current), Some(2));
+        assert_eq!(list.find_file("lib.rs", &current), None);
+        assert_eq!(
+            list.find("src", &current),
+            Err(String::from("More than one match for src"))
+        );
+        assert_eq!(
+            list.find("nope", &current),
+            Err(String::from("No matching buffer for nope"))
+        );
+    }
+
+    #[test]
+    fn removing() {
+        let (mut list, mut view, mut cursor) = buffers();
+        list.switch(3, &mut view, &mut cursor);
+
+        assert_eq!(list.remove(1).unwrap().file(), "src/main.rs");
+        assert_eq!(list.alternate, None);
+        assert!(!list.contains(1));
+        assert!(list.contains(3));
+        assert!(list.remove(1).is_none());
+
+        // A buffer put in place of the one deleted gets a number of its own
+        list.renumber_current();
+        assert_eq!(list.current, 4);
+    }
+
+    #[test]
+    fn making_current() {
+        let (mut list, view, cursor) = buffers();
+
+        list.make_current(2, view, cursor);
+        assert_eq!(list.current, 2);
+        assert_eq!(list.alternate, Some(1));
+        assert!(list.get(2).is_none());
+        assert_eq!(list.get(1).unwrap().view.file(), "src/main.rs");
+    }
+
+    #[test]
+    fn listing() {
+        let (mut list, mut view, mut cursor) = buffers();
+        list.switch(2, &mut view, &mut cursor);
+        view.buffer_mut().insert(&Location::new(0, 0), "\n");
+        let cursor = Cursor::new(Location::new(0, 1));
+
+        assert_eq!(
+            list.list(&view, &cursor),
+            "1 #  \"src/main.rs\" line 1 | 2 %+ \"src/lib.rs\" line 2 | 3    \"README.md\" line 1"
+        );
+        assert!(list.modified().is_none());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::terminal::{execute, print};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use std::io::{Error, Write};
+use std::process::{Command, Stdio};
+
+/// Somewhere outside the editor that the `"+` and `"*` registers copy to and paste from.
+/// `primary` picks the X11 primary selection for `"*` over the clipboard for `"+`.
+pub trait Clipboard {
+    fn copy(&mut self, text: &str, primary: bool) -> Result<(), Error>;
+    /// Returns the text on the clipboard, or `None` if there is nothing to paste.
+    fn paste(&mut self, primary: bool) -> Result<Option<String>, Error>;
+}
+
+/// Picks a clipboard for the environment: the Wayland or X11 clipboard tools when they are
+/// installed, or OSC 52 otherwise.
+pub fn detect() -> Box<dyn Clipboard> {
+    let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+    let x11 = std::env::var_os("DISPLAY").is_some();
+
+    [
+        (wayland, Tool::WlClipboard),
+        (x11, Tool::Xclip),
+        (x11, Tool::Xsel),
+        (cfg!(target_os = "macos"), Tool::Pbcopy),
+    ]
+    .into_iter()
+    .find(|(available, tool)| *available && is_installed(tool.program(false)))
+    .map_or_else(
+        || Box::new(Osc52::default()) as Box<dyn Clipboard>,
+        |(_, tool)| Box::new(tool),
+    )
+}
+
+fn is_installed(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+/// Sets the terminal's own clipboard with an OSC 52 escape sequence, which works over SSH too.
+/// Terminals rarely let it be read back, so pasting gives whatever was last copied.
+#[derive(Default)]
+struct Osc52 {
+    copied: [Option<String>; 2],
+}
+
+impl Clipboard for Osc52 {
+    fn copy(&mut self, text: &str, primary: bool) -> Result<(), Error> {
+        let target = if primary { 'p' } else { 'c' };
+
+        print(format!(
+            "\x1b]52;{target};{}\x07",
+            BASE64_STANDARD.encode(text)
+        ))?;
+        execute()?;
+        self.copied[usize::from(primary)] = Some(text.to_string());
+        Ok(())
+    }
+
+    fn paste(&mut self, primary: bool) -> Result<Option<String>, Error> {
+        Ok(self.copied[usize::from(primary)].clone())
+    }
+}
+
+/// An external program that copies from its input and pastes to its output.
+#[derive(Clone, Copy)]
+enum Tool {
+    WlClipboard,
+    Xclip,
+    Xsel,
+    /// `pbcopy` and `pbpaste` on macOS, which have no primary selection.
+    Pbcopy,
+}
+
+impl Tool {
+    const fn program(self, paste: bool) -> &'static str {
+        match (self, paste) {
+            (Self::WlClipboard, false) => "wl-copy",
+            (Self::WlClipboard, true) => "wl-paste",
+            (Self::Xclip, _) => "xclip",
+            (Self::Xsel, _) => "xsel",
+            (Self::Pbcopy, false) => "pbcopy",
+            (Self::Pbcopy, true) => "pbpaste",
+        }
+    }
+
+    fn command(self, paste: bool, primary: bool) -> Command {
+        let mut command = Command::new(self.program(paste));
+
+        let arguments: &[&str] = match (self, paste) {
+            (Self::WlClipboard, false) => &[],
+            (Self::WlClipboard, true) => &["--no-newline"],
+            (Self::Xclip, false) => &["-in", "-selection"],
+            (Self::Xclip, true) => &["-out", "-selection"],
+            (Self::Xsel, false) => &["--input"],
+            (Self::Xsel, true) => &["--output"],
+            (Self::Pbcopy, _) => &[],
+        };
+        command.args(arguments);
+
+        match self {
+            Self::WlClipboard if primary => {
+                command.arg("--primary");
+            }
+            Self::Xclip => {
+                command.arg(if primary { "primary" } else { "clipboard" });
+            }
+            Self::Xsel => {
+                command.arg(if primary { "--primary" } else { "--clipboard" });
+            }
+            _ => (),
+        }
+        command
+    }
+}
+
+impl Clipboard for Tool {
+    fn copy(&mut self, text: &str, primary: bool) -> Result<(), Error> {
+        let mut child = self
+            .command(false, primary)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        child.wait()?;
+        Ok(())
+    }
+
+    fn paste(&mut self, primary: bool) -> Result<Option<String>, Error> {
+        let output = self
+            .command(true, primary)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+
+        Ok(output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).into_owned()))
+    }
+}
@@ -0,0 +1,909 @@
+use crate::buffer::Step;
+use crate::encoding;
+use encoding_rs::Encoding;
+
+/// An ex command entered on the command line after `:`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// `:w [file]` writes the buffer, to another file if one is given.
+    Write(Option<String>),
+    /// `:q` quits, refusing to discard unsaved changes unless forced with `:q!`.
+    Quit { force: bool },
+    /// `:wq` writes the buffer and quits.
+    WriteQuit,
+    /// `:x` writes the buffer only if it has changed, then quits.
+    Exit,
+    /// `:e [++enc=encoding] [file]` opens a file in a buffer of its own, or reloads the current
+    /// one if no file is given, reading it in the encoding if one is given.
+    Edit {
+        file: Option<String>,
+        force: bool,
+        encoding: Option<&'static Encoding>,
+    },
+    /// `:saveas file` writes the buffer to a new file and switches to editing it.
+    SaveAs(String),
+    /// `:42` moves the cursor to a line.
+    Goto(Address),
+    /// `:set option` changes an editor option.
+    Set(String),
+    /// `:[range]s/pattern/replacement/[flags]` replaces matches of a pattern on each line of a
+    /// range, or the cursor's line if no range is given.
+    Substitute {
+        range: LineRange,
+        pattern: String,
+        replacement: String,
+        flags: Flags,
+    },
+    /// `:[range]d` deletes lines, the cursor's line if no range is given.
+    Delete(LineRange),
+    /// `:normal keys` runs keys as if they were typed in normal mode.
+    Normal(String),
+    /// `:[range]g/pattern/command` runs a command on each line of a range that matches a
+    /// pattern, or on each line that doesn't for `:g!` and `:v`. The range is every line if
+    /// none is given.
+    Global {
+        range: LineRange,
+        pattern: String,
+        invert: bool,
+        command: Box<Command>,
+    },
+    /// `:earlier 5m` goes back through the undo tree by a number of changes, an amount of
+    /// time or a number of saves.
+    Earlier(Step),
+    /// `:later 30s` goes forward through the undo tree.
+    Later(Step),
+    /// `:undolist` lists the branches of the undo tree.
+    UndoList,
+    /// `:ls` lists the open buffers.
+    Buffers,
+    /// `:b 2` or `:b name` shows another open buffer.
+    Buffer(BufferId),
+    /// `:bn [count]` shows the buffer after the current one, or `count` buffers after it.
+    BufferNext(usize),
+    /// `:bp [count]` shows the buffer before the current one.
+    BufferPrevious(usize),
+    /// `:bd [buffer]` closes a buffer, the current one if none is given, refusing to discard
+    /// unsaved changes unless forced with `:bd!`.
+    BufferDelete {
+        buffer: Option<BufferId>,
+        force: bool,
+    },
+    /// `:sp [++enc=encoding] [file]` splits the window in two, opening the file in the new
+    /// window above if one is given. `:vs` splits it into windows side by side.
+    Split {
+        file: Option<String>,
+        vertical: bool,
+        encoding: Option<&'static Encoding>,
+    },
+    /// `:clo` closes the window, unless it's the last one.
+    Close,
+    /// `:on` closes every window but the current one.
+    Only,
+    /// `:res [N|+N|-N]` sets the height of the window, or makes it as tall as it can be if no
+    /// height is given. `:vertical res` sets its width.
+    Resize { vertical: bool, size: Length },
+    /// `:tabnew [++enc=encoding] [file]` opens a tab page after the current one, with the file
+    /// in it or else a new buffer.
+    TabNew {
+        file: Option<String>,
+        encoding: Option<&'static Encoding>,
+    },
+    /// `:tabc` closes the tab page, unless it's the last one.
+    TabClose,
+    /// `:tabo` closes every tab page but the current one.
+    TabOnly,
+    /// `:tabn [N]` goes to the next tab page, or to tab page N.
+    TabNext(Option<usize>),
+    /// `:tabp [count]` goes to the tab page before the current one, or `count` before it.
+    TabPrevious(usize),
+}
+
+/// The size given to `:resize`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Length {
+    To(usize),
+    /// `+N` or `-N` changes the size by N.
+    By(isize),
+    /// As big as there is room for.
+    Max,
+}
+
+/// A buffer given to `:b` or `:bd`, by its number or by part of the name of its file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BufferId {
+    Number(usize),
+    Name(String),
+}
+
+/// A line given to an ex command, such as `.+1`, which the editor works out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    pub base: Base,
+    /// Lines added or taken away with `+` and `-`.
+    pub offset: isize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Base {
+    /// A line number, counting from 1.
+    Number(usize),
+    /// `.`, the cursor's line.
+    Current,
+    /// `$`, the last line.
+    Last,
+    /// `'<` or `'>`, the first or last line of the last visual selection.
+    Mark(char),
+}
+
+impl Address {
+    const fn new(base: Base) -> Self {
+        Self { base, offset: 0 }
+    }
+}
+
+impl LineRange {
+    /// `.`, the cursor's line.
+    const fn current() -> Self {
+        Self {
+            start: Address::new(Base::Current),
+            end: Address::new(Base::Current),
+        }
+    }
+
+    /// `%`, every line.
+    const fn all() -> Self {
+        Self {
+            start: Address::new(Base::Number(1)),
+            end: Address::new(Base::Last),
+        }
+    }
+}
+
+/// The lines an ex command acts on, such as `%` or `10,20`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: Address,
+    pub end: Address,
+}
+
+/// The flags after `:s`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Flags {
+    /// `g` replaces every match on a line rather than only the first.
+    pub global: bool,
+    /// `i` ignores case.
+    pub ignore_case: bool,
+    /// `c` asks before each replacement.
+    pub confirm: bool,
+}
+
+/// Parses the text typed after `:` into a command, or returns the message to show if it isn't
+/// one.
+pub fn parse(input: &str) -> Result<Command, String> {
+    // Trailing spaces are kept for `:normal`
+    let input = input.trim_start();
+    let (range, rest) = line_range(input)?;
+    let rest = rest.trim_start();
+
+    if let Some(range) = &range {
+        if rest.trim_end().is_empty() {
+            return Ok(Command::Goto(range.end.clone()));
+        }
+    }
+
+    if let Some(arguments) = rest
+        .strip_prefix("substitute")
+        .or_else(|| rest.strip_prefix('s'))
+        .filter(|arguments| arguments.starts_with(is_delimiter))
+    {
+        return substitute(range.unwrap_or_else(LineRange::current), arguments);
+    }
+
+    if let Some((arguments, invert)) = global(rest) {
+        return global_command(range.unwrap_or_else(LineRange::all), arguments, invert);
+    }
+
+    let (name, argument) = rest
+        .split_once(char::is_whitespace)
+        .map_or((rest.trim_end(), None), |(name, argument)| {
+            (name, Some(argument))
+        });
+
+    match name {
+        "d" | "delete" if argument.is_none_or(|argument| argument.trim().is_empty()) => {
+            return Ok(Command::Delete(range.unwrap_or_else(LineRange::current)));
+        }
+        "norm" | "normal" if range.is_none() => {
+            return match argument.map(str::trim_start) {
+                Some(keys) if !keys.is_empty() => Ok(Command::Normal(keys.to_string())),
+                _ => Err(String::from("Argument required")),
+            };
+        }
+        _ => (),
+    }
+
+    if range.is_some() {
+        return Err(String::from("No range allowed"));
+    }
+
+    let argument = argument
+        .map(str::trim)
+        .filter(|argument| !argument.is_empty())
+        .map(str::to_string);
+    let (name, force) = name
+        .strip_suffix('!')
+        .map_or((name, false), |name| (name, true));
+
+    // The number can come straight after the name, as in `:b2`
+    if let Some(number) = name
+        .strip_prefix("buffer")
+        .or_else(|| name.strip_prefix('b'))
+        .and_then(|number| number.parse().ok())
+    {
+        return Ok(Command::Buffer(BufferId::Number(number)));
+    }
+
+    match name {
+        "w" | "write" => Ok(Command::Write(argument)),
+        "q" | "quit" => Ok(Command::Quit { force }),
+        "wq" => Ok(Command::WriteQuit),
+        "x" | "xit" | "exit" => Ok(Command::Exit),
+        "e" | "edit" => {
+            let (encoding, file) = edit_argument(argument.as_deref())?;
+            Ok(Command::Edit {
+                file,
+                force,
+                encoding,
+            })
+        }
+        "sav" | "saveas" => argument
+            .map(Command::SaveAs)
+            .ok_or_else(|| String::from("Argument required")),
+        "ea" | "earlier" => Ok(Command::Earlier(step(argument.as_deref())?)),
+        "lat" | "later" => Ok(Command::Later(step(argument.as_deref())?)),
+        "undol" | "undolist" => Ok(Command::UndoList),
+        "ls" | "buffers" | "files" => Ok(Command::Buffers),
+        "b" | "buffer" => argument
+            .as_deref()
+            .map(buffer_id)
+            .map(Command::Buffer)
+            .ok_or_else(|| String::from("Argument required")),
+        "bn" | "bnext" => Ok(Command::BufferNext(count(argument.as_deref())?)),
+        "bp" | "bprevious" | "bN" | "bNext" => {
+            Ok(Command::BufferPrevious(count(argument.as_deref())?))
+        }
+        "bd" | "bdelete" => Ok(Command::BufferDelete {
+            buffer: argument.as_deref().map(buffer_id),
+            force,
+        }),
+        "sp" | "split" | "vs" | "vsplit" => {
+            let (encoding, file) = edit_argument(argument.as_deref())?;
+            Ok(Command::Split {
+                file,
+                vertical: name.starts_with('v'),
+                encoding,
+            })
+        }
+        "clo" | "close" => Ok(Command::Close),
+        "on" | "only" => Ok(Command::Only),
+        "res" | "resize" => Ok(Command::Resize {
+            vertical: false,
+            size: length(argument.as_deref())?,
+        }),
+        // `:vertical` makes the command after it split or resize side by side
+        "vert" | "vertical" => {
+            let command = argument.ok_or_else(|| String::from("Argument required"))?;
+
+            match parse(&command)? {
+                Command::Split { file, encoding, .. } => Ok(Command::Split {
+                    file,
+                    vertical: true,
+                    encoding,
+                }),
+                Command::Resize { size, .. } => Ok(Command::Resize {
+                    vertical: true,
+                    size,
+                }),
+                command => Ok(command),
+            }
+        }
+        "tabnew" | "tabe" | "tabedit" => {
+            let (encoding, file) = edit_argument(argument.as_deref())?;
+            Ok(Command::TabNew { file, encoding })
+        }
+        "tabc" | "tabclose" => Ok(Command::TabClose),
+        "tabo" | "tabonly" => Ok(Command::TabOnly),
+        "tabn" | "tabnext" => Ok(Command::TabNext(
+            argument.as_deref().map(|n| count(Some(n))).transpose()?,
+        )),
+        "tabp" | "tabprevious" | "tabN" | "tabNext" => {
+            Ok(Command::TabPrevious(count(argument.as_deref())?))
+        }
+        "se" | "set" => argument
+            .map(Command::Set)
+            .ok_or_else(|| String::from("Argument required")),
+        _ => Err(format!("Not an editor command: {}", input.trim_end())),
+    }
+}
+
+/// Splits a `++enc=encoding` or `++encoding=encoding` off the start of the argument to `:e`,
+/// leaving the file.
+fn edit_argument(
+    argument: Option<&str>,
+) -> Result<(Option<&'static Encoding>, Option<String>), String> {
+    let Some(argument) = argument else {
+        return Ok((None, None));
+    };
+    let Some(option) = argument.strip_prefix("++") else {
+        return Ok((None, Some(argument.to_string())));
+    };
+
+    let (option, file) = option.split_once(' ').unwrap_or((option, ""));
+    let name = option
+        .strip_prefix("enc=")
+        .or_else(|| option.strip_prefix("encoding="))
+        .ok_or_else(|| format!("Invalid argument: ++{option}"))?;
+    let encoding = encoding::find(name).ok_or_else(|| format!("Unknown encoding: {name}"))?;
+    let file = Some(file.trim_start()).filter(|file| !file.is_empty());
+
+    Ok((Some(encoding), file.map(str::to_string)))
+}
+
+/// Splits a range such as `%` or `.,$` off the start of a command.
+fn line_range(input: &str) -> Result<(Option<LineRange>, &str), String> {
+    if let Some(rest) = input.strip_prefix('%') {
+        return Ok((Some(LineRange::all()), rest));
+    }
+
+    let Some((start, rest)) = address(input)? else {
+        return Ok((None, input));
+    };
+
+    match rest.strip_prefix(',') {
+        Some(rest) => {
+            let (end, rest) = address(rest)?.ok_or_else(|| String::from("Invalid range"))?;
+            Ok((Some(LineRange { start, end }), rest))
+        }
+        None => Ok((
+            Some(LineRange {
+                start: start.clone(),
+                end: start,
+            }),
+            rest,
+        )),
+    }
+}
+
+/// Splits an address such as `.+1` off the start of a command, if there is one.
+fn address(input: &str) -> Result<Option<(Address, &str)>, String> {
+    let (base, mut rest) = if let Some(rest) = input.strip_prefix('.') {
+        (Base::Current, rest)
+    } else if let Some(rest) = input.strip_prefix('$') {
+        (Base::Last, rest)
+    } else if let Some(rest) = input.strip_prefix('\'') {
+        match rest.chars().next() {
+            Some(mark @ ('<' | '>')) => (Base::Mark(mark), &rest[1..]),
+            _ => return Err(String::from("Invalid range")),
+        }
+    } else if let Some((line, rest)) = number(input) {
+        (Base::Number(line), rest)
+    } else if input.starts_with(['+', '-']) {
+        // An offset on its own is from the cursor's line
+        (Base::Current, input)
+    } else {
+        return Ok(None);
+    };
+
+    let mut offset = 0;
+
+    while let Some(sign) = rest.chars().next().filter(|c| matches!(c, '+' | '-')) {
+        let (lines, after) = number(&rest[1..]).unwrap_or((1, &rest[1..]));
+        let lines = isize::try_from(lines).map_err(|_| String::from("Invalid range"))?;
+
+        offset += if sign == '+' { lines } else { -lines };
+        rest = after;
+    }
+
+    Ok(Some((Address { base, offset }, rest)))
+}
+
+/// Splits a number off the start of some text, if it starts with one.
+fn number(input: &str) -> Option<(usize, &str)> {
+    let end = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+
+    Some((input[..end].parse().ok()?, &input[end..]))
+}
+
+/// Parses the number of buffers `:bn` and `:bp` go through, or tab pages for `:tabn` and
+/// `:tabp`, which is one if none is given.
+fn count(argument: Option<&str>) -> Result<usize, String> {
+    argument.map_or(Ok(1), |argument| {
+        argument
+            .parse()
+            .ok()
+            .filter(|count| *count > 0)
+            .ok_or_else(|| format!("Invalid argument: {argument}"))
+    })
+}
+
+/// Parses the size given to `:resize`, which is as big as there is room for if none is given.
+fn length(argument: Option<&str>) -> Result<Length, String> {
+    let Some(argument) = argument else {
+        return Ok(Length::Max);
+    };
+    let invalid = || format!("Invalid argument: {argument}");
+
+    if let Some(n) = argument.strip_prefix('+') {
+        n.parse().map(Length::By).map_err(|_| invalid())
+    } else if let Some(n) = argument.strip_prefix('-') {
+        n.parse()
+            .map(|n: isize| Length::By(-n))
+            .map_err(|_| invalid())
+    } else {
+        argument.parse().map(Length::To).map_err(|_| invalid())
+    }
+}
+
+/// Parses the buffer given to `:b` or `:bd`, which is a number if it is all digits.
+fn buffer_id(argument: &str) -> BufferId {
+    argument
+        .parse()
+        .map_or_else(|_| BufferId::Name(argument.to_string()), BufferId::Number)
+}
+
+/// Parses the argument of `:earlier` or `:later`, such as `5`, `10s`, `2m` or `1f`.
+fn step(argument: Option<&str>) -> Result<Step, String> {
+    let Some(argument) = argument else {
+        return Ok(Step::Changes(1));
+    };
+    let invalid = || format!("Invalid argument: {argument}");
+    let (n, unit) = number(argument).ok_or_else(invalid)?;
+
+    match unit {
+        "" => Ok(Step::Changes(n)),
+        "s" | "m" | "h" | "d" => {
+            let seconds = match unit {
+                "s" => 1,
+                "m" => 60,
+                "h" => 60 * 60,
+                _ => 24 * 60 * 60,
+            };
+            (n as u64)
+                .checked_mul(seconds)
+                .map(Step::Seconds)
+                .ok_or_else(invalid)
+        }
+        "f" => Ok(Step::Saves(n)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Whether a character can separate the parts of `:s`, as the `/`s do in `:s/a/b/`.
+fn is_delimiter(c: char) -> bool {
+    !c.is_alphanumeric() && !c.is_whitespace() && !matches!(c, '\\' | '"' | '|')
+}
+
+/// Parses the `/pattern/replacement/flags` after `:s`.
+fn substitute(range: LineRange, arguments: &str) -> Result<Command, String> {
+    let mut chars = arguments.chars();
+    let delimiter = chars
+        .next()
+        .ok_or_else(|| String::from("Argument required"))?;
+    let mut parts = vec![String::new()];
+
+    while let Some(c) = chars.next() {
+        match c {
+            // An escaped delimiter is part of the text, but other escapes are kept for the
+            // pattern or replacement to interpret
+            '\\' => match chars.next() {
+                Some(c) if c == delimiter => parts.last_mut().unwrap().push(c),
+                Some(c) => {
+                    let part = parts.last_mut().unwrap();
+                    part.push('\\');
+                    part.push(c);
+                }
+                None => parts.last_mut().unwrap().push('\\'),
+            },
+            c if c == delimiter && parts.len() < 3 => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+
+    let mut parts = parts.into_iter();
+    let pattern = parts.next().unwrap_or_default();
+    let replacement = parts.next().unwrap_or_default();
+    let mut flags = Flags::default();
+
+    for flag in parts.next().unwrap_or_default().trim().chars() {
+        match flag {
+            'g' => flags.global = true,
+            'i' => flags.ignore_case = true,
+            'c' => flags.confirm = true,
+            _ => return Err(format!("Trailing characters: {flag}")),
+        }
+    }
+
+    Ok(Command::Substitute {
+        range,
+        pattern,
+        replacement,
+        flags,
+    })
+}
+
+/// Splits `:g`, `:g!` or `:v` off the start of a command, returning what follows it and
+/// whether it is for lines that don't match.
+fn global(input: &str) -> Option<(&str, bool)> {
+    let (rest, invert) = if let Some(rest) = input
+        .strip_prefix("global")
+        .or_else(|| input.strip_prefix('g'))
+    {
+        rest.strip_prefix('!')
+            .map_or((rest, false), |rest| (rest, true))
+    } else {
+        let rest = input
+            .strip_prefix("vglobal")
+            .or_else(|| input.strip_prefix('v'))?;
+        (rest, true)
+    };
+
+    rest.starts_with(is_delimiter).then_some((rest, invert))
+}
+
+/// Parses the `/pattern/command` after `:g` or `:v`.
+fn global_command(range: LineRange, arguments: &str, invert: bool) -> Result<Command, String> {
+    let mut chars = arguments.chars();
+    let delimiter = chars
+        .next()
+        .ok_or_else(|| String::from("Argument required"))?;
+    let mut pattern = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c) if c == delimiter => pattern.push(c),
+                Some(c) => {
+                    pattern.push('\\');
+                    pattern.push(c);
+                }
+                None => pattern.push('\\'),
+            },
+            c if c == delimiter => break,
+            c => pattern.push(c),
+        }
+    }
+
+    if chars.as_str().trim().is_empty() {
+        return Err(String::from("Argument required"));
+    }
+
+    let command = match parse(chars.as_str())? {
+        Command::Global { .. } => return Err(String::from("Cannot do :global recursively")),
+        // There is no one to answer between lines
+        Command::Substitute { flags, .. } if flags.confirm => {
+            return Err(String::from("Cannot confirm :s in :global"));
+        }
+        command => command,
+    };
+
+    Ok(Command::Global {
+        range,
+        pattern,
+        invert,
+        command: Box::new(command),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_commands() {
+        assert_eq!(parse("w"), Ok(Command::Write(None)));
+        assert_eq!(
+            parse("write out.txt "),
+            Ok(Command::Write(Some(String::from("out.txt"))))
+        );
+        assert_eq!(parse("q"), Ok(Command::Quit { force: false }));
+        assert_eq!(parse("q!"), Ok(Command::Quit { force: true }));
+        assert_eq!(parse("wq"), Ok(Command::WriteQuit));
+        assert_eq!(parse("x"), Ok(Command::Exit));
+        assert_eq!(
+            parse("saveas copy.txt"),
+            Ok(Command::SaveAs(String::from("copy.txt")))
+        );
+        assert_eq!(parse("sav"), Err(String::from("Argument required")));
+        assert_eq!(
+            parse("42"),
+            Ok(Command::Goto(Address::new(Base::Number(42))))
+        );
+        assert_eq!(
+            parse("frobnicate now"),
+            Err(String::from("Not an editor command: frobnicate now"))
+        );
+    }
+
+    #[test]
+    fn edit() {
+        assert_eq!(
+            parse("e other.txt"),
+            Ok(Command::Edit {
+                file: Some(String::from("other.txt")),
+                force: false,
+                encoding: None,
+            })
+        );
+        assert_eq!(
+            parse("e!"),
+            Ok(Command::Edit {
+                file: None,
+                force: true,
+                encoding: None,
+            })
+        );
+    }
+
+    fn range(start: Address, end: Address) -> LineRange {
+        LineRange { start, end }
+    }
+
+    fn substitution(input: &str) -> (LineRange, String, String, Flags) {
+        match parse(input) {
+            Ok(Command::Substitute {
+                range,
+                pattern,
+                replacement,
+                flags,
+            }) => (range, pattern, replacement, flags),
+            other => panic!("{input} parsed as {other:?}"),
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        let number = |line| Address::new(Base::Number(line));
+
+        assert_eq!(substitution("%s/a/b/").0, LineRange::all());
+        assert_eq!(substitution("s/a/b/").0, LineRange::current());
+        assert_eq!(substitution("10,20s/a/b/").0, range(number(10), number(20)));
+        assert_eq!(
+            substitution(".,$s/a/b/").0,
+            range(Address::new(Base::Current), Address::new(Base::Last))
+        );
+        assert_eq!(
+            substitution("'<,'>s/a/b/").0,
+            range(Address::new(Base::Mark('<')), Address::new(Base::Mark('>')))
+        );
+        assert_eq!(substitution("5s/a/b/").0, range(number(5), number(5)));
+    }
+
+    #[test]
+    fn offsets() {
+        let current = |offset| Address {
+            base: Base::Current,
+            offset,
+        };
+
+        assert_eq!(parse(".+1"), Ok(Command::Goto(current(1))));
+        assert_eq!(parse("-2"), Ok(Command::Goto(current(-2))));
+        assert_eq!(parse("+"), Ok(Command::Goto(current(1))));
+        assert_eq!(
+            parse("$-3+1"),
+            Ok(Command::Goto(Address {
+                base: Base::Last,
+                offset: -2,
+            }))
+        );
+        assert_eq!(
+            parse("42"),
+            Ok(Command::Goto(Address::new(Base::Number(42))))
+        );
+        // A range goes to its last line
+        assert_eq!(
+            parse("3,7"),
+            Ok(Command::Goto(Address::new(Base::Number(7))))
+        );
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        assert_eq!(parse("1,s/a/b/"), Err(String::from("Invalid range")));
+        assert_eq!(parse("'as/a/b/"), Err(String::from("Invalid range")));
+        assert_eq!(parse("%w"), Err(String::from("No range allowed")));
+    }
+
+    #[test]
+    fn substitute_flags() {
+        assert_eq!(substitution("s/a/b/").3, Flags::default());
+        assert_eq!(
+            substitution("s/a/b/gic").3,
+            Flags {
+                global: true,
+                ignore_case: true,
+                confirm: true,
+            }
+        );
+        assert_eq!(
+            parse("s/a/b/gx"),
+            Err(String::from("Trailing characters: x"))
+        );
+        assert_eq!(parse("s"), Err(String::from("Not an editor command: s")));
+    }
+
+    #[test]
+    fn substitute_parts() {
+        let (_, pattern, replacement, flags) = substitution("substitute/a\\/b/c\\1\\/d/g");
+        assert_eq!(pattern, "a/b");
+        assert_eq!(replacement, "c\\1/d");
+        assert!(flags.global);
+
+        // Any punctuation can be the delimiter, and the last one is optional
+        let (_, pattern, replacement, _) = substitution("s#/usr#/opt");
+        assert_eq!(pattern, "/usr");
+        assert_eq!(replacement, "/opt");
+
+        let (_, pattern, replacement, _) = substitution("s/x");
+        assert_eq!(pattern, "x");
+        assert_eq!(replacement, "");
+    }
+
+    #[test]
+    fn global() {
+        assert_eq!(
+            parse("g/TODO/d"),
+            Ok(Command::Global {
+                range: LineRange::all(),
+                pattern: String::from("TODO"),
+                invert: false,
+                command: Box::new(Command::Delete(LineRange::current())),
+            })
+        );
+        assert!(matches!(
+            parse("v/x/s/a/b/"),
+            Ok(Command::Global { invert: true, .. })
+        ));
+        assert!(matches!(
+            parse("g!/x/normal A;"),
+            Ok(Command::Global { invert: true, command, .. })
+                if *command == Command::Normal(String::from("A;"))
+        ));
+        assert_eq!(
+            parse("g/a/g/b/d"),
+            Err(String::from("Cannot do :global recursively"))
+        );
+        assert_eq!(
+            parse("g/a/s/b/c/c"),
+            Err(String::from("Cannot confirm :s in :global"))
+        );
+        assert_eq!(parse("g/a/"), Err(String::from("Argument required")));
+    }
+
+    #[test]
+    fn undo_steps() {
+        assert_eq!(parse("earlier"), Ok(Command::Earlier(Step::Changes(1))));
+        assert_eq!(parse("ea 5"), Ok(Command::Earlier(Step::Changes(5))));
+        assert_eq!(
+            parse("earlier 2m"),
+            Ok(Command::Earlier(Step::Seconds(120)))
+        );
+        assert_eq!(parse("later 1h"), Ok(Command::Later(Step::Seconds(3600))));
+        assert_eq!(parse("later 3f"), Ok(Command::Later(Step::Saves(3))));
+        assert_eq!(parse("later 3x"), Err(String::from("Invalid argument: 3x")));
+        assert_eq!(
+            parse("earlier 999999999999999d"),
+            Err(String::from("Invalid argument: 999999999999999d"))
+        );
+        assert_eq!(parse("undol"), Ok(Command::UndoList));
+    }
+
+    #[test]
+    fn encodings() {
+        assert_eq!(
+            parse("e ++enc=latin1 notes.txt"),
+            Ok(Command::Edit {
+                file: Some(String::from("notes.txt")),
+                force: false,
+                encoding: Some(encoding_rs::WINDOWS_1252),
+            })
+        );
+        assert_eq!(
+            parse("e! ++encoding=utf-16le"),
+            Ok(Command::Edit {
+                file: None,
+                force: true,
+                encoding: Some(encoding_rs::UTF_16LE),
+            })
+        );
+        assert_eq!(
+            parse("e ++enc=nope"),
+            Err(String::from("Unknown encoding: nope"))
+        );
+        assert_eq!(
+            parse("e ++bin file"),
+            Err(String::from("Invalid argument: ++bin"))
+        );
+    }
+
+    #[test]
+    fn buffers() {
+        assert_eq!(parse("ls"), Ok(Command::Buffers));
+        assert_eq!(parse("b2"), Ok(Command::Buffer(BufferId::Number(2))));
+        assert_eq!(parse("b 2"), Ok(Command::Buffer(BufferId::Number(2))));
+        assert_eq!(
+            parse("b main"),
+            Ok(Command::Buffer(BufferId::Name(String::from("main"))))
+        );
+        assert_eq!(parse("bn"), Ok(Command::BufferNext(1)));
+        assert_eq!(parse("bp 3"), Ok(Command::BufferPrevious(3)));
+        assert_eq!(parse("bn 0"), Err(String::from("Invalid argument: 0")));
+        assert_eq!(
+            parse("bd! 3"),
+            Ok(Command::BufferDelete {
+                buffer: Some(BufferId::Number(3)),
+                force: true,
+            })
+        );
+    }
+
+    #[test]
+    fn windows() {
+        assert_eq!(
+            parse("vs other.txt"),
+            Ok(Command::Split {
+                file: Some(String::from("other.txt")),
+                vertical: true,
+                encoding: None,
+            })
+        );
+        assert_eq!(
+            parse("vertical split"),
+            Ok(Command::Split {
+                file: None,
+                vertical: true,
+                encoding: None,
+            })
+        );
+        assert_eq!(
+            parse("res 10"),
+            Ok(Command::Resize {
+                vertical: false,
+                size: Length::To(10),
+            })
+        );
+        assert_eq!(
+            parse("vert res -5"),
+            Ok(Command::Resize {
+                vertical: true,
+                size: Length::By(-5),
+            })
+        );
+        assert_eq!(
+            parse("res"),
+            Ok(Command::Resize {
+                vertical: false,
+                size: Length::Max,
+            })
+        );
+        assert_eq!(parse("clo"), Ok(Command::Close));
+        assert_eq!(parse("only"), Ok(Command::Only));
+    }
+
+    #[test]
+    fn tab_pages() {
+        assert_eq!(
+            parse("tabnew"),
+            Ok(Command::TabNew {
+                file: None,
+                encoding: None,
+            })
+        );
+        assert_eq!(parse("tabn"), Ok(Command::TabNext(None)));
+        assert_eq!(parse("tabn 2"), Ok(Command::TabNext(Some(2))));
+        assert_eq!(parse("tabp 2"), Ok(Command::TabPrevious(2)));
+        assert_eq!(parse("tabc"), Ok(Command::TabClose));
+        assert_eq!(parse("tabo"), Ok(Command::TabOnly));
+    }
+}
@@ -1,46 +1,171 @@
-use crate::buffer::Backspace;
+use crate::buffer::{Backspace, Buffer, FileFormat, Location, Range, Step};
+use crate::buffer_list::BufferList;
+use crate::clipboard;
+use crate::command::{self, Address, Base, BufferId, Command, Length, LineRange};
+use crate::encoding;
+use crate::grammar::{self, Action, Operation, Parse, Target};
+use crate::motion::{class, Class, Find, Lines, Motion};
+use crate::operator::Operator;
+use crate::register::{self, Kind, Register, Registers};
+use crate::search::{Search, SearchHistory};
+use crate::selection::{Selection, Shape};
+use crate::substitute::{self, Substitution};
+use crate::swap;
+use crate::tab_page::{TabPage, TabPages};
+use crate::text_object::TextObject;
 use crate::view::Size;
-use crate::view::View;
+use crate::view::{View, Wrap, STATUS_LINE};
+use crate::window::{Window, Windows};
 use crossterm::event::Event::Key;
 use crossterm::event::KeyCode::Char;
-use crossterm::event::{read, KeyModifiers};
+use crossterm::event::{poll, read, KeyEvent, KeyModifiers};
 use crossterm::event::{Event, KeyCode};
+use encoding_rs::Encoding;
+use std::time::{Duration, Instant};
 
 use crate::terminal::{
     change_cursor_style, clear_screen, execute, hide_cursor, initialise, move_cursor_to,
-    show_cursor, terminate, Position,
+    show_cursor, terminate,
 };
 
+/// How long typing has to stop for before the swap file is written.
+const SWAP_IDLE: Duration = Duration::from_secs(4);
+/// How often the swap file is written while typing doesn't stop.
+const SWAP_INTERVAL: Duration = Duration::from_secs(30);
+
 #[derive(PartialEq, Eq)]
 pub enum Mode {
     Insert,
     Normal,
-    // Command,
+    Command,
+    Visual(Shape),
+    /// Typing a pattern after `/`, or after `?` to search backward.
+    Search(bool),
+    /// Asking whether to replace each match of `:s` with the `c` flag.
+    Confirm,
+    /// Asking what to do with a swap file found when opening a file.
+    Recover,
+}
+
+impl Mode {
+    /// The name of the mode, for the status line.
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Insert => "INSERT",
+            Self::Normal => "NORMAL",
+            Self::Command => "COMMAND",
+            Self::Visual(Shape::Characters) => "VISUAL",
+            Self::Visual(Shape::Lines) => "VISUAL LINE",
+            Self::Visual(Shape::Block) => "VISUAL BLOCK",
+            Self::Search(_) => "SEARCH",
+            Self::Confirm => "CONFIRM",
+            Self::Recover => "RECOVER",
+        }
+    }
 }
 
 pub struct Editor {
     pub view: View,
     pub cursor: Cursor,
+    /// The buffers open besides the one being shown.
+    buffers: BufferList,
+    /// The windows besides the one the cursor is in, and how the screen is split between them.
+    windows: Windows,
+    /// The size of the terminal, which the windows are fitted to.
+    screen: Size,
+    /// The tab pages, the current one of which has `windows`.
+    tabs: TabPages,
+    /// Whether Ctrl-W was typed, so the next key is a window command.
+    window_command: bool,
+    /// The format of the status line under each window, which `:set statusline` changes.
+    status_line: String,
     pub mode: Mode,
     pub quit: bool,
+    /// The keys of a normal mode command typed so far, such as the `3d` of `3dw`.
+    keys: String,
+    /// The text typed after `:` in command mode.
+    command_line: String,
+    /// The last `f`, `t`, `F` or `T` search, for `;` and `,` to repeat.
+    last_find: Option<Find>,
+    /// Where the selection was started in visual mode.
+    anchor: Location,
+    /// A block insert or append in progress, finished when insert mode ends.
+    block_insert: Option<BlockInsert>,
+    registers: Registers,
+    /// The last pattern searched for, for `n` and `N` to repeat.
+    last_search: Option<Search>,
+    search_history: SearchHistory,
+    /// Where the cursor was before typing a search, to go back to if it's cancelled.
+    search_origin: Location,
+    /// The first and last lines of the last visual selection, for `'<` and `'>`.
+    last_selection: Option<(usize, usize)>,
+    /// A `:s` waiting for each replacement to be confirmed.
+    substitution: Option<Substitution>,
+    /// The replacement of the last `:s`, for `~` in the next one.
+    last_replacement: String,
+    /// The differences between the file and a swap file found for it, while they are shown.
+    swap_diff: Option<View>,
+    /// When the swap file was last brought up to date.
+    swap_written: Instant,
 }
 
 impl Editor {
-    pub fn new(file: &Option<String>) -> Self {
-        Self {
-            view: View::new(file),
-            cursor: Cursor::new(Position::new(0, 0)),
-            mode: Mode::Normal,
-            quit: false,
+    /// Opens each file in a buffer of its own, showing the first.
+    pub fn new(files: &[String], wrap: Wrap) -> Result<Self, std::io::Error> {
+        let view = View::new(&files.first().cloned(), None, wrap)?;
+        let mut buffers = BufferList::new();
+
+        for file in files.iter().skip(1) {
+            if buffers.find_file(file, &view.file()).is_none() {
+                buffers.add(View::new(&Some(file.clone()), None, wrap)?);
+            }
         }
+
+        Ok(Self {
+            mode: if view.swap.is_some() {
+                Mode::Recover
+            } else {
+                Mode::Normal
+            },
+            view,
+            cursor: Cursor::new(Location::new(0, 0)),
+            buffers,
+            windows: Windows::new(),
+            screen: Size::default(),
+            tabs: TabPages::new(),
+            window_command: false,
+            status_line: String::from(STATUS_LINE),
+            quit: false,
+            keys: String::new(),
+            command_line: String::new(),
+            last_find: None,
+            anchor: Location::new(0, 0),
+            block_insert: None,
+            registers: Registers::new(clipboard::detect()),
+            last_search: None,
+            search_history: SearchHistory::default(),
+            search_origin: Location::new(0, 0),
+            last_selection: None,
+            substitution: None,
+            last_replacement: String::new(),
+            swap_diff: None,
+            swap_written: Instant::now(),
+        })
     }
 
     pub fn run(&mut self) -> Result<(), std::io::Error> {
         // Allow reading of bytes directly from stdin without pressing enter
         initialise()?;
 
+        if self.mode != Mode::Recover {
+            self.report_open()?;
+        }
         self.repl()?;
 
+        self.view.buffer_mut().remove_swap();
+        for hidden in self.buffers.hidden_mut() {
+            hidden.view.buffer_mut().remove_swap();
+        }
         terminate()?;
         Ok(())
     }
@@ -54,8 +179,19 @@ impl Editor {
                 break;
             }
 
-            let event = read()?;
-            self.evaluate_event(&event)?;
+            // Bring the swap file up to date whenever typing stops, and every so often
+            // when it doesn't
+            let idle = !poll(SWAP_IDLE)?;
+
+            if idle || self.swap_written.elapsed() >= SWAP_INTERVAL {
+                self.view.buffer_mut().update_swap();
+                self.swap_written = Instant::now();
+            }
+
+            if !idle {
+                let event = read()?;
+                self.evaluate_event(&event)?;
+            }
         }
 
         Ok(())
@@ -63,58 +199,85 @@ impl Editor {
 
     fn evaluate_event(&mut self, event: &Event) -> Result<(), std::io::Error> {
         match event {
+            Key(key) if self.mode == Mode::Command => self.command_key(key.code)?,
+            Key(key) if matches!(self.mode, Mode::Search(_)) => self.search_key(key.code)?,
+            Key(key) if self.mode == Mode::Confirm => self.confirm_key(key.code)?,
+            Key(key) if self.mode == Mode::Recover => self.recover_key(key.code),
+            Key(key) if self.window_command => self.window_key(key.code)?,
+            Key(KeyEvent {
+                code: Char(c),
+                modifiers,
+                ..
+            }) if matches!(self.mode, Mode::Normal | Mode::Visual(_))
+                && !modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                self.normal_key(*c)?;
+            }
             Key(key) => match key.code {
                 Char('q') if key.modifiers == KeyModifiers::CONTROL => {
                     self.quit = true;
                 }
                 Char('s') if key.modifiers == KeyModifiers::CONTROL => {
-                    self.view.save()?;
-                }
-                Char('h') if self.mode != Mode::Insert => self.left(),
-                Char('j') if self.mode != Mode::Insert => self.down(),
-                Char('k') if self.mode != Mode::Insert => self.up(),
-                Char('u') if self.mode != Mode::Insert => {
-                    if let Some(pos) = self.view.undo() {
-                        self.cursor.position = pos;
-                        self.correct_cursor();
+                    if let Err(err) = self.view.save() {
+                        self.view.draw_bottom_message(err)?;
                     }
                 }
-                Char('U') if self.mode != Mode::Insert => {
-                    if let Some(pos) = self.view.redo() {
-                        self.cursor.position = pos;
-                        self.correct_cursor();
-                    }
+                Char('e') if key.modifiers == KeyModifiers::CONTROL => self.scroll(1),
+                Char('y') if key.modifiers == KeyModifiers::CONTROL => self.scroll(-1),
+                Char('d') if key.modifiers == KeyModifiers::CONTROL => {
+                    self.scroll_with_cursor(self.half_page());
                 }
-                Char('l') if self.mode != Mode::Insert => self.right(),
-                Char('i') if self.mode == Mode::Normal => self.mode(Mode::Insert),
-                Char('a') if self.mode == Mode::Normal => {
-                    self.mode(Mode::Insert);
-                    self.right();
+                Char('u') if key.modifiers == KeyModifiers::CONTROL => {
+                    self.scroll_with_cursor(-self.half_page());
                 }
-                Char('I') if self.mode == Mode::Normal => {
-                    self.cursor.position.x = 0;
-                    self.mode(Mode::Insert);
+                Char('f') if key.modifiers == KeyModifiers::CONTROL => {
+                    self.scroll(self.full_page());
                 }
-                Char('A') if self.mode == Mode::Normal => {
-                    self.cursor.position.x = self.current_line_len();
-                    self.mode(Mode::Insert);
+                Char('b') if key.modifiers == KeyModifiers::CONTROL => {
+                    self.scroll(-self.full_page());
                 }
-                Char('o') if self.mode == Mode::Normal => {
-                    self.mode(Mode::Insert);
-                    self.down();
-                    self.view.new_line(&self.cursor.position);
-                    self.correct_cursor();
+                // Ctrl-^ goes back to the buffer shown before, or to the buffer numbered by a count
+                Char('6' | '^')
+                    if key.modifiers.contains(KeyModifiers::CONTROL)
+                        && self.mode == Mode::Normal =>
+                {
+                    let number = self.keys.parse().ok().or(self.buffers.alternate);
+                    self.keys.clear();
+
+                    match number {
+                        Some(number) => self.show_buffer(number)?,
+                        None => self.view.draw_bottom_message("No alternate file")?,
+                    }
+                }
+                Char('w')
+                    if key.modifiers == KeyModifiers::CONTROL && self.mode == Mode::Normal =>
+                {
+                    self.window_command = true;
+                }
+                Char('v')
+                    if key.modifiers == KeyModifiers::CONTROL
+                        && matches!(self.mode, Mode::Normal | Mode::Visual(_)) =>
+                {
+                    self.keys.clear();
+                    self.visual(Shape::Block);
                 }
                 KeyCode::Esc => {
+                    self.keys.clear();
+
+                    if let Some(block) = self.block_insert.take() {
+                        self.finish_block_insert(&block);
+                    }
                     self.mode(Mode::Normal);
                     self.view.update_history(self.cursor.position.clone());
                 }
                 KeyCode::Enter if self.mode == Mode::Insert => {
+                    self.registers.inserted.push('\n');
                     self.view.enter(&self.cursor.position);
                     self.down();
                     self.cursor.position.x = 0;
                 }
                 KeyCode::Backspace if self.mode == Mode::Insert => {
+                    self.registers.inserted.pop();
                     if let Backspace::WrapLines(pos) = self.view.backspace(&self.cursor.position) {
                         self.cursor.position = pos;
                     } else {
@@ -122,37 +285,1615 @@ impl Editor {
                     }
                 }
                 KeyCode::Tab => {
+                    self.registers.inserted.push_str("    ");
+
                     for _ in 0..4 {
-                        self.view.write(&self.cursor.position, ' ');
-                        self.cursor.position.right();
+                        self.cursor.position = self.view.write(&self.cursor.position, ' ');
                     }
                 }
                 Char(c) if self.mode == Mode::Insert => {
-                    self.view.write(&self.cursor.position, c);
-                    self.cursor.position.right();
+                    self.registers.inserted.push(c);
+                    self.cursor.position = self.view.write(&self.cursor.position, c);
                     self.view.draw_bottom_message("")?;
                 }
                 _ => (),
             },
-            Event::Resize(x, y) => self.view.resize(Size {
-                width: *x,
-                height: *y,
+            Event::Resize(x, y) => {
+                self.screen = Size {
+                    width: *x,
+                    height: *y,
+                };
+            }
+            _ => (),
+        }
+
+        // Every change made in normal mode is undone on its own, not only those ended by Esc
+        if self.mode == Mode::Normal {
+            self.view.update_history(self.cursor.position.clone());
+        }
+
+        self.view.follow(&self.cursor.position);
+        move_cursor_to(&self.view.screen_position(&self.cursor.position))?;
+        Ok(())
+    }
+
+    /// Runs the window command typed after Ctrl-W, with the count typed before it if any.
+    fn window_key(&mut self, code: KeyCode) -> Result<(), std::io::Error> {
+        self.window_command = false;
+        let count: Option<usize> = self.keys.parse().ok();
+        self.keys.clear();
+
+        let Char(c) = code else {
+            return Ok(());
+        };
+        let by = |sign: isize| Length::By(sign * count.unwrap_or(1) as isize);
+
+        match c {
+            'h' | 'j' | 'k' | 'l' => {
+                let from = self.view.screen_position(&self.cursor.position);
+
+                if let Some(id) = self.windows.neighbour(c, &from, count.unwrap_or(1)) {
+                    self.activate(id);
+                }
+            }
+            'w' | 'W' => self.activate(self.windows.cycle(count, c == 'w')),
+            's' | 'S' | 'v' => {
+                self.split(c == 'v')?;
+            }
+            'c' => self.close_window()?,
+            'q' => self.try_quit(false)?,
+            'o' => self.windows.only(),
+            '=' => self.windows.equalize(),
+            '+' => self.resize_window(false, by(1)),
+            '-' => self.resize_window(false, by(-1)),
+            '>' => self.resize_window(true, by(1)),
+            '<' => self.resize_window(true, by(-1)),
+            '_' => self.resize_window(false, count.map_or(Length::Max, Length::To)),
+            '|' => self.resize_window(true, count.map_or(Length::Max, Length::To)),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn command_key(&mut self, code: KeyCode) -> Result<(), std::io::Error> {
+        match code {
+            KeyCode::Esc => self.mode(Mode::Normal),
+            KeyCode::Backspace if self.command_line.pop().is_none() => self.mode(Mode::Normal),
+            KeyCode::Enter => {
+                self.mode(Mode::Normal);
+                let input = std::mem::take(&mut self.command_line);
+
+                if !input.trim().is_empty() {
+                    self.registers.command.clone_from(&input);
+                }
+
+                match command::parse(&input) {
+                    Ok(command) => {
+                        if let Err(err) = self.execute(command) {
+                            self.view.draw_bottom_message(err)?;
+                        }
+                    }
+                    Err(message) => self.view.draw_bottom_message(message)?,
+                }
+            }
+            Char(c) => self.command_line.push(c),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn search_key(&mut self, code: KeyCode) -> Result<(), std::io::Error> {
+        let forward = self.mode == Mode::Search(true);
+
+        match code {
+            KeyCode::Esc => self.cancel_search(),
+            KeyCode::Backspace if self.command_line.pop().is_none() => self.cancel_search(),
+            KeyCode::Enter => {
+                self.cancel_search();
+                let input = std::mem::take(&mut self.command_line);
+
+                // An empty pattern searches for the last one again
+                let pattern = if input.is_empty() {
+                    match &self.last_search {
+                        Some(search) => search.pattern.clone(),
+                        None => return self.view.draw_bottom_message("No previous search pattern"),
+                    }
+                } else {
+                    input
+                };
+
+                self.search_history.add(&pattern);
+
+                match Search::new(pattern, forward) {
+                    Ok(search) => self.last_search = Some(search),
+                    Err(message) => return self.view.draw_bottom_message(message),
+                }
+                return self.operate(Operation {
+                    count: None,
+                    register: None,
+                    operator: None,
+                    target: Target::Motion(Motion::SearchNext),
+                });
+            }
+            KeyCode::Up => {
+                if let Some(pattern) = self.search_history.previous() {
+                    self.command_line = pattern.to_string();
+                }
+            }
+            KeyCode::Down => self.command_line = self.search_history.next().to_string(),
+            Char(c) => self.command_line.push(c),
+            _ => (),
+        }
+
+        // Show where the pattern typed so far first matches
+        if matches!(self.mode, Mode::Search(_)) {
+            self.cursor.position = Search::new(self.command_line.clone(), forward)
+                .ok()
+                .and_then(|search| search.next(&self.view.buffer(), &self.search_origin, 1))
+                .map_or_else(|| self.search_origin.clone(), |(pos, _)| pos);
+        }
+        Ok(())
+    }
+
+    /// Handles the answer to what to do with a swap file found when opening a file.
+    fn recover_key(&mut self, code: KeyCode) {
+        let Some(swap) = self.view.swap.take() else {
+            self.mode(Mode::Normal);
+            return;
+        };
+        let running = swap.running.is_some();
+
+        match code {
+            Char('r') => {
+                self.view.buffer_mut().recover(&swap.text);
+
+                // The swap file can be taken over unless its editor is still running
+                if !running {
+                    self.view.buffer_mut().claim_swap();
+                }
+            }
+            Char('d') if !running => {
+                std::fs::remove_file(&swap.path).ok();
+                self.view.buffer_mut().claim_swap();
+            }
+            Char('s') => {
+                if self.swap_diff.take().is_none() {
+                    let file = self.view.buffer().text().to_string();
+                    let mut lines = swap::diff(&file, &swap.text);
+
+                    if lines.is_empty() {
+                        lines.push(String::from("No differences"));
+                    }
+                    self.swap_diff = Some(View::with_buffer(
+                        Buffer::new(&lines, String::new()),
+                        self.view.wrap,
+                    ));
+                }
+                self.view.swap = Some(swap);
+                return;
+            }
+            // Leave the swap file alone, without keeping one for this editor
+            Char('e') => (),
+            Char('q') => self.quit = true,
+            _ => {
+                self.view.swap = Some(swap);
+                return;
+            }
+        }
+
+        self.swap_diff = None;
+        self.mode(Mode::Normal);
+    }
+
+    fn cancel_search(&mut self) {
+        self.cursor.position = self.search_origin.clone();
+        self.mode(Mode::Normal);
+    }
+
+    fn execute(&mut self, command: Command) -> Result<(), std::io::Error> {
+        match command {
+            Command::Write(None) => self.view.save()?,
+            Command::Write(Some(file)) => self.view.write_to(&file)?,
+            Command::Quit { force } => self.try_quit(force)?,
+            Command::WriteQuit => {
+                self.view.save()?;
+                self.try_quit(false)?;
+            }
+            Command::Exit => {
+                if self.view.is_modified() {
+                    self.view.save()?;
+                }
+                self.try_quit(false)?;
+            }
+            Command::Edit {
+                file,
+                force,
+                encoding,
+            } => {
+                // Another file is opened in a buffer of its own, or shown if it's open already
+                let other = file.filter(|file| *file != self.view.file());
+
+                if let Some(file) = other {
+                    let number = match self.buffers.find_file(&file, &self.view.file()) {
+                        Some(number) => number,
+                        None => self
+                            .buffers
+                            .add(View::new(&Some(file), encoding, self.view.wrap)?),
+                    };
+                    self.show_buffer(number)?;
+                } else if force || !self.view.is_modified() {
+                    let file = self.view.file();
+                    self.view.buffer_mut().remove_swap();
+
+                    match View::new(&Some(file), encoding, self.view.wrap) {
+                        Ok(view) => self.view.reload(view),
+                        Err(err) => {
+                            self.view.buffer_mut().claim_swap();
+                            return Err(err);
+                        }
+                    }
+                    self.cursor = Cursor::new(Location::new(0, 0));
+
+                    if self.view.swap.is_some() {
+                        self.mode(Mode::Recover);
+                    } else {
+                        self.report_open()?;
+                    }
+                } else {
+                    self.view
+                        .draw_bottom_message("No write since last change (add ! to override)")?;
+                }
+            }
+            Command::SaveAs(file) => self.view.save_as(file)?,
+            Command::Goto(address) => match self.line_of(&address) {
+                Ok(line) => {
+                    self.cursor.position = Location::new(0, line);
+                    self.cursor.update(0);
+                }
+                Err(message) => self.view.draw_bottom_message(message)?,
+            },
+            Command::Substitute {
+                range,
+                pattern,
+                replacement,
+                flags,
+            } => match self.substitution(&range, pattern, &replacement, flags) {
+                Ok(mut substitution) if flags.confirm => {
+                    let found = substitution.next_match(&self.view.buffer());
+
+                    match found {
+                        Some(pos) => {
+                            self.cursor.position = pos;
+                            self.substitution = Some(substitution);
+                            self.mode(Mode::Confirm);
+                        }
+                        None => self.finish_substitution(&substitution)?,
+                    }
+                }
+                Ok(mut substitution) => {
+                    while substitution.next_match(&self.view.buffer()).is_some() {
+                        substitution.replace(&mut self.view.buffer_mut());
+                    }
+                    self.finish_substitution(&substitution)?;
+                }
+                Err(message) => self.view.draw_bottom_message(message)?,
+            },
+            Command::Delete(range) => match self.lines(&range) {
+                Ok((first, last)) => self.delete_lines(first, last)?,
+                Err(message) => self.view.draw_bottom_message(message)?,
+            },
+            Command::Normal(keys) => self.normal(&keys)?,
+            Command::Global {
+                range,
+                pattern,
+                invert,
+                command,
+            } => match self.mark_global(&range, pattern, invert) {
+                Ok(()) => self.global(&command)?,
+                Err(message) => self.view.draw_bottom_message(message)?,
+            },
+            Command::Earlier(step) => self.time_travel(step, false),
+            Command::Later(step) => self.time_travel(step, true),
+            Command::UndoList => {
+                let list = self.view.buffer().history.list();
+                self.view.draw_bottom_message(list)?;
+            }
+            Command::Buffers => {
+                let list = self.buffers.list(&self.view, &self.cursor);
+                self.view.draw_bottom_message(list)?;
+            }
+            Command::Buffer(buffer) => match self.buffer_number(&buffer) {
+                Ok(number) => self.show_buffer(number)?,
+                Err(message) => self.view.draw_bottom_message(message)?,
+            },
+            Command::BufferNext(count) => self.show_buffer(self.buffers.cycle(count, true))?,
+            Command::BufferPrevious(count) => self.show_buffer(self.buffers.cycle(count, false))?,
+            Command::BufferDelete { buffer, force } => {
+                let number = match buffer.map(|buffer| self.buffer_number(&buffer)) {
+                    Some(Ok(number)) => number,
+                    Some(Err(message)) => return self.view.draw_bottom_message(message),
+                    None => self.buffers.current,
+                };
+                self.delete_buffer(number, force)?;
+            }
+            Command::Split {
+                file,
+                vertical,
+                encoding,
+            } => {
+                // The file may be the one already shown, which isn't read again
+                if self.split(vertical)?
+                    && file.as_ref().is_some_and(|file| *file != self.view.file())
+                {
+                    self.execute(Command::Edit {
+                        file,
+                        force: false,
+                        encoding,
+                    })?;
+                }
+            }
+            Command::Close => self.close_window()?,
+            Command::Only => self.windows.only(),
+            Command::Resize { vertical, size } => self.resize_window(vertical, size),
+            Command::TabNew { file, encoding } => {
+                let new = match file {
+                    Some(_) => None,
+                    None => Some(self.buffers.add(View::new(&None, None, self.view.wrap)?)),
+                };
+                self.new_tab();
+
+                if let Some(number) = new {
+                    self.show_buffer(number)?;
+                } else if file.as_ref().is_some_and(|file| *file != self.view.file()) {
+                    self.execute(Command::Edit {
+                        file,
+                        force: false,
+                        encoding,
+                    })?;
+                }
+            }
+            Command::TabClose => self.close_tab()?,
+            Command::TabOnly => self.tabs.only(),
+            Command::TabNext(Some(n)) => self.show_tab(n - 1),
+            Command::TabNext(None) => self.cycle_tabs(1, true),
+            Command::TabPrevious(count) => self.cycle_tabs(count, false),
+            Command::Set(option) => match option.as_str() {
+                "wrap" => self.view.wrap = Wrap::Soft,
+                "nowrap" => self.view.wrap = Wrap::Scroll,
+                "backup" => self.view.buffer_mut().backup = true,
+                "nobackup" => self.view.buffer_mut().backup = false,
+                "readonly" | "ro" => self.view.buffer_mut().readonly = true,
+                "noreadonly" | "noro" => self.view.buffer_mut().readonly = false,
+                "fileformat" | "ff" => {
+                    let format = self.view.buffer().format.name();
+                    self.view
+                        .draw_bottom_message(format!("fileformat={format}"))?;
+                }
+                "fileformat=unix" | "ff=unix" => self.set_format(FileFormat::Unix),
+                "fileformat=dos" | "ff=dos" => self.set_format(FileFormat::Dos),
+                "fixendofline" | "fixeol" => self.view.buffer_mut().fix_end_of_line = true,
+                "nofixendofline" | "nofixeol" => self.view.buffer_mut().fix_end_of_line = false,
+                "fileencoding" | "fenc" => {
+                    let name = encoding::name(self.view.buffer().encoding);
+                    self.view
+                        .draw_bottom_message(format!("fileencoding={name}"))?;
+                }
+                "statusline" | "stl" => {
+                    let format = self.status_line.clone();
+                    self.view
+                        .draw_bottom_message(format!("statusline={format}"))?;
+                }
+                _ => match option.split_once('=') {
+                    Some(("fileencoding" | "fenc", name)) => match encoding::find(name) {
+                        Some(encoding) => self.set_encoding(encoding),
+                        None => self
+                            .view
+                            .draw_bottom_message(format!("Unknown encoding: {name}"))?,
+                    },
+                    Some(("statusline" | "stl", format)) => self.status_line = format.to_string(),
+                    _ => self
+                        .view
+                        .draw_bottom_message(format!("Unknown option: {option}"))?,
+                },
+            },
+        }
+        Ok(())
+    }
+
+    /// Works out which line an address refers to.
+    fn line_of(&self, address: &Address) -> Result<usize, String> {
+        let line = match address.base {
+            Base::Number(line) => line.saturating_sub(1),
+            Base::Current => self.cursor.position.y,
+            Base::Last => self.view.len() - 1,
+            Base::Mark(mark) => {
+                let (first, last) = self
+                    .last_selection
+                    .ok_or_else(|| String::from("Mark not set"))?;
+
+                if mark == '<' {
+                    first
+                } else {
+                    last
+                }
+            }
+        };
+
+        Ok(line
+            .saturating_add_signed(address.offset)
+            .min(self.view.len() - 1))
+    }
+
+    /// Sets up `:s` on a range of lines, or returns the message to show if the pattern or
+    /// range isn't valid.
+    fn substitution(
+        &mut self,
+        range: &LineRange,
+        pattern: String,
+        replacement: &str,
+        flags: command::Flags,
+    ) -> Result<Substitution, String> {
+        let (first, last) = self.lines(range)?;
+        let pattern = self.pattern_or_last(pattern)?;
+        let search = Search::new(
+            if flags.ignore_case {
+                format!("\\c{pattern}")
+            } else {
+                pattern.clone()
+            },
+            true,
+        )?;
+
+        self.search_history.add(&pattern);
+        self.last_search = Some(Search::new(pattern, true)?);
+        self.last_replacement = substitute::with_previous(replacement, &self.last_replacement);
+
+        Ok(Substitution::new(
+            search,
+            &self.last_replacement,
+            flags.global,
+            first,
+            last,
+        ))
+    }
+
+    /// Returns the first and last lines of a range, in order.
+    fn lines(&self, range: &LineRange) -> Result<(usize, usize), String> {
+        let (start, end) = (self.line_of(&range.start)?, self.line_of(&range.end)?);
+        Ok((start.min(end), start.max(end)))
+    }
+
+    /// Returns a pattern for an ex command, or the last one searched for if it is empty.
+    fn pattern_or_last(&self, pattern: String) -> Result<String, String> {
+        if !pattern.is_empty() {
+            return Ok(pattern);
+        }
+
+        self.last_search
+            .as_ref()
+            .map(|search| search.pattern.clone())
+            .ok_or_else(|| String::from("No previous search pattern"))
+    }
+
+    /// Marks the lines in a range that match a pattern for `:g`, or that don't for `:v`.
+    fn mark_global(
+        &mut self,
+        range: &LineRange,
+        pattern: String,
+        invert: bool,
+    ) -> Result<(), String> {
+        let (first, last) = self.lines(range)?;
+        let pattern = self.pattern_or_last(pattern)?;
+        let search = Search::new(pattern.clone(), true)?;
+
+        let lines: Vec<usize> = (first..=last)
+            .filter(|y| search.matches(&self.view.buffer(), *y).is_empty() == invert)
+            .collect();
+
+        self.search_history.add(&pattern);
+        self.last_search = Some(search);
+
+        if lines.is_empty() {
+            return Err(if invert {
+                format!("Pattern found in every line: {pattern}")
+            } else {
+                format!("Pattern not found: {pattern}")
+            });
+        }
+        self.view.buffer_mut().mark_lines(lines);
+        Ok(())
+    }
+
+    /// Runs a command on each marked line for `:g`. Lines are marked up front so that
+    /// commands which add or delete lines don't upset which ones are visited, and all the
+    /// changes are undone together.
+    fn global(&mut self, command: &Command) -> Result<(), std::io::Error> {
+        let checkpoint = self.view.buffer().history.checkpoint();
+        let mut result = Ok(());
+
+        loop {
+            let Some(line) = self.view.buffer_mut().next_mark() else {
+                break;
+            };
+            self.cursor.position = Location::new(0, line);
+            self.cursor.update(0);
+            result = self.execute(command.clone());
+
+            if result.is_err() || self.quit {
+                break;
+            }
+        }
+
+        self.view.buffer_mut().mark_lines(Vec::new());
+        self.view.buffer_mut().history.merge_since(checkpoint);
+        result
+    }
+
+    /// Deletes whole lines for `:d`, saving them to the registers as `dd` does.
+    fn delete_lines(&mut self, first: usize, last: usize) -> Result<(), std::io::Error> {
+        let range = Range::new(Location::new(0, first), Location::new(0, last), true);
+        let register = Register::new(self.view.buffer().slice(&range), Kind::Lines);
+
+        self.save(Operator::Delete, None, register)?;
+        self.cursor.position =
+            Operator::Delete.apply(&mut self.view.buffer_mut(), &range, &self.cursor.position);
+        self.cursor
+            .update(self.view.display_column(&self.cursor.position));
+        self.view.update_history(self.cursor.position.clone());
+        Ok(())
+    }
+
+    /// Runs keys as if they were typed in normal mode, for `:normal`. A command left
+    /// unfinished is cancelled, and insert mode is left, as if Esc was typed after them.
+    fn normal(&mut self, keys: &str) -> Result<(), std::io::Error> {
+        self.keys.clear();
+
+        for c in keys.chars() {
+            self.evaluate_event(&Key(KeyEvent::new(Char(c), KeyModifiers::NONE)))?;
+
+            if self.quit {
+                return Ok(());
+            }
+        }
+
+        if self.mode != Mode::Normal || !self.keys.is_empty() {
+            self.evaluate_event(&Key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE)))?;
+        }
+        Ok(())
+    }
+
+    fn confirm_key(&mut self, code: KeyCode) -> Result<(), std::io::Error> {
+        let Some(mut substitution) = self.substitution.take() else {
+            self.mode(Mode::Normal);
+            return Ok(());
+        };
+
+        let more = match code {
+            Char('y') => {
+                substitution.replace(&mut self.view.buffer_mut());
+                true
+            }
+            Char('n') => {
+                substitution.skip();
+                true
+            }
+            // Replace this match and every one after it
+            Char('a') => {
+                substitution.replace(&mut self.view.buffer_mut());
+
+                while substitution.next_match(&self.view.buffer()).is_some() {
+                    substitution.replace(&mut self.view.buffer_mut());
+                }
+                false
+            }
+            // Replace this match and stop
+            Char('l') => {
+                substitution.replace(&mut self.view.buffer_mut());
+                false
+            }
+            Char('q') | KeyCode::Esc => false,
+            _ => {
+                self.substitution = Some(substitution);
+                return Ok(());
+            }
+        };
+
+        if more {
+            let found = substitution.next_match(&self.view.buffer());
+
+            if let Some(pos) = found {
+                self.cursor.position = pos;
+                self.substitution = Some(substitution);
+                return Ok(());
+            }
+        }
+
+        self.mode(Mode::Normal);
+        self.finish_substitution(&substitution)
+    }
+
+    /// Moves to the last line changed by `:s`, making its replacements one undo step, and
+    /// says how many it made.
+    fn finish_substitution(&mut self, substitution: &Substitution) -> Result<(), std::io::Error> {
+        let Some(line) = substitution.last_line else {
+            return self.view.draw_bottom_message(format!(
+                "Pattern not found: {}",
+                substitution.search.pattern
+            ));
+        };
+
+        self.cursor.position = Location::new(self.view.buffer().indentation(line), line);
+        self.cursor
+            .update(self.view.display_column(&self.cursor.position));
+        self.view.update_history(self.cursor.position.clone());
+
+        if substitution.replaced > 1 {
+            self.view.draw_bottom_message(format!(
+                "{} substitutions on {} lines",
+                substitution.replaced, substitution.lines
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn normal_key(&mut self, key: char) -> Result<(), std::io::Error> {
+        self.keys.push(key);
+
+        let visual = matches!(self.mode, Mode::Visual(_));
+        let parse = if visual {
+            grammar::parse_visual(&self.keys)
+        } else {
+            grammar::parse(&self.keys)
+        };
+
+        match parse {
+            Parse::Incomplete => (),
+            Parse::Invalid => self.keys.clear(),
+            Parse::Done(action) => {
+                self.keys.clear();
+
+                match action {
+                    Action::Operation(Operation {
+                        count,
+                        operator: None,
+                        target: Target::Object(object),
+                        ..
+                    }) => self.select(object, count),
+                    Action::Operation(operation) => self.operate(operation)?,
+                    Action::Command { keys, .. } if visual => self.visual_command(&keys),
+                    Action::Command {
+                        count,
+                        register,
+                        keys,
+                    } => self.normal_command(&keys, count, register)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn normal_command(
+        &mut self,
+        keys: &str,
+        count: Option<usize>,
+        register: Option<char>,
+    ) -> Result<(), std::io::Error> {
+        match keys {
+            ":" => {
+                self.command_line.clear();
+                self.mode(Mode::Command);
+            }
+            "u" => {
+                if let Some(pos) = self.view.undo() {
+                    self.restore_cursor(pos);
+                }
+            }
+            "U" => {
+                if let Some(pos) = self.view.redo() {
+                    self.restore_cursor(pos);
+                }
+            }
+            // A count goes to that tab page, where `gT` goes back that many
+            "gt" => match count {
+                Some(n) => self.show_tab(n - 1),
+                None => self.cycle_tabs(1, true),
+            },
+            "gT" => self.cycle_tabs(count.unwrap_or(1), false),
+            // Go back or forward through every change made, across branches of the undo tree
+            "g-" | "g+" => self.time_travel(Step::Changes(count.unwrap_or(1)), keys == "g+"),
+            "/" | "?" => {
+                self.command_line.clear();
+                self.search_origin = self.cursor.position.clone();
+                self.search_history.reset();
+                self.mode(Mode::Search(keys == "/"));
+            }
+            "v" => self.visual(Shape::Characters),
+            "V" => self.visual(Shape::Lines),
+            "p" | "P" => self.put(register, count.unwrap_or(1), keys == "p")?,
+            "i" => self.mode(Mode::Insert),
+            "a" => {
+                self.mode(Mode::Insert);
+                self.right();
+            }
+            "I" => {
+                self.cursor.position.x = 0;
+                self.mode(Mode::Insert);
+            }
+            "A" => {
+                self.cursor.position.x = self.current_line_len();
+                self.mode(Mode::Insert);
+            }
+            "o" => {
+                self.mode(Mode::Insert);
+                self.view
+                    .new_line(&Location::new(0, self.cursor.position.y + 1));
+                self.down();
+                self.correct_cursor();
+            }
+            // Scroll the cursor's line to the middle, top or bottom of the screen
+            "zz" => self
+                .view
+                .scroll_to(self.cursor.position.y, self.view.height() / 2),
+            "zt" => self.view.scroll_to(self.cursor.position.y, 0),
+            "zb" => self
+                .view
+                .scroll_to(self.cursor.position.y, self.view.height() - 1),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Changes how lines end when the file is saved, which counts as a change to it.
+    fn set_format(&mut self, format: FileFormat) {
+        self.view.buffer_mut().format = format;
+    }
+
+    /// Changes the encoding the file is saved in, which counts as a change to it.
+    fn set_encoding(&mut self, encoding: &'static Encoding) {
+        self.view.buffer_mut().encoding = encoding;
+    }
+
+    /// Says how the file just opened was read, if there is anything to note about it, such as
+    /// it being new or binary.
+    fn report_open(&mut self) -> Result<(), std::io::Error> {
+        if !self.view.notes.is_empty() {
+            let message = format!("\"{}\" {}", self.view.file(), self.view.notes.join(" "));
+            self.view.notes.clear();
+            self.view.draw_bottom_message(message)?;
+        }
+        Ok(())
+    }
+
+    /// Closes the window if there are others, or else the tab page if there are others, or
+    /// else quits, unless a buffer has unsaved changes and quitting isn't forced. A buffer
+    /// stays open when its window is closed.
+    fn try_quit(&mut self, force: bool) -> Result<(), std::io::Error> {
+        if self.windows.count() > 1 {
+            self.close_window()?;
+        } else if self.tabs.count() > 1 {
+            self.close_tab()?;
+        } else if force {
+            self.quit = true;
+        } else if self.view.is_modified() {
+            self.view
+                .draw_bottom_message("No write since last change (add ! to override)")?;
+        } else if let Some(hidden) = self.buffers.modified() {
+            let message = format!(
+                "No write since last change for buffer {} (add ! to override)",
+                hidden.number
+            );
+            self.view.draw_bottom_message(message)?;
+        } else {
+            self.quit = true;
+        }
+        Ok(())
+    }
+
+    /// Works out the number of the buffer given to `:b` or `:bd`.
+    fn buffer_number(&self, buffer: &BufferId) -> Result<usize, String> {
+        match buffer {
+            BufferId::Number(number) if self.buffers.contains(*number) => Ok(*number),
+            BufferId::Number(number) => Err(format!("Buffer {number} does not exist")),
+            BufferId::Name(name) => self.buffers.find(name, &self.view.file()),
+        }
+    }
+
+    /// Shows an open buffer in place of the current one, which stays open with its cursor
+    /// where it was.
+    fn show_buffer(&mut self, number: usize) -> Result<(), std::io::Error> {
+        if number == self.buffers.current {
+            return Ok(());
+        }
+
+        let wrap = self.view.wrap;
+        self.view.buffer_mut().update_swap();
+
+        if !self
+            .buffers
+            .switch(number, &mut self.view, &mut self.cursor)
+        {
+            return self
+                .view
+                .draw_bottom_message(format!("Buffer {number} does not exist"));
+        }
+        self.view.wrap = wrap;
+        self.cursor.clamp(&self.view);
+
+        if self.view.swap.is_some() {
+            self.mode(Mode::Recover);
+            Ok(())
+        } else if !self.view.notes.is_empty() {
+            self.report_open()
+        } else {
+            let message = format!(
+                "\"{}\" line {} of {}",
+                self.view.file(),
+                self.cursor.position.y + 1,
+                self.view.buffer().len()
+            );
+            self.view.draw_bottom_message(message)
+        }
+    }
+
+    /// Closes a buffer. If it's the one shown, the buffer shown before it takes its place, or
+    /// else the next one, or else a new empty buffer.
+    fn delete_buffer(&mut self, number: usize, force: bool) -> Result<(), std::io::Error> {
+        let modified = if number == self.buffers.current {
+            self.view.is_modified()
+        } else {
+            self.buffers
+                .get(number)
+                .is_some_and(|hidden| hidden.view.is_modified())
+        };
+
+        if modified && !force {
+            return self.view.draw_bottom_message(format!(
+                "No write since last change for buffer {number} (add ! to override)"
+            ));
+        }
+
+        // Windows showing the buffer are closed with it, unless it's in the last one
+        self.tabs.close_showing(number);
+        for id in self.windows.showing(number) {
+            self.windows.close(id);
+        }
+        if number == self.buffers.current {
+            if self.windows.count() > 1 {
+                self.close_window()?;
+            } else if self.tabs.count() > 1 {
+                self.close_tab()?;
+            }
+        }
+
+        if number == self.buffers.current {
+            if self.buffers.is_empty() {
+                self.view.buffer_mut().remove_swap();
+                self.view = View::new(&None, None, self.view.wrap)?;
+                self.cursor = Cursor::new(Location::new(0, 0));
+                self.buffers.renumber_current();
+                return Ok(());
+            }
+
+            let next = self
+                .buffers
+                .alternate
+                .unwrap_or_else(|| self.buffers.cycle(1, true));
+            self.show_buffer(next)?;
+        }
+
+        if let Some(mut view) = self.buffers.remove(number) {
+            view.buffer_mut().remove_swap();
+        }
+        Ok(())
+    }
+
+    /// Moves the cursor to another window. The buffer it was in stays open with its cursor
+    /// where it was, in case it's shown in another window later.
+    fn activate(&mut self, id: usize) {
+        if let Some(window) = self.windows.take(id) {
+            let left = self.enter(window);
+            self.windows.put(self.windows.active, left);
+            self.windows.active = id;
+        }
+    }
+
+    /// Puts what another window shows in the window the cursor is in, making its buffer
+    /// current, and returns what it showed before.
+    fn enter(&mut self, mut window: Window) -> Window {
+        window.cursor.clamp(&window.view);
+        self.view.buffer_mut().update_swap();
+
+        let view = std::mem::replace(&mut self.view, window.view);
+        let cursor = std::mem::replace(&mut self.cursor, window.cursor);
+        let buffer = self.buffers.current;
+
+        self.buffers
+            .make_current(window.buffer, view.split(), cursor.clone());
+        Window {
+            buffer,
+            view,
+            cursor,
+        }
+    }
+
+    /// Splits the window in two, moving the cursor to the new window above it, or to its left
+    /// if vertical, which shows the same buffer. Returns false if there isn't room.
+    fn split(&mut self, vertical: bool) -> Result<bool, std::io::Error> {
+        let window = Window {
+            buffer: self.buffers.current,
+            view: self.view.split(),
+            cursor: self.cursor.clone(),
+        };
+
+        match self.windows.split(vertical, window) {
+            Some(id) => {
+                self.activate(id);
+                Ok(true)
+            }
+            None => {
+                self.view.draw_bottom_message("Not enough room")?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Closes the window, moving the cursor to the window that gets its space.
+    fn close_window(&mut self) -> Result<(), std::io::Error> {
+        match self.windows.close_active() {
+            Some(window) => {
+                self.enter(window);
+                Ok(())
+            }
+            None => self.view.draw_bottom_message("Cannot close last window"),
+        }
+    }
+
+    /// Opens a tab page after the current one, with a window showing the current buffer.
+    fn new_tab(&mut self) {
+        let window = Window {
+            buffer: self.buffers.current,
+            view: self.view.split(),
+            cursor: self.cursor.clone(),
+        };
+        let left = self.enter(window);
+
+        self.tabs.open(TabPage {
+            windows: std::mem::replace(&mut self.windows, Windows::new()),
+            window: left,
+        });
+    }
+
+    /// Moves the cursor to the tab page at an index, counting from zero.
+    fn show_tab(&mut self, index: usize) {
+        if let Some(page) = self.tabs.take(index) {
+            let left = self.enter(page.window);
+            let windows = std::mem::replace(&mut self.windows, page.windows);
+
+            self.tabs.leave(
+                TabPage {
+                    windows,
+                    window: left,
+                },
+                index,
+            );
+        }
+    }
+
+    /// Moves the cursor `count` tab pages after the current one, or before it, wrapping around
+    /// the ends.
+    fn cycle_tabs(&mut self, count: usize, forward: bool) {
+        let len = self.tabs.count();
+        let step = count % len;
+
+        self.show_tab(if forward {
+            (self.tabs.current + step) % len
+        } else {
+            (self.tabs.current + len - step) % len
+        });
+    }
+
+    /// Closes the tab page, moving the cursor to the one after it, or before it if it was the
+    /// last. Its buffers stay open.
+    fn close_tab(&mut self) -> Result<(), std::io::Error> {
+        match self.tabs.close() {
+            Some(page) => {
+                self.enter(page.window);
+                self.windows = page.windows;
+                Ok(())
+            }
+            None => self.view.draw_bottom_message("Cannot close last tab page"),
+        }
+    }
+
+    /// Sets the height of the window, or its width if vertical, not counting its status line.
+    fn resize_window(&mut self, vertical: bool, size: Length) {
+        // The window's size includes its status line, but not the line beside it
+        let status = u16::from(!vertical);
+        let current = self.windows.size(vertical);
+        let size = match size {
+            Length::To(size) => size.try_into().unwrap_or(u16::MAX).saturating_add(status),
+            Length::By(change) => current.saturating_add_signed(change.try_into().unwrap_or(0)),
+            Length::Max => u16::MAX,
+        };
+
+        self.windows.resize(vertical, size);
+    }
+
+    fn time_travel(&mut self, step: Step, forward: bool) {
+        if let Some(pos) = self.view.time_travel(step, forward) {
+            self.restore_cursor(pos);
+        }
+    }
+
+    /// Moves the cursor to where a change undone or redone was made, as it was recorded.
+    fn restore_cursor(&mut self, pos: Location) {
+        self.cursor.position = pos;
+        self.cursor.clamp(&self.view);
+        self.cursor
+            .update(self.view.display_column(&self.cursor.position));
+    }
+
+    /// Puts the contents of a register `count` times after or before the cursor.
+    fn put(&mut self, name: Option<char>, count: usize, after: bool) -> Result<(), std::io::Error> {
+        let file = self.view.file().to_string();
+
+        match self.registers.get(name, &file) {
+            Ok(Some(register)) => {
+                self.cursor.position = register.put(
+                    &mut self.view.buffer_mut(),
+                    &self.cursor.position,
+                    after,
+                    count,
+                );
+                self.cursor
+                    .update(self.view.display_column(&self.cursor.position));
+                self.view.update_history(self.cursor.position.clone());
+            }
+            Ok(None) => self
+                .view
+                .draw_bottom_message(format!("Nothing in register {}", name.unwrap_or('"')))?,
+            Err(err) => self
+                .view
+                .draw_bottom_message(format!("Clipboard error: {err}"))?,
+        }
+        Ok(())
+    }
+
+    /// Saves the text an operator is about to act on to a register, if it is one that does.
+    fn save(
+        &mut self,
+        operator: Operator,
+        name: Option<char>,
+        register: Register,
+    ) -> Result<(), std::io::Error> {
+        let saved = if operator == Operator::Yank {
+            self.registers.yank(name, register)
+        } else {
+            self.registers.delete(name, register)
+        };
+
+        match saved {
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidInput => {
+                self.view.draw_bottom_message(err.to_string())?;
+            }
+            Err(err) => self
+                .view
+                .draw_bottom_message(format!("Clipboard error: {err}"))?,
+            Ok(()) => (),
+        }
+        Ok(())
+    }
+
+    fn visual_command(&mut self, keys: &str) {
+        match keys {
+            "v" => self.visual(Shape::Characters),
+            "V" => self.visual(Shape::Lines),
+            // Start a command on the selected lines
+            ":" => {
+                self.mode(Mode::Normal);
+                self.command_line = String::from("'<,'>");
+                self.mode(Mode::Command);
+            }
+            // Move the cursor to the other end of the selection
+            "o" => {
+                std::mem::swap(&mut self.anchor, &mut self.cursor.position);
+                self.cursor
+                    .update(self.view.display_column(&self.cursor.position));
+            }
+            "I" | "A" if self.mode == Mode::Visual(Shape::Block) => {
+                if let Some(selection) = self.selection() {
+                    let (first, last) = selection.lines();
+                    let (left, right) = selection.columns(&self.view.buffer());
+
+                    if keys == "I" {
+                        self.start_block_insert(first, last, left, false);
+                    } else {
+                        self.start_block_insert(first, last, right, true);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Starts selecting in the given shape, switches to it from another visual mode, or goes
+    /// back to normal mode if already in it.
+    fn visual(&mut self, shape: Shape) {
+        match self.mode {
+            Mode::Visual(current) if current == shape => self.mode(Mode::Normal),
+            Mode::Visual(_) => self.mode(Mode::Visual(shape)),
+            _ => {
+                self.anchor = self.cursor.position.clone();
+                self.mode(Mode::Visual(shape));
+            }
+        }
+    }
+
+    /// Returns what is selected, if in visual mode.
+    fn selection(&self) -> Option<Selection> {
+        match self.mode {
+            Mode::Visual(shape) => Some(Selection {
+                anchor: self.anchor.clone(),
+                cursor: self.cursor.position.clone(),
+                shape,
             }),
+            _ => None,
+        }
+    }
+
+    /// Selects a text object in visual mode, switching to visual line mode for objects made
+    /// of whole lines.
+    fn select(&mut self, object: TextObject, count: Option<usize>) {
+        let buffer = self.view.buffer();
+
+        let Some(range) = object.range(&buffer, &self.cursor.position, count.unwrap_or(1)) else {
+            return;
+        };
+
+        self.anchor = range.start.clone();
+        self.cursor.position = if range.linewise {
+            Location::new(0, range.end.y)
+        } else {
+            // The end of a range is exclusive, but the cursor is on the last selected character
+            buffer
+                .location_of(buffer.index_of(&range.end).saturating_sub(1))
+                .max(range.start)
+        };
+        drop(buffer);
+        self.cursor
+            .update(self.view.display_column(&self.cursor.position));
+
+        if range.linewise {
+            self.mode(Mode::Visual(Shape::Lines));
+        }
+    }
+
+    /// Applies an operator to each row of a block selection separately, from the bottom up so
+    /// the rows above are unaffected. Changing a block inserts the same text on every row.
+    fn operate_block(
+        &mut self,
+        operator: Operator,
+        register: Option<char>,
+    ) -> Result<(), std::io::Error> {
+        let Some(selection) = self.selection() else {
+            return Ok(());
+        };
+        let (first, last) = selection.lines();
+        let (left, _) = selection.columns(&self.view.buffer());
+        let rows = selection.rows(&self.view.buffer());
+
+        if operator.is_saved() {
+            let text: Vec<String> = rows
+                .iter()
+                .map(|row| self.view.buffer().slice(row))
+                .collect();
+            self.save(
+                operator,
+                register,
+                Register::new(text.join("\n"), Kind::Block),
+            )?;
+        }
+
+        for row in rows.iter().rev() {
+            operator.apply(&mut self.view.buffer_mut(), row, &self.cursor.position);
+        }
+
+        self.mode(Mode::Normal);
+        self.cursor.position = Location::new(self.view.column_to_grapheme(first, left), first);
+        self.cursor
+            .update(self.view.display_column(&self.cursor.position));
+
+        if operator == Operator::Change {
+            self.start_block_insert(first, last, left, false);
+        } else if operator.is_edit() {
+            self.view.update_history(self.cursor.position.clone());
+        }
+        Ok(())
+    }
+
+    /// Enters insert mode at a display column of the first line of a block, padding the line
+    /// with spaces when appending past its end.
+    fn start_block_insert(&mut self, first: usize, last: usize, column: usize, append: bool) {
+        self.cursor.position = if append {
+            self.view.buffer_mut().pad_to(first, column)
+        } else {
+            Location::new(self.view.column_to_grapheme(first, column), first)
+        };
+        self.block_insert = Some(BlockInsert {
+            start: self.cursor.position.clone(),
+            last,
+            column,
+            append,
+        });
+        self.mode(Mode::Insert);
+    }
+
+    /// Repeats the text typed on the first line of a block insert on the rest of its lines.
+    /// Lines too short to reach the block are skipped when inserting, and padded when
+    /// appending.
+    fn finish_block_insert(&mut self, block: &BlockInsert) {
+        let start = &block.start;
+
+        if self.cursor.position.y != start.y || self.cursor.position.x <= start.x {
+            return;
+        }
+
+        let text = self.view.buffer().graphemes(start.y)[start.x..self.cursor.position.x].concat();
+        let mut buffer = self.view.buffer_mut();
+
+        for y in start.y + 1..=block.last {
+            let width = buffer.display_column(&Location::new(buffer.nth_line_len(y), y));
+
+            let mut pos = if block.append {
+                buffer.pad_to(y, block.column)
+            } else if width > block.column {
+                Location::new(buffer.column_to_grapheme(y, block.column), y)
+            } else {
+                continue;
+            };
+
+            for c in text.chars() {
+                pos = buffer.write(&pos, c);
+            }
+        }
+    }
+
+    /// Runs a motion on its own, or an operator on a motion, text object or lines. Edits made
+    /// by an operator are a single undo step.
+    fn operate(&mut self, mut operation: Operation) -> Result<(), std::io::Error> {
+        match operation.target {
+            Target::Motion(Motion::Find(find)) => self.last_find = Some(find),
+            // `*` and `#` search for the word under the cursor like any other search
+            Target::Motion(Motion::SearchWord(forward)) => {
+                let Some((search, start)) =
+                    Search::word(&self.view.buffer(), &self.cursor.position, forward)
+                else {
+                    return self.view.draw_bottom_message("No string under cursor");
+                };
+
+                // Searching from the start of the word skips over it in either direction
+                self.cursor.position = start;
+                self.search_history.add(&search.pattern);
+                self.last_search = Some(search);
+                operation.target = Target::Motion(Motion::SearchNext);
+            }
             _ => (),
         }
 
-        move_cursor_to(&self.cursor.position)?;
+        let Some(operator) = operation.operator else {
+            if let Target::Motion(motion) = operation.target {
+                let from = self.cursor.position.clone();
+
+                if let Some(pos) = self.motion_target(motion, operation.count) {
+                    self.cursor.position = pos;
+                }
+
+                if matches!(motion, Motion::Up | Motion::Down) {
+                    self.recall_cursor();
+                } else {
+                    self.cursor
+                        .update(self.view.display_column(&self.cursor.position));
+                }
+                self.report_search(motion, &from, operation.count)?;
+            }
+            return Ok(());
+        };
+
+        // Don't act at all rather than lose the text to a register that can't hold it
+        if operator.is_saved() && operation.register.is_some_and(register::is_read_only) {
+            return self
+                .view
+                .draw_bottom_message(String::from("Invalid register name"));
+        }
+
+        let target = match (operator, operation.target) {
+            // `cw` changes up to the end of the word like `ce`, leaving the space after it
+            (Operator::Change, Target::Motion(Motion::WordForward(big)))
+                if class(&Lines::new(&self.view.buffer()), &self.cursor.position)
+                    != Class::Blank =>
+            {
+                let buffer = self.view.buffer();
+                let lines = Lines::new(&buffer);
+                let mut next = self.cursor.position.clone();
+                next.right();
+
+                if class(&lines, &next) == class(&lines, &self.cursor.position) {
+                    Target::Motion(Motion::WordEnd(big))
+                } else {
+                    Target::Motion(Motion::Right)
+                }
+            }
+            (_, Target::Selection)
+                if self.mode == Mode::Visual(Shape::Block) && !operator.is_linewise() =>
+            {
+                return self.operate_block(operator, operation.register);
+            }
+            (_, target) => target,
+        };
+
+        let Some(range) = self.range(target, operation.count) else {
+            return Ok(());
+        };
+
+        if matches!(self.mode, Mode::Visual(_)) {
+            self.mode(Mode::Normal);
+        }
+
+        if operator.is_saved() {
+            let kind = if range.linewise {
+                Kind::Lines
+            } else {
+                Kind::Characters
+            };
+            let text = self.view.buffer().slice(&range);
+            self.save(operator, operation.register, Register::new(text, kind))?;
+        }
+
+        self.cursor.position =
+            operator.apply(&mut self.view.buffer_mut(), &range, &self.cursor.position);
+        self.cursor
+            .update(self.view.display_column(&self.cursor.position));
+
+        if operator == Operator::Change {
+            self.mode(Mode::Insert);
+        } else if operator.is_edit() {
+            self.view.update_history(self.cursor.position.clone());
+        } else if range.linewise {
+            let lines = range.end.y - range.start.y + 1;
+            self.view
+                .draw_bottom_message(format!("{lines} lines yanked"))?;
+        }
         Ok(())
     }
 
-    fn refresh_screen(&self) -> Result<(), std::io::Error> {
+    /// Returns where a motion moves the cursor, or `None` if it fails.
+    fn motion_target(&self, motion: Motion, count: Option<usize>) -> Option<Location> {
+        let pos = &self.cursor.position;
+        let buffer = self.view.buffer();
+        let n = count.unwrap_or(1);
+        let first_non_blank = |y: usize| Location::new(buffer.indentation(y), y);
+
+        match motion {
+            Motion::ScreenUp | Motion::ScreenDown => Some((0..n).fold(pos.clone(), |pos, _| {
+                self.view
+                    .move_screen_row(&pos, motion == Motion::ScreenDown)
+            })),
+            Motion::ScreenTop => Some(first_non_blank(
+                (self.view.offset() + n - 1).min(self.view.bottom()),
+            )),
+            Motion::ScreenMiddle => Some(first_non_blank(
+                (self.view.offset() + self.view.bottom()) / 2,
+            )),
+            Motion::ScreenBottom => Some(first_non_blank(
+                self.view
+                    .bottom()
+                    .saturating_sub(n - 1)
+                    .max(self.view.offset()),
+            )),
+            Motion::RepeatFind | Motion::RepeatFindReversed => match self.resolve(motion)? {
+                Motion::Find(find) => find.apply(&buffer, pos, n, true),
+                _ => None,
+            },
+            Motion::SearchNext | Motion::SearchPrevious => self
+                .search_for(motion)?
+                .next(&buffer, pos, n)
+                .map(|(pos, _)| pos),
+            _ => motion.apply(&buffer, pos, count),
+        }
+    }
+
+    /// Replaces `;` and `,` with the `f`, `t`, `F` or `T` they repeat.
+    fn resolve(&self, motion: Motion) -> Option<Motion> {
+        match motion {
+            Motion::RepeatFind => self.last_find.map(Motion::Find),
+            Motion::RepeatFindReversed => self.last_find.map(|find| Motion::Find(find.reversed())),
+            _ => Some(motion),
+        }
+    }
+
+    /// Returns the search `n` or `N` repeats, in the direction it goes.
+    fn search_for(&self, motion: Motion) -> Option<Search> {
+        match motion {
+            Motion::SearchNext => self.last_search.clone(),
+            Motion::SearchPrevious => self.last_search.as_ref().map(Search::reversed),
+            _ => None,
+        }
+    }
+
+    /// Says when a search from `from` wrapped around the end of the buffer or found nothing.
+    fn report_search(
+        &mut self,
+        motion: Motion,
+        from: &Location,
+        count: Option<usize>,
+    ) -> Result<(), std::io::Error> {
+        if !matches!(motion, Motion::SearchNext | Motion::SearchPrevious) {
+            return Ok(());
+        }
+
+        let Some(search) = self.search_for(motion) else {
+            return self.view.draw_bottom_message("No previous search pattern");
+        };
+
+        let found = search.next(&self.view.buffer(), from, count.unwrap_or(1));
+
+        match found {
+            None => self
+                .view
+                .draw_bottom_message(format!("Pattern not found: {}", search.pattern)),
+            Some((_, true)) if search.forward => self
+                .view
+                .draw_bottom_message("search hit BOTTOM, continuing at TOP"),
+            Some((_, true)) => self
+                .view
+                .draw_bottom_message("search hit TOP, continuing at BOTTOM"),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Returns the range an operator acts on, if there is one at the cursor.
+    fn range(&self, target: Target, count: Option<usize>) -> Option<Range> {
+        let pos = &self.cursor.position;
+
+        match target {
+            Target::Motion(motion) => Some(self.resolve(motion)?.range(
+                &self.view.buffer(),
+                pos,
+                &self.motion_target(motion, count)?,
+            )),
+            Target::Object(object) => object.range(&self.view.buffer(), pos, count.unwrap_or(1)),
+            Target::Selection => Some(self.selection()?.range(&self.view.buffer())),
+            Target::Line => Some(Range::new(
+                pos.clone(),
+                Location::new(0, (pos.y + count.unwrap_or(1) - 1).min(self.view.len() - 1)),
+                true,
+            )),
+        }
+    }
+
+    fn half_page(&self) -> isize {
+        (self.view.height() / 2).try_into().unwrap()
+    }
+
+    fn full_page(&self) -> isize {
+        self.view
+            .height()
+            .saturating_sub(2)
+            .max(1)
+            .try_into()
+            .unwrap()
+    }
+
+    /// Scrolls the viewport without moving the cursor, unless it would leave the screen.
+    fn scroll(&mut self, delta: isize) {
+        self.view.scroll(delta);
+
+        let top = self.view.offset();
+        let bottom = self.view.bottom();
+
+        if self.cursor.position.y < top {
+            self.cursor.position.y = top;
+        } else if self.cursor.position.y > bottom {
+            self.cursor.position.y = bottom;
+        }
+        self.cursor.position.y = self.cursor.position.y.min(self.view.len() - 1);
+        self.recall_cursor();
+    }
+
+    /// Scrolls the viewport and moves the cursor by the same number of lines.
+    fn scroll_with_cursor(&mut self, delta: isize) {
+        self.cursor.position.y = self
+            .cursor
+            .position
+            .y
+            .saturating_add_signed(delta)
+            .min(self.view.len() - 1);
+        self.scroll(delta);
+    }
+
+    fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
         hide_cursor()?;
 
         if self.quit {
             clear_screen()?;
             print!("Goodbye.");
         } else {
-            self.view.render(&self.cursor.position)?;
+            let search = match self.mode {
+                Mode::Search(forward) => Search::new(self.command_line.clone(), forward).ok(),
+                Mode::Confirm => self
+                    .substitution
+                    .as_ref()
+                    .map(|substitution| substitution.search.clone()),
+                _ => None,
+            };
+
+            if let Some(diff) = &mut self.swap_diff {
+                let pos = Location::new(0, 0);
+                diff.draw_status(&self.status_line, &pos, Some(self.mode.name()))?;
+                diff.render(&pos, None, None)?;
+            } else {
+                // Windows are split, closed and resized between frames, so they're arranged
+                // afresh each time
+                // The tab line is only shown when there is more than one tab page
+                let tab_line = self.tabs.count() > 1;
+
+                self.windows
+                    .arrange(self.screen, u16::from(tab_line), &mut self.view);
+                self.view.follow(&self.cursor.position);
+                self.windows.render(
+                    &self.view,
+                    &self.cursor.position,
+                    &self.status_line,
+                    self.mode.name(),
+                )?;
+
+                if tab_line {
+                    let labels = self.tabs.labels(&self.windows, &self.view);
+                    View::draw_tab_line(&labels, self.tabs.current, self.screen.width)?;
+                }
+                self.view.render(
+                    &self.cursor.position,
+                    self.selection().as_ref(),
+                    search.as_ref(),
+                )?;
+            }
+
+            match self.mode {
+                Mode::Command => self
+                    .view
+                    .draw_bottom_message(format!(":{}", self.command_line))?,
+                Mode::Search(forward) => self.view.draw_bottom_message(format!(
+                    "{}{}",
+                    if forward { '/' } else { '?' },
+                    self.command_line
+                ))?,
+                Mode::Recover => {
+                    if let Some(swap) = &self.view.swap {
+                        let message = match swap.running {
+                            Some(pid) => format!(
+                                "beditor process {pid} is editing {}: (e)dit anyway \
+                                 (s)how changes (r)ecover (q)uit",
+                                self.view.file()
+                            ),
+                            None => format!(
+                                "Swap file found for {}: (r)ecover (d)elete (s)how changes \
+                                 (e)dit anyway (q)uit",
+                                self.view.file()
+                            ),
+                        };
+                        self.view.draw_bottom_message(message)?;
+                    }
+                }
+                Mode::Confirm => {
+                    if let Some(substitution) = &self.substitution {
+                        self.view.draw_bottom_message(format!(
+                            "replace with {} (y/n/a/q/l)?",
+                            substitution.replacement
+                        ))?;
+                    }
+                }
+                _ => (),
+            }
         }
 
         show_cursor()?;
@@ -162,35 +1903,36 @@ impl Editor {
     }
 
     fn mode(&mut self, mode: Mode) {
+        if mode == Mode::Insert && self.mode != Mode::Insert {
+            self.registers.inserted.clear();
+        }
+
+        if !matches!(mode, Mode::Visual(_)) {
+            if let Some(selection) = self.selection() {
+                self.last_selection = Some(selection.lines());
+            }
+        }
         self.mode = mode;
         change_cursor_style(&self.mode);
     }
 
     fn left(&mut self) {
         self.cursor.position.left();
-        self.cursor.update();
+        self.cursor
+            .update(self.view.display_column(&self.cursor.position));
     }
 
     fn right(&mut self) {
         if self.cursor.position.x < self.current_line_len() {
             self.cursor.position.right();
-            self.cursor.update();
-        }
-    }
-
-    fn up(&mut self) {
-        self.cursor.position.up();
-        if self.cursor.position.x > self.current_line_len() {
-            self.correct_cursor();
+            self.cursor
+                .update(self.view.display_column(&self.cursor.position));
         }
-        self.recall_cursor();
     }
 
     fn down(&mut self) {
-        self.cursor.position.down();
-        if self.cursor.position.x > self.current_line_len() {
-            // If the new position would be longer than the line, move back to the end of the line
-            self.correct_cursor();
+        if self.cursor.position.y + 1 < self.view.len() {
+            self.cursor.position.down();
         }
         self.recall_cursor();
     }
@@ -199,44 +1941,56 @@ impl Editor {
         self.cursor.position.x = self.current_line_len();
     }
 
+    /// Moves the cursor to the grapheme under the display column it was last placed at, so
+    /// vertical movement keeps its column across wide characters and short lines.
     fn recall_cursor(&mut self) {
-        let current_len = self.current_line_len();
-
-        if self.cursor.position.x < self.cursor.previous_x {
-            self.cursor.position.x = self.cursor.previous_x;
-        }
-        if current_len < self.cursor.previous_x {
-            self.correct_cursor();
-        }
+        self.cursor.position.x = self
+            .view
+            .column_to_grapheme(self.cursor.position.y, self.cursor.previous_x);
     }
 
-    fn current_line_len(&self) -> u16 {
-        self.view.nth_line_len(self.cursor.position.y as usize)
+    fn current_line_len(&self) -> usize {
+        self.view.nth_line_len(self.cursor.position.y)
     }
 }
 
+/// A block insert or append started with `I` or `A` in visual block mode, or by changing a
+/// block.
+struct BlockInsert {
+    /// Where typing started on the first line of the block.
+    start: Location,
+    last: usize,
+    /// The display column the text goes at on every line.
+    column: usize,
+    append: bool,
+}
+
+#[derive(Clone)]
 pub struct Cursor {
-    position: Position,
-    previous_x: u16,
+    position: Location,
+    previous_x: usize,
 }
 
 impl Cursor {
-    pub const fn new(pos: Position) -> Self {
+    pub const fn new(pos: Location) -> Self {
         Self {
             position: pos,
-            previous_x: 1,
+            previous_x: 0,
         }
     }
 
-    pub fn update(&mut self) {
-        if self.position.x != self.previous_x {
-            self.previous_x = self.position.clone().x;
-        }
+    pub const fn position(&self) -> &Location {
+        &self.position
     }
-}
 
-// pub struct Command {
-//     iterator: u32,
-//     movement: Movement,
-//     action: Action,
-// }
+    /// Keeps the cursor within the text, which may have been changed in another window.
+    pub fn clamp(&mut self, view: &View) {
+        self.position.y = self.position.y.min(view.len() - 1);
+        self.position.x = self.position.x.min(view.nth_line_len(self.position.y));
+    }
+
+    /// Remembers the display column of the cursor for vertical movement.
+    pub fn update(&mut self, column: usize) {
+        self.previous_x = column;
+    }
+}
@@ -0,0 +1,186 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, SHIFT_JIS, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+
+/// Looks up an encoding by the name Vim or the Encoding Standard gives it, such as `latin1`,
+/// `utf-16le` or `sjis`.
+pub fn find(name: &str) -> Option<&'static Encoding> {
+    match name.to_ascii_lowercase().as_str() {
+        // Vim's utf-16 and ucs-2 are big-endian, where the Encoding Standard's are little-endian
+        "utf-16" | "ucs-2" => Some(UTF_16BE),
+        "cp932" => Some(SHIFT_JIS),
+        // Encodings that are only ever decoded, such as the replacement one, aren't offered
+        name => Encoding::for_label(name.as_bytes())
+            .filter(|encoding| encoding.output_encoding() == *encoding || is_unicode(encoding)),
+    }
+}
+
+/// Returns the name of an encoding as Vim gives it.
+pub fn name(encoding: &'static Encoding) -> String {
+    if encoding == WINDOWS_1252 {
+        String::from("latin1")
+    } else if encoding == SHIFT_JIS {
+        String::from("sjis")
+    } else {
+        encoding.name().to_ascii_lowercase()
+    }
+}
+
+/// Whether an encoding can represent every character, so it can have a byte order mark.
+pub fn is_unicode(encoding: &'static Encoding) -> bool {
+    encoding == UTF_8 || encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+/// Decodes the contents of a file in the given encoding, or else the one its byte order mark
+/// names, or else UTF-8 if they are valid UTF-8, or else the encoding they look most like.
+/// Returns the text without the byte order mark, the encoding, whether there was a byte order
+/// mark and whether any bytes weren't valid in the encoding and were replaced.
+pub fn decode(
+    bytes: &[u8],
+    encoding: Option<&'static Encoding>,
+) -> (String, &'static Encoding, bool, bool) {
+    let encoding = encoding
+        .or_else(|| Encoding::for_bom(bytes).map(|(encoding, _)| encoding))
+        .unwrap_or_else(|| {
+            if std::str::from_utf8(bytes).is_ok() {
+                UTF_8
+            } else {
+                let mut detector = EncodingDetector::new();
+                detector.feed(bytes, true);
+                detector.guess(None, false)
+            }
+        });
+
+    let (text, malformed) = encoding.decode_without_bom_handling(bytes);
+    match text.strip_prefix('\u{feff}') {
+        Some(text) if is_unicode(encoding) => (text.to_string(), encoding, true, malformed),
+        _ => (text.into_owned(), encoding, false, malformed),
+    }
+}
+
+/// Whether the contents of a file look like binary data rather than text, which they do if
+/// they have a NUL byte and no byte order mark to say they are UTF-16.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.contains(&0) && Encoding::for_bom(bytes).is_none()
+}
+
+/// Encodes text to save it, or returns `None` if it has characters the encoding can't
+/// represent.
+pub fn encode(text: &str, encoding: &'static Encoding) -> Option<Vec<u8>> {
+    if encoding == UTF_16LE {
+        Some(text.encode_utf16().flat_map(u16::to_le_bytes).collect())
+    } else if encoding == UTF_16BE {
+        Some(text.encode_utf16().flat_map(u16::to_be_bytes).collect())
+    } else {
+        // Encoding a character the encoding doesn't have gives an HTML character reference
+        // in its place, which would silently change the text
+        let (bytes, _, unmappable) = encoding.encode(text);
+        (!unmappable).then(|| bytes.into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{EUC_JP, REPLACEMENT};
+
+    #[test]
+    fn names() {
+        assert_eq!(find("latin1"), Some(WINDOWS_1252));
+        assert_eq!(find("UTF-8"), Some(UTF_8));
+        assert_eq!(find("utf-16"), Some(UTF_16BE));
+        assert_eq!(find("utf-16le"), Some(UTF_16LE));
+        assert_eq!(find("cp932"), Some(SHIFT_JIS));
+        assert_eq!(find("euc-jp"), Some(EUC_JP));
+        assert_eq!(find("replacement"), None);
+        assert_eq!(find("klingon"), None);
+
+        assert_eq!(name(WINDOWS_1252), "latin1");
+        assert_eq!(name(SHIFT_JIS), "sjis");
+        assert_eq!(name(UTF_16LE), "utf-16le");
+        assert_eq!(find(&name(EUC_JP)), Some(EUC_JP));
+        assert!(!is_unicode(REPLACEMENT) && !is_unicode(WINDOWS_1252));
+    }
+
+    #[test]
+    fn byte_order_marks() {
+        assert_eq!(
+            decode(b"\xef\xbb\xbfhi", None),
+            (String::from("hi"), UTF_8, true, false)
+        );
+        assert_eq!(
+            decode(b"\xff\xfeh\0i\0", None),
+            (String::from("hi"), UTF_16LE, true, false)
+        );
+        assert_eq!(
+            decode(b"\xfe\xff\0h\0i", None),
+            (String::from("hi"), UTF_16BE, true, false)
+        );
+        assert_eq!(
+            decode(b"hi", None),
+            (String::from("hi"), UTF_8, false, false)
+        );
+    }
+
+    #[test]
+    fn detection() {
+        assert_eq!(
+            decode("naïve".as_bytes(), None),
+            (String::from("naïve"), UTF_8, false, false)
+        );
+
+        // Not valid UTF-8, so it is guessed
+        let (text, encoding, bom, malformed) = decode(b"caf\xe9 cr\xe8me br\xfbl\xe9e", None);
+        assert_eq!(text, "café crème brûlée");
+        assert_eq!(encoding, WINDOWS_1252);
+        assert!(!bom && !malformed);
+    }
+
+    #[test]
+    fn given_encodings() {
+        // A given encoding is used even if the bytes look like another one
+        assert_eq!(
+            decode("é".as_bytes(), Some(WINDOWS_1252)),
+            (String::from("Ã©"), WINDOWS_1252, false, false)
+        );
+
+        let (text, encoding, bom, malformed) = decode(b"a\xffb", Some(UTF_8));
+        assert_eq!(text, "a\u{fffd}b");
+        assert_eq!(encoding, UTF_8);
+        assert!(!bom && malformed);
+    }
+
+    #[test]
+    fn round_trips() {
+        let text = "façade — 日本語 🦀";
+
+        for encoding in [UTF_8, UTF_16LE, UTF_16BE] {
+            let bytes = encode(text, encoding).unwrap();
+            assert_eq!(decode(&bytes, Some(encoding)).0, text);
+
+            // With the byte order mark, the encoding is found from it
+            let bytes = [encode("\u{feff}", encoding).unwrap(), bytes].concat();
+            assert_eq!(
+                decode(&bytes, None),
+                (String::from(text), encoding, true, false)
+            );
+        }
+
+        let bytes = encode("日本語", SHIFT_JIS).unwrap();
+        assert_eq!(bytes, b"\x93\xfa\x96\x7b\x8c\xea");
+        assert_eq!(decode(&bytes, Some(SHIFT_JIS)).0, "日本語");
+        assert_eq!(encode("crème", WINDOWS_1252), Some(b"cr\xe8me".to_vec()));
+    }
+
+    #[test]
+    fn unmappable_characters() {
+        assert_eq!(encode("日本語", WINDOWS_1252), None);
+        assert_eq!(encode("🦀", SHIFT_JIS), None);
+    }
+
+    #[test]
+    fn binary() {
+        assert!(is_binary(b"\x7fELF\x02\x01\x01\0\0"));
+        assert!(!is_binary(b"plain text"));
+        assert!(!is_binary(b"\xff\xfeh\0i\0"));
+    }
+}
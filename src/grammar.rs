@@ -0,0 +1,550 @@
+use crate::motion::{Find, Motion};
+use crate::operator::Operator;
+use crate::register::is_register;
+use crate::text_object::{ObjectKind, TextObject};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// The largest count a command takes, so that moving by it can't overflow. Longer counts are
+/// cut down to it.
+const MAX_COUNT: usize = 999_999_999;
+
+/// What an operator acts on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Motion(Motion),
+    Object(TextObject),
+    /// A doubled operator such as `dd`, which acts on `count` whole lines.
+    Line,
+    /// The text selected in visual mode.
+    Selection,
+}
+
+/// A normal mode command built from an optional count, an optional operator and a target, such
+/// as `3dw`. Without an operator, the target must be a motion which moves the cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Operation {
+    pub count: Option<usize>,
+    /// The register named with `"`, such as the `a` of `"ayy`.
+    pub register: Option<char>,
+    pub operator: Option<Operator>,
+    pub target: Target,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    Operation(Operation),
+    /// Any other normal mode command, such as `i` or `zz`, with the count and register typed
+    /// before it.
+    Command {
+        count: Option<usize>,
+        register: Option<char>,
+        keys: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Parse {
+    /// More keys are needed to finish the command.
+    Incomplete,
+    /// The keys can't form a command.
+    Invalid,
+    Done(Action),
+}
+
+/// Parses the keys typed in normal mode so far.
+pub fn parse(keys: &str) -> Parse {
+    match action(&mut Keys(keys.chars().peekable())) {
+        Ok(action) => Parse::Done(action),
+        Err(parse) => parse,
+    }
+}
+
+/// Parses the keys typed in visual mode so far. Operators act on the selection straight away,
+/// and a text object becomes the new selection.
+pub fn parse_visual(keys: &str) -> Parse {
+    match visual_action(&mut Keys(keys.chars().peekable())) {
+        Ok(action) => Parse::Done(action),
+        Err(parse) => parse,
+    }
+}
+
+struct Keys<'a>(Peekable<Chars<'a>>);
+
+impl Keys<'_> {
+    fn next(&mut self) -> Result<char, Parse> {
+        self.0.next().ok_or(Parse::Incomplete)
+    }
+
+    /// Reads a count, which can't start with `0` as that is a motion.
+    fn count(&mut self) -> Option<usize> {
+        let mut count: Option<usize> = None;
+
+        while let Some(digit) = self.0.peek().and_then(|c| c.to_digit(10)) {
+            if digit == 0 && count.is_none() {
+                break;
+            }
+            let more = count
+                .unwrap_or(0)
+                .saturating_mul(10)
+                .saturating_add(digit as usize);
+            count = Some(more.min(MAX_COUNT));
+            self.0.next();
+        }
+        count
+    }
+
+    /// Reads a register name given with `"`, such as the `"a` of `"ayy`.
+    fn register(&mut self) -> Result<Option<char>, Parse> {
+        if self.0.next_if_eq(&'"').is_none() {
+            return Ok(None);
+        }
+
+        let name = self.next()?;
+
+        if is_register(name) {
+            Ok(Some(name))
+        } else {
+            Err(Parse::Invalid)
+        }
+    }
+
+    /// Reads a count and a register in either order, such as `2"a` or `"a2`. Counts either side
+    /// of the register are multiplied.
+    fn prefix(&mut self) -> Result<(Option<usize>, Option<char>), Parse> {
+        let count = self.count();
+        let register = self.register()?;
+
+        Ok((multiply(count, self.count()), register))
+    }
+}
+
+/// Combines the counts typed before and after something, such as the `2` and `3` of `2d3w`.
+fn multiply(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.saturating_mul(b).min(MAX_COUNT)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn action(keys: &mut Keys) -> Result<Action, Parse> {
+    let (count, register) = keys.prefix()?;
+    let key = keys.next()?;
+
+    let operator = match key {
+        'g' => {
+            let second = keys.next()?;
+
+            match Operator::from_g_key(second) {
+                Some(operator) => operator,
+                None => return Ok(motion_or_command(count, register, &format!("g{second}"))),
+            }
+        }
+        'z' => {
+            let second = keys.next()?;
+            return Ok(motion_or_command(count, register, &format!("z{second}")));
+        }
+        _ => match Operator::from_key(key) {
+            Some(operator) => operator,
+            None => {
+                if let Some(motion) = find(key, keys)? {
+                    return Ok(Action::Operation(Operation {
+                        count,
+                        register,
+                        operator: None,
+                        target: Target::Motion(motion),
+                    }));
+                }
+                return Ok(shortcut(count, register, key));
+            }
+        },
+    };
+
+    let count = multiply(count, keys.count());
+
+    Ok(Action::Operation(Operation {
+        count,
+        register,
+        operator: Some(operator),
+        target: target(keys, operator)?,
+    }))
+}
+
+fn visual_action(keys: &mut Keys) -> Result<Action, Parse> {
+    let (count, register) = keys.prefix()?;
+    let key = keys.next()?;
+
+    let operator = match key {
+        'x' => Operator::Delete,
+        's' => Operator::Change,
+        'u' => Operator::Lowercase,
+        'U' => Operator::Uppercase,
+        '~' => Operator::ToggleCase,
+        'J' => Operator::Join,
+        'r' => Operator::Replace(keys.next()?),
+        'i' | 'a' => {
+            return Ok(Action::Operation(Operation {
+                count,
+                register,
+                operator: None,
+                target: Target::Object(object(key, keys)?),
+            }))
+        }
+        'g' | 'z' => {
+            let second = keys.next()?;
+
+            match Operator::from_g_key(second) {
+                Some(operator) if key == 'g' => operator,
+                _ => {
+                    return Ok(motion_or_command(
+                        count,
+                        register,
+                        &format!("{key}{second}"),
+                    ))
+                }
+            }
+        }
+        _ => match Operator::from_key(key) {
+            Some(operator) => operator,
+            None => {
+                if let Some(motion) = find(key, keys)? {
+                    return Ok(Action::Operation(Operation {
+                        count,
+                        register,
+                        operator: None,
+                        target: Target::Motion(motion),
+                    }));
+                }
+                return Ok(motion_or_command(count, register, &key.to_string()));
+            }
+        },
+    };
+
+    Ok(Action::Operation(Operation {
+        count,
+        register,
+        operator: Some(operator),
+        target: Target::Selection,
+    }))
+}
+
+/// Parses what comes after an operator.
+fn target(keys: &mut Keys, operator: Operator) -> Result<Target, Parse> {
+    let key = keys.next()?;
+
+    match key {
+        'i' | 'a' => Ok(Target::Object(object(key, keys)?)),
+        'g' => {
+            let second = keys.next()?;
+
+            if Operator::from_g_key(second) == Some(operator) {
+                Ok(Target::Line)
+            } else {
+                motion(&format!("g{second}"))
+                    .map(Target::Motion)
+                    .ok_or(Parse::Invalid)
+            }
+        }
+        _ if Operator::from_key(key) == Some(operator)
+            || Operator::from_g_key(key) == Some(operator) =>
+        {
+            Ok(Target::Line)
+        }
+        _ => find(key, keys)?
+            .or_else(|| motion(&key.to_string()))
+            .map(Target::Motion)
+            .ok_or(Parse::Invalid),
+    }
+}
+
+/// Parses the key after `i` or `a` into a text object.
+fn object(key: char, keys: &mut Keys) -> Result<TextObject, Parse> {
+    Ok(TextObject {
+        around: key == 'a',
+        kind: ObjectKind::from_key(keys.next()?).ok_or(Parse::Invalid)?,
+    })
+}
+
+/// Parses `f`, `t`, `F` or `T` and the character after it.
+fn find(key: char, keys: &mut Keys) -> Result<Option<Motion>, Parse> {
+    let (forward, till) = match key {
+        'f' => (true, false),
+        't' => (true, true),
+        'F' => (false, false),
+        'T' => (false, true),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(Motion::Find(Find {
+        target: keys.next()?,
+        forward,
+        till,
+    })))
+}
+
+fn motion(keys: &str) -> Option<Motion> {
+    match keys {
+        "h" => Some(Motion::Left),
+        "l" => Some(Motion::Right),
+        "k" => Some(Motion::Up),
+        "j" => Some(Motion::Down),
+        "gk" => Some(Motion::ScreenUp),
+        "gj" => Some(Motion::ScreenDown),
+        "H" => Some(Motion::ScreenTop),
+        "M" => Some(Motion::ScreenMiddle),
+        "L" => Some(Motion::ScreenBottom),
+        "w" => Some(Motion::WordForward(false)),
+        "W" => Some(Motion::WordForward(true)),
+        "b" => Some(Motion::WordBackward(false)),
+        "B" => Some(Motion::WordBackward(true)),
+        "e" => Some(Motion::WordEnd(false)),
+        "E" => Some(Motion::WordEnd(true)),
+        "ge" => Some(Motion::WordEndBackward(false)),
+        "gE" => Some(Motion::WordEndBackward(true)),
+        "0" => Some(Motion::LineStart),
+        "^" => Some(Motion::FirstNonBlank),
+        "$" => Some(Motion::LineEnd),
+        "gg" => Some(Motion::FirstLine),
+        "G" => Some(Motion::LastLine),
+        "}" => Some(Motion::ParagraphForward),
+        "{" => Some(Motion::ParagraphBackward),
+        ")" => Some(Motion::SentenceForward),
+        "(" => Some(Motion::SentenceBackward),
+        "%" => Some(Motion::MatchingBracket),
+        ";" => Some(Motion::RepeatFind),
+        "," => Some(Motion::RepeatFindReversed),
+        "n" => Some(Motion::SearchNext),
+        "N" => Some(Motion::SearchPrevious),
+        "*" => Some(Motion::SearchWord(true)),
+        "#" => Some(Motion::SearchWord(false)),
+        _ => None,
+    }
+}
+
+fn motion_or_command(count: Option<usize>, register: Option<char>, keys: &str) -> Action {
+    motion(keys).map_or_else(
+        || Action::Command {
+            count,
+            register,
+            keys: keys.to_string(),
+        },
+        |motion| {
+            Action::Operation(Operation {
+                count,
+                register,
+                operator: None,
+                target: Target::Motion(motion),
+            })
+        },
+    )
+}
+
+/// Expands single keys that stand for an operator and target, such as `x` for `dl`.
+fn shortcut(count: Option<usize>, register: Option<char>, key: char) -> Action {
+    let (operator, target) = match key {
+        'x' => (Operator::Delete, Target::Motion(Motion::Right)),
+        'X' => (Operator::Delete, Target::Motion(Motion::Left)),
+        'D' => (Operator::Delete, Target::Motion(Motion::LineEnd)),
+        'C' => (Operator::Change, Target::Motion(Motion::LineEnd)),
+        's' => (Operator::Change, Target::Motion(Motion::Right)),
+        'S' => (Operator::Change, Target::Line),
+        'Y' => (Operator::Yank, Target::Line),
+        _ => return motion_or_command(count, register, &key.to_string()),
+    };
+
+    Action::Operation(Operation {
+        count,
+        register,
+        operator: Some(operator),
+        target,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation(count: Option<usize>, operator: Option<Operator>, target: Target) -> Parse {
+        Parse::Done(Action::Operation(Operation {
+            count,
+            register: None,
+            operator,
+            target,
+        }))
+    }
+
+    #[test]
+    fn operators_take_motions_and_objects() {
+        assert_eq!(
+            parse("dw"),
+            operation(
+                None,
+                Some(Operator::Delete),
+                Target::Motion(Motion::WordForward(false))
+            )
+        );
+        assert_eq!(
+            parse("c$"),
+            operation(
+                None,
+                Some(Operator::Change),
+                Target::Motion(Motion::LineEnd)
+            )
+        );
+        assert_eq!(
+            parse("gUiw"),
+            operation(
+                None,
+                Some(Operator::Uppercase),
+                Target::Object(TextObject {
+                    around: false,
+                    kind: ObjectKind::Word(false),
+                }),
+            )
+        );
+        assert_eq!(
+            parse("dfx"),
+            operation(
+                None,
+                Some(Operator::Delete),
+                Target::Motion(Motion::Find(Find {
+                    target: 'x',
+                    forward: true,
+                    till: false,
+                })),
+            )
+        );
+    }
+
+    #[test]
+    fn doubled_operators_act_on_lines() {
+        assert_eq!(
+            parse("dd"),
+            operation(None, Some(Operator::Delete), Target::Line)
+        );
+        assert_eq!(
+            parse("gUU"),
+            operation(None, Some(Operator::Uppercase), Target::Line)
+        );
+        assert_eq!(
+            parse("gUgU"),
+            operation(None, Some(Operator::Uppercase), Target::Line)
+        );
+    }
+
+    #[test]
+    fn counts_either_side_of_the_operator_multiply() {
+        assert_eq!(
+            parse("y2j"),
+            operation(Some(2), Some(Operator::Yank), Target::Motion(Motion::Down))
+        );
+        assert_eq!(
+            parse("2d3w"),
+            operation(
+                Some(6),
+                Some(Operator::Delete),
+                Target::Motion(Motion::WordForward(false))
+            )
+        );
+        assert_eq!(
+            parse("5x"),
+            operation(
+                Some(5),
+                Some(Operator::Delete),
+                Target::Motion(Motion::Right)
+            )
+        );
+    }
+
+    #[test]
+    fn zero_is_a_motion_unless_it_continues_a_count() {
+        assert_eq!(
+            parse("0"),
+            operation(None, None, Target::Motion(Motion::LineStart))
+        );
+        assert_eq!(
+            parse("10j"),
+            operation(Some(10), None, Target::Motion(Motion::Down))
+        );
+    }
+
+    #[test]
+    fn long_counts_are_capped() {
+        assert_eq!(
+            parse("99999999999999999999dd"),
+            operation(Some(MAX_COUNT), Some(Operator::Delete), Target::Line)
+        );
+        assert_eq!(
+            parse("99999d99999d"),
+            operation(Some(MAX_COUNT), Some(Operator::Delete), Target::Line)
+        );
+    }
+
+    #[test]
+    fn registers_come_before_or_after_a_count() {
+        let yank = |count| {
+            Parse::Done(Action::Operation(Operation {
+                count,
+                register: Some('a'),
+                operator: Some(Operator::Yank),
+                target: Target::Line,
+            }))
+        };
+
+        assert_eq!(parse("\"ayy"), yank(None));
+        assert_eq!(parse("2\"ayy"), yank(Some(2)));
+        assert_eq!(parse("\"a2yy"), yank(Some(2)));
+    }
+
+    #[test]
+    fn unfinished_and_invalid_keys() {
+        assert_eq!(parse("d"), Parse::Incomplete);
+        assert_eq!(parse("2d"), Parse::Incomplete);
+        assert_eq!(parse("f"), Parse::Incomplete);
+        assert_eq!(parse("dq"), Parse::Invalid);
+        assert_eq!(parse("diq"), Parse::Invalid);
+    }
+
+    #[test]
+    fn other_keys_are_commands() {
+        assert_eq!(
+            parse("3i"),
+            Parse::Done(Action::Command {
+                count: Some(3),
+                register: None,
+                keys: String::from("i"),
+            })
+        );
+        assert_eq!(
+            parse("zz"),
+            Parse::Done(Action::Command {
+                count: None,
+                register: None,
+                keys: String::from("zz"),
+            })
+        );
+    }
+
+    #[test]
+    fn visual_operators_act_on_the_selection() {
+        assert_eq!(
+            parse_visual("d"),
+            operation(None, Some(Operator::Delete), Target::Selection)
+        );
+        assert_eq!(
+            parse_visual("rx"),
+            operation(None, Some(Operator::Replace('x')), Target::Selection)
+        );
+        assert_eq!(
+            parse_visual("ap"),
+            operation(
+                None,
+                None,
+                Target::Object(TextObject {
+                    around: true,
+                    kind: ObjectKind::Paragraph,
+                }),
+            )
+        );
+    }
+}
@@ -1,14 +1,46 @@
 use crate::editor::Editor;
+use crate::view::Wrap;
 
 mod buffer;
+mod buffer_list;
+mod clipboard;
+mod command;
 mod editor;
+mod encoding;
+mod grammar;
+mod motion;
+mod operator;
+mod register;
+mod search;
+mod selection;
+mod substitute;
+mod swap;
+mod tab_page;
 mod terminal;
+mod text_object;
+mod undo_file;
 mod view;
+mod window;
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let (flags, args): (Vec<String>, Vec<String>) = std::env::args()
+        .skip(1)
+        .partition(|arg| arg.starts_with("--"));
 
-    if let Err(err) = Editor::new(&args.get(1).cloned()).run() {
+    let wrap = if flags.iter().any(|flag| flag == "--wrap") {
+        Wrap::Soft
+    } else {
+        Wrap::Scroll
+    };
+
+    // Leave raw mode if the editor panics, so the terminal can still be used
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        terminal::terminate().ok();
+        hook(info);
+    }));
+
+    if let Err(err) = Editor::new(&args, wrap).and_then(|mut editor| editor.run()) {
         println!("FATAL: {err}");
     }
 }
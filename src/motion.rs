@@ -0,0 +1,847 @@
+use crate::buffer::{Buffer, Location, Range};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// How an operator treats the text between the cursor and the target of a motion.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MotionKind {
+    /// The character at the far end is not included.
+    Exclusive,
+    /// The character at the far end is included.
+    Inclusive,
+    /// Every line from the cursor's to the target's is included in full.
+    Linewise,
+}
+
+/// A search for a character on the cursor's line with `f`, `t`, `F` or `T`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Find {
+    pub target: char,
+    pub forward: bool,
+    /// Whether to stop just before the character, as `t` and `T` do.
+    pub till: bool,
+}
+
+impl Find {
+    /// Returns the same search in the opposite direction, for `,`.
+    pub const fn reversed(self) -> Self {
+        Self {
+            forward: !self.forward,
+            ..self
+        }
+    }
+
+    /// Finds the `count`th occurrence of the character. When repeating a `t` or `T` search,
+    /// a match right next to the cursor is skipped so the cursor doesn't get stuck on it.
+    pub fn apply(
+        self,
+        buffer: &Buffer,
+        pos: &Location,
+        count: usize,
+        repeat: bool,
+    ) -> Option<Location> {
+        let target = self.target.to_string();
+        let graphemes = buffer.graphemes(pos.y);
+        let skip = usize::from(self.till && repeat);
+
+        let x = if self.forward {
+            (pos.x + 1 + skip..graphemes.len())
+                .filter(|x| graphemes[*x] == target)
+                .nth(count - 1)?
+        } else {
+            (0..pos.x.saturating_sub(skip))
+                .rev()
+                .filter(|x| graphemes[*x] == target)
+                .nth(count - 1)?
+        };
+
+        match (self.till, self.forward) {
+            (false, _) => Some(Location::new(x, pos.y)),
+            (true, true) => Some(Location::new(x - 1, pos.y)),
+            (true, false) => Some(Location::new(x + 1, pos.y)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    /// `gj` and `gk` move by screen row, so they depend on the view rather than the buffer.
+    ScreenUp,
+    ScreenDown,
+    /// `H`, `M` and `L` move to the top, middle and bottom of the screen, so they depend on
+    /// the view too.
+    ScreenTop,
+    ScreenMiddle,
+    ScreenBottom,
+    /// `w` and `W`. The flag is set for WORDs, which are separated by whitespace only.
+    WordForward(bool),
+    /// `b` and `B`.
+    WordBackward(bool),
+    /// `e` and `E`.
+    WordEnd(bool),
+    /// `ge` and `gE`.
+    WordEndBackward(bool),
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    /// `gg`, or the line given as a count.
+    FirstLine,
+    /// `G`, or the line given as a count.
+    LastLine,
+    ParagraphForward,
+    ParagraphBackward,
+    SentenceForward,
+    SentenceBackward,
+    MatchingBracket,
+    Find(Find),
+    /// `;` and `,` repeat the last `Find`, which only the editor knows.
+    RepeatFind,
+    RepeatFindReversed,
+    /// `n` and `N` repeat the last search, which only the editor knows too.
+    SearchNext,
+    SearchPrevious,
+    /// `*` searches forward for the word under the cursor, and `#` backward.
+    SearchWord(bool),
+}
+
+impl Motion {
+    pub const fn kind(self) -> MotionKind {
+        match self {
+            Self::Up
+            | Self::Down
+            | Self::ScreenTop
+            | Self::ScreenMiddle
+            | Self::ScreenBottom
+            | Self::FirstLine
+            | Self::LastLine => MotionKind::Linewise,
+            Self::LineEnd | Self::WordEnd(_) | Self::WordEndBackward(_) | Self::MatchingBracket => {
+                MotionKind::Inclusive
+            }
+            Self::Find(find) if find.forward => MotionKind::Inclusive,
+            Self::ScreenUp
+            | Self::ScreenDown
+            | Self::Left
+            | Self::Right
+            | Self::WordForward(_)
+            | Self::WordBackward(_)
+            | Self::LineStart
+            | Self::FirstNonBlank
+            | Self::ParagraphForward
+            | Self::ParagraphBackward
+            | Self::SentenceForward
+            | Self::SentenceBackward
+            | Self::Find(_)
+            | Self::RepeatFind
+            | Self::RepeatFindReversed
+            | Self::SearchNext
+            | Self::SearchPrevious
+            | Self::SearchWord(_) => MotionKind::Exclusive,
+        }
+    }
+
+    /// Returns the range an operator acts on when given this motion from `from` to `to`.
+    pub fn range(self, buffer: &Buffer, from: &Location, to: &Location) -> Range {
+        let kind = self.kind();
+        let mut range = Range::new(from.clone(), to.clone(), kind == MotionKind::Linewise);
+
+        match kind {
+            MotionKind::Inclusive => range.end.x += 1,
+            // An exclusive motion ending at the start of a line stops at the end of the line
+            // before instead, so `dw` on the last word of a line doesn't join the next one
+            MotionKind::Exclusive if range.end.x == 0 && range.end.y > range.start.y => {
+                let y = range.end.y - 1;
+                range.end = Location::new(buffer.nth_line_len(y), y);
+            }
+            _ => (),
+        }
+        range
+    }
+
+    /// Returns where the motion moves the cursor from `pos`, or `None` if it can't move, such
+    /// as when `f` finds nothing. Motions that depend on the view or the editor's state don't
+    /// move the cursor here.
+    pub fn apply(self, buffer: &Buffer, pos: &Location, count: Option<usize>) -> Option<Location> {
+        let last_line = buffer.len() - 1;
+        let lines = Lines::new(buffer);
+        let n = count.unwrap_or(1);
+        let repeat = |f: &dyn Fn(&Location) -> Location| (0..n).fold(pos.clone(), |pos, _| f(&pos));
+        let first_non_blank = |y: usize| Location::new(buffer.indentation(y), y);
+
+        Some(match self {
+            Self::Left => Location::new(pos.x.saturating_sub(n), pos.y),
+            Self::Right => Location::new((pos.x + n).min(buffer.nth_line_len(pos.y)), pos.y),
+            Self::Up => Location::new(pos.x, pos.y.saturating_sub(n)),
+            Self::Down => Location::new(pos.x, (pos.y + n).min(last_line)),
+            Self::WordForward(big) => repeat(&|pos| word_forward(&lines, pos, big)),
+            Self::WordBackward(big) => repeat(&|pos| word_backward(&lines, pos, big)),
+            Self::WordEnd(big) => repeat(&|pos| word_end(&lines, pos, big)),
+            Self::WordEndBackward(big) => repeat(&|pos| word_end_backward(&lines, pos, big)),
+            Self::LineStart => Location::new(0, pos.y),
+            Self::FirstNonBlank => first_non_blank(pos.y),
+            Self::LineEnd => {
+                let y = (pos.y + n - 1).min(last_line);
+                Location::new(buffer.nth_line_len(y), y)
+            }
+            Self::FirstLine => first_non_blank(n.min(buffer.len()) - 1),
+            Self::LastLine => {
+                first_non_blank(count.map_or(last_line, |n| n.clamp(1, buffer.len()) - 1))
+            }
+            Self::ParagraphForward => repeat(&|pos| paragraph(buffer, pos, true)),
+            Self::ParagraphBackward => repeat(&|pos| paragraph(buffer, pos, false)),
+            Self::SentenceForward => repeat(&|pos| sentence(&lines, pos, true)),
+            Self::SentenceBackward => repeat(&|pos| sentence(&lines, pos, false)),
+            Self::MatchingBracket => matching_bracket(buffer, pos)?,
+            Self::Find(find) => find.apply(buffer, pos, n, false)?,
+            Self::ScreenUp
+            | Self::ScreenDown
+            | Self::ScreenTop
+            | Self::ScreenMiddle
+            | Self::ScreenBottom
+            | Self::RepeatFind
+            | Self::RepeatFindReversed
+            | Self::SearchNext
+            | Self::SearchPrevious
+            | Self::SearchWord(_) => return None,
+        })
+    }
+}
+
+/// The kinds of character that words are made of. A word is a run of either keyword or
+/// punctuation characters, and a WORD is a run of either of them.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Blank,
+    Keyword,
+    Punctuation,
+}
+
+/// The lines of a buffer split into graphemes for motions that step through them one at a
+/// time. The last couple of lines split are kept, so a line is only split again when a motion
+/// comes back to it after moving on.
+pub struct Lines<'a> {
+    buffer: &'a Buffer,
+    split: RefCell<Vec<(usize, Rc<[String]>)>>,
+}
+
+impl<'a> Lines<'a> {
+    pub const fn new(buffer: &'a Buffer) -> Self {
+        Self {
+            buffer,
+            split: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Returns the graphemes of the nth line.
+    fn line(&self, n: usize) -> Rc<[String]> {
+        let mut split = self.split.borrow_mut();
+
+        if let Some((_, line)) = split.iter().find(|(y, _)| *y == n) {
+            return Rc::clone(line);
+        }
+
+        let line: Rc<[String]> = self.buffer.graphemes(n).into();
+        if split.len() == 2 {
+            split.remove(0);
+        }
+        split.push((n, Rc::clone(&line)));
+        line
+    }
+
+    /// Returns the length of the nth line in graphemes.
+    pub fn line_len(&self, n: usize) -> usize {
+        self.line(n).len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+/// Classifies the grapheme at a location, treating the end of a line as blank.
+pub fn class(lines: &Lines, pos: &Location) -> Class {
+    lines
+        .line(pos.y)
+        .get(pos.x)
+        .map_or(Class::Blank, |grapheme| class_of(grapheme))
+}
+
+/// Classifies the grapheme at a location for word or WORD motions.
+pub fn word_class(lines: &Lines, pos: &Location, big: bool) -> Class {
+    match class(lines, pos) {
+        Class::Punctuation if big => Class::Keyword,
+        class => class,
+    }
+}
+
+pub fn class_of(grapheme: &str) -> Class {
+    let c = grapheme.chars().next().unwrap_or(' ');
+
+    if c.is_whitespace() {
+        Class::Blank
+    } else if c.is_alphanumeric() || c == '_' {
+        Class::Keyword
+    } else {
+        Class::Punctuation
+    }
+}
+
+/// Returns the location after `pos`, where the end of each line counts as a position of its
+/// own, or `None` at the end of the document.
+pub fn next(lines: &Lines, pos: &Location) -> Option<Location> {
+    if pos.x < lines.line_len(pos.y) {
+        Some(Location::new(pos.x + 1, pos.y))
+    } else if pos.y + 1 < lines.len() {
+        Some(Location::new(0, pos.y + 1))
+    } else {
+        None
+    }
+}
+
+/// Returns the location before `pos`, the inverse of `next`.
+pub fn previous(lines: &Lines, pos: &Location) -> Option<Location> {
+    if pos.x > 0 {
+        Some(Location::new(pos.x - 1, pos.y))
+    } else if pos.y > 0 {
+        Some(Location::new(lines.line_len(pos.y - 1), pos.y - 1))
+    } else {
+        None
+    }
+}
+
+/// Whether a location is on an empty line, which counts as a word of its own.
+pub fn is_empty_line(lines: &Lines, pos: &Location) -> bool {
+    lines.line_len(pos.y) == 0
+}
+
+fn word_forward(lines: &Lines, pos: &Location, big: bool) -> Location {
+    let start = word_class(lines, pos, big);
+    let mut current = pos.clone();
+
+    if start != Class::Blank {
+        while word_class(lines, &current, big) == start {
+            match next(lines, &current) {
+                Some(pos) => current = pos,
+                None => return current,
+            }
+        }
+    }
+
+    while class(lines, &current) == Class::Blank {
+        if current != *pos && is_empty_line(lines, &current) {
+            break;
+        }
+        match next(lines, &current) {
+            Some(pos) => current = pos,
+            None => break,
+        }
+    }
+    current
+}
+
+fn word_backward(lines: &Lines, pos: &Location, big: bool) -> Location {
+    let Some(mut current) = previous(lines, pos) else {
+        return pos.clone();
+    };
+
+    while class(lines, &current) == Class::Blank {
+        if is_empty_line(lines, &current) {
+            return current;
+        }
+        match previous(lines, &current) {
+            Some(pos) => current = pos,
+            None => return current,
+        }
+    }
+
+    let start = word_class(lines, &current, big);
+
+    while let Some(pos) = previous(lines, &current) {
+        if word_class(lines, &pos, big) != start {
+            break;
+        }
+        current = pos;
+    }
+    current
+}
+
+fn word_end(lines: &Lines, pos: &Location, big: bool) -> Location {
+    let Some(mut current) = next(lines, pos) else {
+        return pos.clone();
+    };
+
+    // There is no word end after the last word of the document to move to
+    while class(lines, &current) == Class::Blank {
+        match next(lines, &current) {
+            Some(pos) => current = pos,
+            None => return pos.clone(),
+        }
+    }
+
+    let start = word_class(lines, &current, big);
+
+    while let Some(pos) = next(lines, &current) {
+        if word_class(lines, &pos, big) != start {
+            break;
+        }
+        current = pos;
+    }
+    current
+}
+
+fn word_end_backward(lines: &Lines, pos: &Location, big: bool) -> Location {
+    let start = word_class(lines, pos, big);
+    let mut left_word = start == Class::Blank;
+    let mut current = pos.clone();
+
+    while let Some(pos) = previous(lines, &current) {
+        current = pos;
+
+        match word_class(lines, &current, big) {
+            Class::Blank => {
+                left_word = true;
+
+                if is_empty_line(lines, &current) {
+                    break;
+                }
+            }
+            class if left_word || class != start => break,
+            _ => (),
+        }
+    }
+    current
+}
+
+/// Moves to the next or previous empty line, or to the start or end of the document if there
+/// are none.
+fn paragraph(buffer: &Buffer, pos: &Location, forward: bool) -> Location {
+    let is_empty = |y: usize| buffer.nth_line_len(y) == 0;
+    let mut y = pos.y;
+
+    if forward {
+        let last_line = buffer.len() - 1;
+
+        while y < last_line && is_empty(y) {
+            y += 1;
+        }
+        while y < last_line && !is_empty(y) {
+            y += 1;
+        }
+        Location::new(
+            if is_empty(y) {
+                0
+            } else {
+                buffer.nth_line_len(y)
+            },
+            y,
+        )
+    } else {
+        while y > 0 && is_empty(y) {
+            y -= 1;
+        }
+        while y > 0 && !is_empty(y) {
+            y -= 1;
+        }
+        Location::new(0, y)
+    }
+}
+
+const SENTENCE_ENDS: [&str; 3] = [".", "!", "?"];
+const SENTENCE_CLOSERS: [&str; 4] = [")", "]", "\"", "'"];
+
+/// Whether a sentence starts at a location: the first non-blank character after a `.`, `!` or
+/// `?` and whitespace, or after an empty line. Empty lines are sentences of their own.
+pub fn is_sentence_start(lines: &Lines, pos: &Location) -> bool {
+    if is_empty_line(lines, pos) {
+        return true;
+    }
+    if class(lines, pos) == Class::Blank {
+        return false;
+    }
+
+    let mut current = pos.clone();
+    let mut blanks = false;
+
+    while let Some(pos) = previous(lines, &current) {
+        current = pos;
+
+        if class(lines, &current) == Class::Blank {
+            if is_empty_line(lines, &current) {
+                return true;
+            }
+            blanks = true;
+            continue;
+        }
+        if !blanks {
+            return false;
+        }
+
+        // Closing brackets and quotes may come between the punctuation and the whitespace
+        loop {
+            let line = lines.line(current.y);
+            let grapheme = line.get(current.x).map_or("", String::as_str);
+
+            if !SENTENCE_CLOSERS.contains(&grapheme) {
+                return SENTENCE_ENDS.contains(&grapheme);
+            }
+            match previous(lines, &current) {
+                Some(pos) => current = pos,
+                None => return false,
+            }
+        }
+    }
+    // Only whitespace comes before the start of the document's first sentence
+    true
+}
+
+fn sentence(lines: &Lines, pos: &Location, forward: bool) -> Location {
+    let step = if forward { next } else { previous };
+    let mut current = pos.clone();
+
+    while let Some(pos) = step(lines, &current) {
+        current = pos;
+
+        if is_sentence_start(lines, &current) {
+            break;
+        }
+    }
+    current
+}
+
+/// Finds the first bracket at or after the cursor on its line and returns the location of the
+/// bracket that matches it.
+fn matching_bracket(buffer: &Buffer, pos: &Location) -> Option<Location> {
+    const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+    let text = buffer.text();
+    let line_end = buffer.index_of(&Location::new(buffer.nth_line_len(pos.y), pos.y));
+    let (start, (open, close)) = (buffer.index_of(pos)..line_end).find_map(|i| {
+        PAIRS
+            .iter()
+            .find(|(open, close)| text.char(i) == *open || text.char(i) == *close)
+            .map(|pair| (i, *pair))
+    })?;
+    let mut depth = 0;
+
+    let mut nest = |i: &usize| {
+        match text.char(*i) {
+            c if c == open => depth += 1,
+            c if c == close => depth -= 1,
+            _ => (),
+        }
+        depth == 0
+    };
+
+    let matching = if text.char(start) == open {
+        (start..text.len_chars()).find(&mut nest)
+    } else {
+        (0..=start).rev().find(&mut nest)
+    }?;
+
+    Some(buffer.location_of(matching))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str) -> Buffer {
+        let lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        Buffer::new(&lines, String::new())
+    }
+
+    /// Returns where a motion moves the cursor from a location, as `(x, y)`.
+    fn moved(
+        text: &str,
+        from: (usize, usize),
+        motion: Motion,
+        count: Option<usize>,
+    ) -> Option<(usize, usize)> {
+        motion
+            .apply(&buffer(text), &Location::new(from.0, from.1), count)
+            .map(|pos| (pos.x, pos.y))
+    }
+
+    #[test]
+    fn words() {
+        let text = "foo bar.baz  qux";
+
+        assert_eq!(
+            moved(text, (0, 0), Motion::WordForward(false), None),
+            Some((4, 0))
+        );
+        assert_eq!(
+            moved(text, (4, 0), Motion::WordForward(false), None),
+            Some((7, 0))
+        );
+        assert_eq!(
+            moved(text, (0, 0), Motion::WordForward(false), Some(4)),
+            Some((13, 0))
+        );
+        assert_eq!(
+            moved(text, (4, 0), Motion::WordForward(true), None),
+            Some((13, 0))
+        );
+        assert_eq!(
+            moved(text, (0, 0), Motion::WordEnd(false), None),
+            Some((2, 0))
+        );
+        assert_eq!(
+            moved(text, (2, 0), Motion::WordEnd(false), None),
+            Some((6, 0))
+        );
+        assert_eq!(
+            moved(text, (2, 0), Motion::WordEnd(true), None),
+            Some((10, 0))
+        );
+        assert_eq!(
+            moved(text, (13, 0), Motion::WordBackward(false), None),
+            Some((8, 0))
+        );
+        assert_eq!(
+            moved(text, (13, 0), Motion::WordBackward(true), None),
+            Some((4, 0))
+        );
+        assert_eq!(
+            moved(text, (8, 0), Motion::WordEndBackward(false), None),
+            Some((7, 0))
+        );
+        assert_eq!(
+            moved(text, (13, 0), Motion::WordEndBackward(true), None),
+            Some((10, 0))
+        );
+    }
+
+    #[test]
+    fn words_across_lines() {
+        let text = "one\n\n  two";
+
+        // An empty line is a word of its own
+        assert_eq!(
+            moved(text, (0, 0), Motion::WordForward(false), None),
+            Some((0, 1))
+        );
+        assert_eq!(
+            moved(text, (0, 1), Motion::WordForward(false), None),
+            Some((2, 2))
+        );
+        assert_eq!(
+            moved(text, (2, 2), Motion::WordBackward(false), None),
+            Some((0, 1))
+        );
+        assert_eq!(
+            moved(text, (0, 0), Motion::WordEnd(false), None),
+            Some((2, 0))
+        );
+        assert_eq!(
+            moved(text, (2, 0), Motion::WordEnd(false), None),
+            Some((4, 2))
+        );
+        // The last word stays put at the end of the document
+        assert_eq!(
+            moved(text, (4, 2), Motion::WordEnd(false), None),
+            Some((4, 2))
+        );
+    }
+
+    #[test]
+    fn words_of_wide_characters() {
+        assert_eq!(
+            moved("日本 語", (0, 0), Motion::WordForward(false), None),
+            Some((3, 0))
+        );
+        assert_eq!(
+            moved("e\u{301}té ok", (0, 0), Motion::WordEnd(false), None),
+            Some((2, 0))
+        );
+    }
+
+    #[test]
+    fn lines() {
+        let text = "  indented\nshort\nlast";
+
+        assert_eq!(moved(text, (5, 0), Motion::LineStart, None), Some((0, 0)));
+        assert_eq!(
+            moved(text, (5, 0), Motion::FirstNonBlank, None),
+            Some((2, 0))
+        );
+        assert_eq!(moved(text, (0, 0), Motion::LineEnd, None), Some((10, 0)));
+        assert_eq!(moved(text, (0, 0), Motion::LineEnd, Some(2)), Some((5, 1)));
+        assert_eq!(moved(text, (3, 0), Motion::Down, Some(10)), Some((3, 2)));
+        assert_eq!(moved(text, (3, 2), Motion::Up, None), Some((3, 1)));
+        assert_eq!(moved(text, (3, 1), Motion::Left, Some(10)), Some((0, 1)));
+        assert_eq!(moved(text, (3, 1), Motion::Right, Some(10)), Some((5, 1)));
+    }
+
+    #[test]
+    fn documents() {
+        let text = "  one\ntwo\n  three";
+
+        assert_eq!(moved(text, (1, 1), Motion::FirstLine, None), Some((2, 0)));
+        assert_eq!(moved(text, (1, 1), Motion::LastLine, None), Some((2, 2)));
+        assert_eq!(moved(text, (0, 0), Motion::LastLine, Some(2)), Some((0, 1)));
+        assert_eq!(
+            moved(text, (0, 0), Motion::FirstLine, Some(99)),
+            Some((2, 2))
+        );
+    }
+
+    #[test]
+    fn paragraphs() {
+        let text = "a\nb\n\nc\n\nd";
+
+        assert_eq!(
+            moved(text, (0, 0), Motion::ParagraphForward, None),
+            Some((0, 2))
+        );
+        assert_eq!(
+            moved(text, (0, 0), Motion::ParagraphForward, Some(2)),
+            Some((0, 4))
+        );
+        assert_eq!(
+            moved(text, (0, 4), Motion::ParagraphForward, None),
+            Some((1, 5))
+        );
+        assert_eq!(
+            moved(text, (0, 5), Motion::ParagraphBackward, None),
+            Some((0, 4))
+        );
+        assert_eq!(
+            moved(text, (0, 1), Motion::ParagraphBackward, None),
+            Some((0, 0))
+        );
+    }
+
+    #[test]
+    fn sentences() {
+        let text = "One. (Two three.)  Four!\n\nFive";
+
+        assert_eq!(
+            moved(text, (0, 0), Motion::SentenceForward, None),
+            Some((5, 0))
+        );
+        assert_eq!(
+            moved(text, (5, 0), Motion::SentenceForward, None),
+            Some((19, 0))
+        );
+        assert_eq!(
+            moved(text, (19, 0), Motion::SentenceForward, None),
+            Some((0, 1))
+        );
+        assert_eq!(
+            moved(text, (0, 1), Motion::SentenceForward, None),
+            Some((0, 2))
+        );
+        assert_eq!(
+            moved(text, (21, 0), Motion::SentenceBackward, None),
+            Some((19, 0))
+        );
+        assert_eq!(
+            moved(text, (0, 0), Motion::SentenceForward, Some(3)),
+            Some((0, 1))
+        );
+    }
+
+    #[test]
+    fn brackets() {
+        let text = "if (a[1]) {\n}";
+
+        assert_eq!(
+            moved(text, (0, 0), Motion::MatchingBracket, None),
+            Some((8, 0))
+        );
+        assert_eq!(
+            moved(text, (8, 0), Motion::MatchingBracket, None),
+            Some((3, 0))
+        );
+        assert_eq!(
+            moved(text, (5, 0), Motion::MatchingBracket, None),
+            Some((7, 0))
+        );
+        assert_eq!(
+            moved(text, (9, 0), Motion::MatchingBracket, None),
+            Some((0, 1))
+        );
+        assert_eq!(
+            moved("no brackets", (0, 0), Motion::MatchingBracket, None),
+            None
+        );
+    }
+
+    #[test]
+    fn finds() {
+        let text = "a,b,c,d";
+        let find = |forward, till| {
+            Motion::Find(Find {
+                target: ',',
+                forward,
+                till,
+            })
+        };
+
+        assert_eq!(moved(text, (0, 0), find(true, false), None), Some((1, 0)));
+        assert_eq!(
+            moved(text, (0, 0), find(true, false), Some(2)),
+            Some((3, 0))
+        );
+        assert_eq!(moved(text, (0, 0), find(true, true), None), Some((0, 0)));
+        assert_eq!(moved(text, (6, 0), find(false, false), None), Some((5, 0)));
+        assert_eq!(moved(text, (6, 0), find(false, true), None), Some((6, 0)));
+        assert_eq!(moved(text, (0, 0), find(true, false), Some(4)), None);
+
+        // Repeating `t` steps over the match next to the cursor
+        let till = Find {
+            target: ',',
+            forward: true,
+            till: true,
+        };
+        let pos = till.apply(&buffer(text), &Location::new(0, 0), 1, true);
+        assert_eq!(pos, Some(Location::new(2, 0)));
+    }
+
+    #[test]
+    fn ranges() {
+        let buffer = buffer("foo bar\nbaz");
+        let range = |motion: Motion, from: (usize, usize), to: (usize, usize)| {
+            let range = motion.range(
+                &buffer,
+                &Location::new(from.0, from.1),
+                &Location::new(to.0, to.1),
+            );
+            (
+                (range.start.x, range.start.y),
+                (range.end.x, range.end.y),
+                range.linewise,
+            )
+        };
+
+        assert_eq!(
+            range(Motion::WordForward(false), (0, 0), (4, 0)),
+            ((0, 0), (4, 0), false)
+        );
+        assert_eq!(
+            range(Motion::WordEnd(false), (0, 0), (2, 0)),
+            ((0, 0), (3, 0), false)
+        );
+        assert_eq!(range(Motion::Down, (2, 0), (2, 1)), ((2, 0), (2, 1), true));
+        // `dw` on the last word of a line stops at the end of the line
+        assert_eq!(
+            range(Motion::WordForward(false), (4, 0), (0, 1)),
+            ((4, 0), (7, 0), false)
+        );
+        // Backward motions put the range the right way round
+        assert_eq!(
+            range(Motion::WordBackward(false), (4, 0), (0, 0)),
+            ((0, 0), (4, 0), false)
+        );
+    }
+
+    #[test]
+    fn view_motions_do_not_move_here() {
+        assert_eq!(moved("text", (0, 0), Motion::ScreenDown, None), None);
+        assert_eq!(moved("text", (0, 0), Motion::SearchNext, None), None);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::buffer::{Buffer, Location, Range};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// How many columns `>` and `<` shift lines by.
+const SHIFT_WIDTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Dedent,
+    Lowercase,
+    Uppercase,
+    ToggleCase,
+    Format,
+    /// Joins lines, as `J` does to a visual selection.
+    Join,
+    /// Replaces every character with the given one, as `r` does to a visual selection.
+    Replace(char),
+}
+
+impl Operator {
+    /// Returns the operator for a single key, such as `d`. Case operators start with `g`.
+    pub const fn from_key(key: char) -> Option<Self> {
+        match key {
+            'd' => Some(Self::Delete),
+            'c' => Some(Self::Change),
+            'y' => Some(Self::Yank),
+            '>' => Some(Self::Indent),
+            '<' => Some(Self::Dedent),
+            '=' => Some(Self::Format),
+            _ => None,
+        }
+    }
+
+    /// Returns the operator for the key typed after `g`, such as the `U` in `gU`.
+    pub const fn from_g_key(key: char) -> Option<Self> {
+        match key {
+            'u' => Some(Self::Lowercase),
+            'U' => Some(Self::Uppercase),
+            '~' => Some(Self::ToggleCase),
+            _ => None,
+        }
+    }
+
+    /// Whether the operator always acts on whole lines, whatever it's given.
+    pub const fn is_linewise(self) -> bool {
+        matches!(
+            self,
+            Self::Indent | Self::Dedent | Self::Format | Self::Join
+        )
+    }
+
+    /// Whether the operator modifies the buffer.
+    pub const fn is_edit(self) -> bool {
+        !matches!(self, Self::Yank)
+    }
+
+    /// Whether the operator saves the text it acts on to a register.
+    pub const fn is_saved(self) -> bool {
+        matches!(self, Self::Delete | Self::Change | Self::Yank)
+    }
+
+    /// Applies the operator to a range and returns where the cursor ends up.
+    pub fn apply(self, buffer: &mut Buffer, range: &Range, cursor: &Location) -> Location {
+        let range = if self.is_linewise() {
+            Range::new(range.start.clone(), range.end.clone(), true)
+        } else {
+            range.clone()
+        };
+        let first_line = range.start.y;
+
+        match self {
+            Self::Delete => {
+                buffer.delete(&range);
+
+                if range.linewise {
+                    let y = first_line.min(buffer.len() - 1);
+                    return Location::new(buffer.indentation(y), y);
+                }
+            }
+            Self::Change => {
+                if range.linewise {
+                    // Keep an empty line to type into
+                    let end = Location::new(buffer.nth_line_len(range.end.y), range.end.y);
+                    buffer.delete(&Range::new(Location::new(0, first_line), end, false));
+                    return Location::new(0, first_line);
+                }
+                buffer.delete(&range);
+            }
+            Self::Yank => {
+                if range.linewise {
+                    return Location::new(cursor.x, first_line);
+                }
+            }
+            Self::Indent | Self::Dedent => {
+                for y in first_line..=range.end.y {
+                    if buffer.indentation(y) < buffer.nth_line_len(y) {
+                        let indentation = buffer.indentation_width(y);
+                        buffer.set_indentation(
+                            y,
+                            if self == Self::Indent {
+                                indentation + SHIFT_WIDTH
+                            } else {
+                                indentation.saturating_sub(SHIFT_WIDTH)
+                            },
+                        );
+                    }
+                }
+                return Location::new(buffer.indentation(first_line), first_line);
+            }
+            Self::Lowercase => buffer.transform(&range, str::to_lowercase),
+            Self::Uppercase => buffer.transform(&range, str::to_uppercase),
+            Self::ToggleCase => buffer.transform(&range, toggle_case),
+            Self::Format => {
+                format(buffer, first_line, range.end.y);
+                return Location::new(buffer.indentation(first_line), first_line);
+            }
+            // Joining a single line joins it with the next one
+            Self::Join => return buffer.join(first_line, range.end.y.max(first_line + 1)),
+            Self::Replace(c) => buffer.transform(&range, |text| replace(text, c)),
+        }
+
+        if range.linewise {
+            Location::new(0, first_line)
+        } else {
+            range.start
+        }
+    }
+}
+
+fn toggle_case(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| {
+            if c.is_lowercase() {
+                c.to_uppercase().collect::<Vec<_>>()
+            } else {
+                c.to_lowercase().collect()
+            }
+        })
+        .collect()
+}
+
+/// Replaces every grapheme in some text with a character, apart from line breaks.
+fn replace(text: &str, c: char) -> String {
+    text.graphemes(true)
+        .map(|grapheme| {
+            if matches!(grapheme, "\n" | "\r\n") {
+                grapheme.to_string()
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+/// Re-indents lines by how deeply they are nested in brackets, starting from the indentation of
+/// the nearest non-blank line above them.
+fn format(buffer: &mut Buffer, first: usize, last: usize) {
+    let mut indentation = (0..first)
+        .rev()
+        .find(|y| buffer.indentation(*y) < buffer.nth_line_len(*y))
+        .map_or(0, |y| {
+            let line = buffer
+                .line(y)
+                .map(|line| line.to_string())
+                .unwrap_or_default();
+            let line = line.trim();
+            let (opened, mut closed) = brackets(line);
+
+            // A leading closing bracket is already reflected in the line's own indentation
+            if line.starts_with(['}', ']', ')']) {
+                closed -= 1;
+            }
+            (buffer.indentation_width(y) + opened * SHIFT_WIDTH)
+                .saturating_sub(closed * SHIFT_WIDTH)
+        });
+
+    for y in first..=last {
+        let line = buffer
+            .line(y)
+            .map(|line| line.to_string())
+            .unwrap_or_default();
+        let line = line.trim();
+
+        if line.is_empty() {
+            buffer.set_indentation(y, 0);
+            continue;
+        }
+
+        let (opened, closed) = brackets(line);
+
+        if line.starts_with(['}', ']', ')']) {
+            buffer.set_indentation(y, indentation.saturating_sub(SHIFT_WIDTH));
+        } else {
+            buffer.set_indentation(y, indentation);
+        }
+        indentation = (indentation + opened * SHIFT_WIDTH).saturating_sub(closed * SHIFT_WIDTH);
+    }
+}
+
+/// Counts the opening and closing brackets in a line.
+fn brackets(line: &str) -> (usize, usize) {
+    (
+        line.matches(['{', '[', '(']).count(),
+        line.matches(['}', ']', ')']).count(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str) -> Buffer {
+        let lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        Buffer::new(&lines, String::new())
+    }
+
+    fn lines(buffer: &Buffer) -> Vec<String> {
+        (0..buffer.len())
+            .map(|y| {
+                buffer
+                    .line(y)
+                    .map(|line| line.to_string())
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    fn apply(operator: Operator, buffer: &mut Buffer, first: usize, last: usize) -> Location {
+        let range = Range::new(Location::new(0, first), Location::new(0, last), true);
+        operator.apply(buffer, &range, &Location::new(0, first))
+    }
+
+    #[test]
+    fn shifting() {
+        let mut buffer = buffer("a\n  b\n\nc");
+
+        assert_eq!(
+            apply(Operator::Indent, &mut buffer, 0, 2),
+            Location::new(4, 0)
+        );
+        // Blank lines are left alone
+        assert_eq!(lines(&buffer), ["    a", "      b", "", "c"]);
+
+        apply(Operator::Dedent, &mut buffer, 0, 3);
+        apply(Operator::Dedent, &mut buffer, 0, 3);
+        assert_eq!(lines(&buffer), ["a", "b", "", "c"]);
+    }
+
+    #[test]
+    fn shifting_tabs_by_their_width() {
+        let mut buffer = buffer("\tx\n \ty");
+
+        apply(Operator::Indent, &mut buffer, 0, 0);
+        apply(Operator::Dedent, &mut buffer, 1, 1);
+        assert_eq!(lines(&buffer), [" ".repeat(12) + "x", " ".repeat(4) + "y"]);
+    }
+
+    #[test]
+    fn formatting() {
+        let mut buffer = buffer("fn f() {\nif x {\ny();\n}\n\n   }");
+
+        assert_eq!(
+            apply(Operator::Format, &mut buffer, 1, 5),
+            Location::new(4, 1)
+        );
+        assert_eq!(
+            lines(&buffer),
+            ["fn f() {", "    if x {", "        y();", "    }", "", "}"]
+        );
+    }
+
+    #[test]
+    fn formatting_under_tabs() {
+        let mut buffer = buffer("\tif x {\ny();\n}");
+
+        apply(Operator::Format, &mut buffer, 1, 2);
+        assert_eq!(
+            lines(&buffer),
+            [
+                "\tif x {".to_string(),
+                " ".repeat(12) + "y();",
+                " ".repeat(8) + "}"
+            ]
+        );
+    }
+
+    #[test]
+    fn changing_case() {
+        let mut buffer = buffer("Hello, World");
+        let range = Range::new(Location::new(0, 0), Location::new(5, 0), false);
+
+        Operator::ToggleCase.apply(&mut buffer, &range, &Location::new(0, 0));
+        assert_eq!(lines(&buffer), ["hELLO, World"]);
+        Operator::Uppercase.apply(&mut buffer, &range, &Location::new(0, 0));
+        assert_eq!(lines(&buffer), ["HELLO, World"]);
+    }
+}
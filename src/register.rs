@@ -0,0 +1,440 @@
+use crate::buffer::{grapheme_width, Buffer, Location};
+use crate::clipboard::Clipboard;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use unicode_width::UnicodeWidthStr;
+
+/// How the text in a register is put back into the buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Put inside a line, at the cursor.
+    Characters,
+    /// Put as whole lines, above or below the cursor's line.
+    Lines,
+    /// Put as a rectangle, one line of the text on each line from the cursor down.
+    Block,
+}
+
+/// Yanked or deleted text. Lines and blocks are separated by `\n`, without one at the end.
+#[derive(Debug, Clone)]
+pub struct Register {
+    pub text: String,
+    pub kind: Kind,
+}
+
+impl Register {
+    pub const fn new(text: String, kind: Kind) -> Self {
+        Self { text, kind }
+    }
+
+    /// Puts the text `count` times after the cursor, or before it, and returns where the
+    /// cursor ends up.
+    pub fn put(&self, buffer: &mut Buffer, pos: &Location, after: bool, count: usize) -> Location {
+        match self.kind {
+            Kind::Characters => {
+                let mut start = pos.clone();
+
+                if after && start.x < buffer.nth_line_len(start.y) {
+                    start.right();
+                }
+
+                let text = self.text.repeat(count);
+                let mut end = buffer.insert(&start, &text);
+
+                // The cursor goes on the last character put, unless that was on another line
+                if text.contains('\n') {
+                    start
+                } else {
+                    end.left();
+                    end.max(start)
+                }
+            }
+            Kind::Lines => {
+                let y = if after { pos.y + 1 } else { pos.y };
+                let text = vec![self.text.as_str(); count].join("\n");
+
+                if y < buffer.len() {
+                    buffer.insert(&Location::new(0, y), &(text + "\n"));
+                } else {
+                    let last = buffer.len() - 1;
+                    buffer.insert(
+                        &Location::new(buffer.nth_line_len(last), last),
+                        &("\n".to_string() + &text),
+                    );
+                }
+                Location::new(buffer.indentation(y), y)
+            }
+            Kind::Block => {
+                let after = after && pos.x < buffer.nth_line_len(pos.y);
+                let start = buffer.display_column(pos);
+                let column = start
+                    + buffer
+                        .grapheme(pos)
+                        .filter(|_| after)
+                        .map_or(0, |grapheme| grapheme_width(&grapheme, start));
+                let rows: Vec<&str> = self.text.split('\n').collect();
+                let width = rows.iter().map(|row| row.width()).max().unwrap_or(0);
+
+                for (i, row) in rows.iter().enumerate() {
+                    let y = pos.y + i;
+
+                    if y >= buffer.len() {
+                        let last = buffer.len() - 1;
+                        buffer.insert(&Location::new(buffer.nth_line_len(last), last), "\n");
+                    }
+
+                    let at = buffer.pad_to(y, column);
+
+                    // Keep the block rectangular when there is text after it
+                    let row = if at.x < buffer.nth_line_len(y) {
+                        format!("{row}{}", " ".repeat(width - row.width()))
+                    } else {
+                        (*row).to_string()
+                    };
+                    buffer.insert(&at, &row.repeat(count));
+                }
+                Location::new(buffer.column_to_grapheme(pos.y, column), pos.y)
+            }
+        }
+    }
+}
+
+/// Whether a character names a register after `"`.
+pub fn is_register(name: char) -> bool {
+    name.is_ascii_alphanumeric() || "\"-_.%:+*".contains(name)
+}
+
+/// Whether a register can only be put from, as `".`, `"%` and `":` are kept by the editor.
+pub fn is_read_only(name: char) -> bool {
+    ".%:".contains(name)
+}
+
+/// The registers that text is yanked and deleted into and put from.
+pub struct Registers {
+    /// What `""` holds: whatever was last yanked or deleted.
+    unnamed: Option<Register>,
+    /// `"0` holds the last yank, and `"1` to `"9` the last deletes of a line or more, newest
+    /// first.
+    numbered: [Option<Register>; 10],
+    /// `"a` to `"z`.
+    named: HashMap<char, Register>,
+    /// `"-` holds the last delete within a line.
+    small_delete: Option<Register>,
+    /// `".` holds the text last typed in insert mode.
+    pub inserted: String,
+    /// `":` holds the last command line.
+    pub command: String,
+    clipboard: Box<dyn Clipboard>,
+}
+
+impl Registers {
+    pub fn new(clipboard: Box<dyn Clipboard>) -> Self {
+        Self {
+            unnamed: None,
+            numbered: Default::default(),
+            named: HashMap::new(),
+            small_delete: None,
+            inserted: String::new(),
+            command: String::new(),
+            clipboard,
+        }
+    }
+
+    /// Stores yanked text in the named register, or `"0` if none is named.
+    pub fn yank(&mut self, name: Option<char>, register: Register) -> Result<(), Error> {
+        if name.is_none() {
+            self.numbered[0] = Some(register.clone());
+        }
+        self.store(name, register)
+    }
+
+    /// Stores deleted text in the named register. If none is named, deletes of a line or more
+    /// are shifted into `"1` and smaller ones go in `"-`.
+    pub fn delete(&mut self, name: Option<char>, register: Register) -> Result<(), Error> {
+        if name.is_none() {
+            if register.kind == Kind::Characters && !register.text.contains('\n') {
+                self.small_delete = Some(register.clone());
+            } else {
+                self.numbered[1..].rotate_right(1);
+                self.numbered[1] = Some(register.clone());
+            }
+        }
+        self.store(name, register)
+    }
+
+    fn store(&mut self, name: Option<char>, register: Register) -> Result<(), Error> {
+        let register = match name {
+            Some('_') => return Ok(()),
+            Some(name) if is_read_only(name) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid register name: {name}"),
+                ))
+            }
+            Some(name @ ('+' | '*')) => {
+                let mut text = register.text.clone();
+
+                if register.kind == Kind::Lines {
+                    text.push('\n');
+                }
+                self.clipboard.copy(&text, name == '*')?;
+                register
+            }
+            Some(name) if name.is_ascii_uppercase() => {
+                let name = name.to_ascii_lowercase();
+                let register = match self.named.remove(&name) {
+                    Some(previous) => append(previous, register),
+                    None => register,
+                };
+
+                self.named.insert(name, register.clone());
+                register
+            }
+            Some(name) if name.is_ascii_lowercase() => {
+                self.named.insert(name, register.clone());
+                register
+            }
+            Some(name) if name.is_ascii_digit() => {
+                self.numbered[name as usize - '0' as usize] = Some(register.clone());
+                register
+            }
+            Some('-') => {
+                self.small_delete = Some(register.clone());
+                register
+            }
+            _ => register,
+        };
+
+        self.unnamed = Some(register);
+        Ok(())
+    }
+
+    /// Returns the contents of a register, or of `""` if none is named. `file` is the name of
+    /// the file being edited, for `"%`.
+    pub fn get(&mut self, name: Option<char>, file: &str) -> Result<Option<Register>, Error> {
+        let characters = |text: &str| Some(Register::new(text.to_string(), Kind::Characters));
+
+        Ok(match name {
+            None | Some('"') => self.unnamed.clone(),
+            Some(name @ ('+' | '*')) => {
+                self.clipboard
+                    .paste(name == '*')?
+                    .map(|text| match text.strip_suffix('\n') {
+                        Some(lines) => Register::new(lines.to_string(), Kind::Lines),
+                        None => Register::new(text, Kind::Characters),
+                    })
+            }
+            Some(name) if name.is_ascii_alphabetic() => {
+                self.named.get(&name.to_ascii_lowercase()).cloned()
+            }
+            Some(name) if name.is_ascii_digit() => {
+                self.numbered[name as usize - '0' as usize].clone()
+            }
+            Some('-') => self.small_delete.clone(),
+            Some('.') if !self.inserted.is_empty() => characters(&self.inserted),
+            Some(':') if !self.command.is_empty() => characters(&self.command),
+            Some('%') => characters(file),
+            _ => None,
+        })
+    }
+}
+
+/// Appends text to a register for an uppercase register name. Appending lines to characters,
+/// or the other way round, gives lines.
+fn append(previous: Register, register: Register) -> Register {
+    match (previous.kind, register.kind) {
+        (Kind::Characters, Kind::Characters) => {
+            Register::new(previous.text + &register.text, Kind::Characters)
+        }
+        (Kind::Block, _) => Register::new(previous.text + "\n" + &register.text, Kind::Block),
+        _ => Register::new(previous.text + "\n" + &register.text, Kind::Lines),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clipboard that keeps what is copied to it, with the clipboard and primary selection
+    /// kept apart.
+    #[derive(Default)]
+    struct Fake {
+        copied: [Option<String>; 2],
+    }
+
+    impl Clipboard for Fake {
+        fn copy(&mut self, text: &str, primary: bool) -> Result<(), Error> {
+            self.copied[usize::from(primary)] = Some(text.to_string());
+            Ok(())
+        }
+
+        fn paste(&mut self, primary: bool) -> Result<Option<String>, Error> {
+            Ok(self.copied[usize::from(primary)].clone())
+        }
+    }
+
+    fn registers() -> Registers {
+        Registers::new(Box::new(Fake::default()))
+    }
+
+    fn text(registers: &mut Registers, name: char) -> Option<String> {
+        registers
+            .get(Some(name), "file.txt")
+            .unwrap()
+            .map(|register| register.text)
+    }
+
+    fn characters(text: &str) -> Register {
+        Register::new(text.to_string(), Kind::Characters)
+    }
+
+    fn lines(text: &str) -> Register {
+        Register::new(text.to_string(), Kind::Lines)
+    }
+
+    #[test]
+    fn yanks_and_deletes() {
+        let mut registers = registers();
+        registers.yank(None, characters("yanked")).unwrap();
+        registers.delete(None, characters("word")).unwrap();
+        registers.delete(None, lines("line one")).unwrap();
+        registers.delete(None, lines("line two")).unwrap();
+
+        assert_eq!(text(&mut registers, '"').as_deref(), Some("line two"));
+        assert_eq!(text(&mut registers, '0').as_deref(), Some("yanked"));
+        assert_eq!(text(&mut registers, '1').as_deref(), Some("line two"));
+        assert_eq!(text(&mut registers, '2').as_deref(), Some("line one"));
+        assert_eq!(text(&mut registers, '-').as_deref(), Some("word"));
+        assert_eq!(text(&mut registers, '3'), None);
+    }
+
+    #[test]
+    fn named_registers() {
+        let mut registers = registers();
+        registers.yank(Some('a'), characters("one")).unwrap();
+        registers.yank(Some('A'), characters(" two")).unwrap();
+        assert_eq!(text(&mut registers, 'a').as_deref(), Some("one two"));
+
+        // Appending lines makes the register linewise
+        registers.yank(Some('A'), lines("three")).unwrap();
+        let register = registers.get(Some('a'), "").unwrap().unwrap();
+        assert_eq!(register.text, "one two\nthree");
+        assert_eq!(register.kind, Kind::Lines);
+
+        // Naming a register leaves `"0` alone
+        assert_eq!(text(&mut registers, '0'), None);
+        assert_eq!(text(&mut registers, '"').as_deref(), Some("one two\nthree"));
+    }
+
+    #[test]
+    fn black_hole() {
+        let mut registers = registers();
+        registers.yank(None, characters("kept")).unwrap();
+        registers.delete(Some('_'), characters("gone")).unwrap();
+
+        assert_eq!(text(&mut registers, '"').as_deref(), Some("kept"));
+        assert_eq!(text(&mut registers, '_'), None);
+    }
+
+    #[test]
+    fn clipboard() {
+        let mut registers = registers();
+        registers.yank(Some('+'), lines("a line")).unwrap();
+        registers.yank(Some('*'), characters("word")).unwrap();
+
+        let register = registers.get(Some('+'), "").unwrap().unwrap();
+        assert_eq!(register.text, "a line");
+        assert_eq!(register.kind, Kind::Lines);
+        assert_eq!(text(&mut registers, '*').as_deref(), Some("word"));
+    }
+
+    #[test]
+    fn read_only_registers() {
+        let mut registers = registers();
+        registers.inserted = String::from("typed");
+        registers.command = String::from("w");
+
+        assert_eq!(text(&mut registers, '.').as_deref(), Some("typed"));
+        assert_eq!(text(&mut registers, ':').as_deref(), Some("w"));
+        assert_eq!(text(&mut registers, '%').as_deref(), Some("file.txt"));
+
+        for name in ['.', ':', '%'] {
+            let err = registers
+                .yank(Some(name), characters("yanked"))
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+            assert!(registers.delete(Some(name), lines("line")).is_err());
+        }
+        assert_eq!(text(&mut registers, '.').as_deref(), Some("typed"));
+        assert_eq!(text(&mut registers, '"'), None);
+    }
+
+    fn put(
+        text: &str,
+        register: &Register,
+        pos: (usize, usize),
+        after: bool,
+        count: usize,
+    ) -> (String, (usize, usize)) {
+        let lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        let mut buffer = Buffer::new(&lines, String::new());
+        let cursor = register.put(&mut buffer, &Location::new(pos.0, pos.1), after, count);
+        (buffer.text().to_string(), (cursor.x, cursor.y))
+    }
+
+    #[test]
+    fn put_characters() {
+        assert_eq!(
+            put("abc", &characters("XY"), (0, 0), true, 1),
+            (String::from("aXYbc"), (2, 0))
+        );
+        assert_eq!(
+            put("abc", &characters("XY"), (0, 0), false, 2),
+            (String::from("XYXYabc"), (3, 0))
+        );
+        assert_eq!(
+            put("", &characters("XY"), (0, 0), true, 1),
+            (String::from("XY"), (1, 0))
+        );
+        assert_eq!(
+            put("abc", &characters("X\nY"), (0, 0), true, 1),
+            (String::from("aX\nYbc"), (1, 0))
+        );
+    }
+
+    #[test]
+    fn put_lines() {
+        assert_eq!(
+            put("one\ntwo", &lines("  new"), (1, 0), true, 1),
+            (String::from("one\n  new\ntwo"), (2, 1))
+        );
+        assert_eq!(
+            put("one\ntwo", &lines("new"), (1, 0), false, 1),
+            (String::from("new\none\ntwo"), (0, 0))
+        );
+        assert_eq!(
+            put("one", &lines("new"), (0, 0), true, 2),
+            (String::from("one\nnew\nnew"), (0, 1))
+        );
+    }
+
+    #[test]
+    fn put_blocks() {
+        let block = Register::new(String::from("ab\nc"), Kind::Block);
+
+        // Short rows are padded when there is text after them, and lines added or padded to
+        // reach the block's column
+        assert_eq!(
+            put("1234\n12\nxyz", &block, (1, 0), true, 1),
+            (String::from("12ab34\n12c\nxyz"), (2, 0))
+        );
+        assert_eq!(
+            put("1234\n1234", &block, (1, 0), false, 1),
+            (String::from("1ab234\n1c 234"), (1, 0))
+        );
+        assert_eq!(
+            put("1234", &block, (3, 0), true, 1),
+            (String::from("1234ab\n    c"), (4, 0))
+        );
+    }
+}
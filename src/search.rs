@@ -0,0 +1,326 @@
+use crate::buffer::{Buffer, Location};
+use crate::motion::{class_of, Class};
+use regex::{Regex, RegexBuilder};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A pattern searched for with `/`, `?`, `*` or `#`.
+#[derive(Debug, Clone)]
+pub struct Search {
+    pub pattern: String,
+    /// Whether the search goes down the buffer, as `/` and `*` do.
+    pub forward: bool,
+    pub regex: Regex,
+}
+
+impl Search {
+    /// Compiles a Vim style pattern, or returns the message to show if it isn't valid.
+    pub fn new(pattern: String, forward: bool) -> Result<Self, String> {
+        Ok(Self {
+            regex: compile(&pattern)?,
+            pattern,
+            forward,
+        })
+    }
+
+    /// Returns a search for the keyword under or after the cursor as a whole word, and where
+    /// that keyword starts, or `None` if there isn't one on its line.
+    pub fn word(buffer: &Buffer, pos: &Location, forward: bool) -> Option<(Self, Location)> {
+        let graphemes = buffer.graphemes(pos.y);
+        let is_keyword = |x: &usize| class_of(&graphemes[*x]) == Class::Keyword;
+
+        // The cursor can be just past the end of the line, which counts as the last character
+        let x = pos.x.min(graphemes.len().saturating_sub(1));
+        let mut start = (x..graphemes.len()).find(is_keyword)?;
+        let end = (start..graphemes.len())
+            .find(|x| !is_keyword(x))
+            .unwrap_or(graphemes.len());
+
+        while start > 0 && is_keyword(&(start - 1)) {
+            start -= 1;
+        }
+
+        let pattern = format!("\\<{}\\>", graphemes[start..end].concat());
+        let search = Self::new(pattern, forward).ok()?;
+        Some((search, Location::new(start, pos.y)))
+    }
+
+    /// Returns the same search in the other direction, for `N`.
+    pub fn reversed(&self) -> Self {
+        Self {
+            forward: !self.forward,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the matches on the nth line as ranges of grapheme indices.
+    pub fn matches(&self, buffer: &Buffer, n: usize) -> Vec<std::ops::Range<usize>> {
+        let line = buffer
+            .line(n)
+            .map(|line| line.to_string())
+            .unwrap_or_default();
+
+        // The byte offset each grapheme starts at, and the end of the line
+        let mut starts: Vec<usize> = line.grapheme_indices(true).map(|(i, _)| i).collect();
+        starts.push(line.len());
+
+        self.regex
+            .find_iter(&line)
+            .map(|found| {
+                let start = starts.partition_point(|i| *i <= found.start()) - 1;
+                let end = starts.partition_point(|i| *i < found.end());
+                start..end.max(start)
+            })
+            .collect()
+    }
+
+    /// Returns the `count`th match from `pos` in the search's direction, wrapping around the
+    /// ends of the buffer, and whether it had to wrap. Returns `None` if nothing matches.
+    pub fn next(&self, buffer: &Buffer, pos: &Location, count: usize) -> Option<(Location, bool)> {
+        let mut pos = pos.clone();
+        let mut wrapped = false;
+
+        for _ in 0..count {
+            let (next, wrap) = self.step(buffer, &pos)?;
+            pos = next;
+            wrapped |= wrap;
+        }
+        Some((pos, wrapped))
+    }
+
+    fn step(&self, buffer: &Buffer, pos: &Location) -> Option<(Location, bool)> {
+        let len = buffer.len();
+
+        // The cursor's line is searched both first and last, for matches on either side of it
+        for i in 0..=len {
+            let (y, wrapped) = if self.forward {
+                ((pos.y + i) % len, pos.y + i >= len)
+            } else {
+                ((pos.y + len - i) % len, i > pos.y)
+            };
+            let mut starts = self.matches(buffer, y).into_iter().map(|found| found.start);
+
+            let x = if self.forward {
+                starts.find(|x| i > 0 || *x > pos.x)
+            } else {
+                starts.rfind(|x| i > 0 || *x < pos.x)
+            };
+
+            if let Some(x) = x {
+                return Some((Location::new(x, y), wrapped));
+            }
+        }
+        None
+    }
+}
+
+/// The patterns searched for, for Up and Down to recall while typing a new one.
+#[derive(Default)]
+pub struct SearchHistory {
+    patterns: Vec<String>,
+    /// The pattern last recalled, or the length of `patterns` when none is.
+    position: usize,
+}
+
+impl SearchHistory {
+    /// Adds a pattern, moving it to the end if it was searched for before.
+    pub fn add(&mut self, pattern: &str) {
+        self.patterns.retain(|previous| previous != pattern);
+        self.patterns.push(pattern.to_string());
+        self.reset();
+    }
+
+    /// Stops recalling patterns, so the next `previous` gives the newest one.
+    pub fn reset(&mut self) {
+        self.position = self.patterns.len();
+    }
+
+    /// Returns the pattern before the one last recalled, or `None` at the oldest one.
+    pub fn previous(&mut self) -> Option<&str> {
+        self.position = self.position.checked_sub(1)?;
+        Some(&self.patterns[self.position])
+    }
+
+    /// Returns the pattern after the one last recalled, or nothing after the newest one.
+    pub fn next(&mut self) -> &str {
+        self.position = (self.position + 1).min(self.patterns.len());
+        self.patterns.get(self.position).map_or("", String::as_str)
+    }
+}
+
+/// Compiles a Vim style pattern into a regex. As in Vim's default "magic" mode, groups,
+/// alternation and most repetition need a backslash: `\(`, `\)`, `\|`, `\+`, `\?`,
+/// `\{n,m}`, while `.`, `*`, `[]`, `^` and `$` don't. `\<` and `\>` match the edges of
+/// words. Case is ignored unless the pattern has an uppercase letter, which `\c` and `\C`
+/// override.
+fn compile(pattern: &str) -> Result<Regex, String> {
+    let mut regex = String::new();
+    let mut ignore_case = None;
+    let mut uppercase = false;
+    let mut in_braces = false;
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('(' | ')' | '|' | '+' | '?')) => regex.push(c),
+                Some('=') => regex.push('?'),
+                Some('{') => {
+                    regex.push('{');
+                    in_braces = true;
+                }
+                Some('<' | '>') => regex.push_str("\\b"),
+                Some('c') => ignore_case = Some(true),
+                Some('C') => ignore_case = Some(false),
+                Some(c @ ('d' | 'D' | 'w' | 'W' | 's' | 'S' | 'n' | 't')) => {
+                    regex.push('\\');
+                    regex.push(c);
+                }
+                Some(c) => regex.push_str(&regex::escape(&c.to_string())),
+                None => regex.push_str("\\\\"),
+            },
+            '}' if in_braces => {
+                regex.push('}');
+                in_braces = false;
+            }
+            '(' | ')' | '|' | '+' | '?' | '{' | '}' => {
+                regex.push_str(&regex::escape(&c.to_string()))
+            }
+            _ => {
+                uppercase |= c.is_uppercase();
+                regex.push(c);
+            }
+        }
+    }
+
+    RegexBuilder::new(&regex)
+        .case_insensitive(ignore_case.unwrap_or(!uppercase))
+        .build()
+        .map_err(|_| format!("Invalid pattern: {pattern}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str) -> Buffer {
+        let lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        Buffer::new(&lines, String::new())
+    }
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        compile(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn magic_patterns() {
+        assert!(is_match("a\\+b", "aaab"));
+        assert!(!is_match("a\\+b", "b"));
+        assert!(is_match("a+b", "a+b"));
+        assert!(is_match("\\(ab\\)\\{2}", "abab"));
+        assert!(is_match("(x)", "(x)"));
+        assert!(is_match("cat\\|dog", "hotdog"));
+        assert!(is_match("colou\\=r", "color"));
+        assert!(is_match("^\\d\\+$", "123"));
+        assert!(is_match("a.c", "abc"));
+        assert!(is_match("\\.", "."));
+        assert!(!is_match("\\.", "a"));
+        assert!(is_match("\\<is\\>", "this is it"));
+        assert!(!is_match("\\<is\\>", "this"));
+        assert_eq!(
+            Search::new(String::from("a\\{"), true).err(),
+            Some(String::from("Invalid pattern: a\\{"))
+        );
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(is_match("word", "WORD"));
+        assert!(!is_match("Word", "word"));
+        assert!(is_match("Word\\c", "word"));
+        assert!(!is_match("\\Cword", "WORD"));
+    }
+
+    #[test]
+    fn next_matches() {
+        let buffer = buffer("one two\ntwo\nthree two");
+        let forward = Search::new(String::from("two"), true).unwrap();
+        let next = |search: &Search, x, y, count| {
+            search
+                .next(&buffer, &Location::new(x, y), count)
+                .map(|(pos, wrapped)| ((pos.x, pos.y), wrapped))
+        };
+
+        assert_eq!(next(&forward, 0, 0, 1), Some(((4, 0), false)));
+        assert_eq!(next(&forward, 4, 0, 1), Some(((0, 1), false)));
+        assert_eq!(next(&forward, 4, 0, 2), Some(((6, 2), false)));
+        // Past the last match the search wraps around to the top
+        assert_eq!(next(&forward, 6, 2, 1), Some(((4, 0), true)));
+
+        let backward = forward.reversed();
+        assert_eq!(next(&backward, 6, 2, 1), Some(((0, 1), false)));
+        assert_eq!(next(&backward, 4, 0, 1), Some(((6, 2), true)));
+
+        let missing = Search::new(String::from("four"), true).unwrap();
+        assert_eq!(next(&missing, 0, 0, 1), None);
+    }
+
+    #[test]
+    fn only_match_in_the_buffer() {
+        let buffer = buffer("one\ntwo");
+        let search = Search::new(String::from("two"), true).unwrap();
+
+        // Searching from the match itself comes back round to it
+        let (pos, wrapped) = search.next(&buffer, &Location::new(0, 1), 1).unwrap();
+        assert_eq!(pos, Location::new(0, 1));
+        assert!(wrapped);
+    }
+
+    #[test]
+    fn matches_in_graphemes() {
+        let buffer = buffer("é日本 日本");
+        let search = Search::new(String::from("日本"), true).unwrap();
+
+        assert_eq!(search.matches(&buffer, 0), [1..3, 4..6]);
+        // An empty match is an empty range
+        let empty = Search::new(String::from("^"), true).unwrap();
+        assert_eq!(
+            empty.matches(&buffer, 0),
+            [std::ops::Range { start: 0, end: 0 }]
+        );
+    }
+
+    #[test]
+    fn words_under_the_cursor() {
+        let buffer = buffer("let foo_bar = foo;");
+
+        let (search, start) = Search::word(&buffer, &Location::new(6, 0), true).unwrap();
+        assert_eq!(search.pattern, "\\<foo_bar\\>");
+        assert_eq!(start, Location::new(4, 0));
+
+        // Off a word, the next keyword on the line is taken
+        let (search, start) = Search::word(&buffer, &Location::new(11, 0), false).unwrap();
+        assert_eq!(search.pattern, "\\<foo\\>");
+        assert_eq!(start, Location::new(14, 0));
+        assert!(!search.forward);
+
+        assert!(Search::word(&buffer, &Location::new(17, 0), true).is_none());
+    }
+
+    #[test]
+    fn history() {
+        let mut history = SearchHistory::default();
+        assert_eq!(history.previous(), None);
+
+        history.add("one");
+        history.add("two");
+        history.add("one");
+        assert_eq!(history.previous(), Some("one"));
+        assert_eq!(history.previous(), Some("two"));
+        assert_eq!(history.previous(), None);
+        assert_eq!(history.next(), "one");
+        assert_eq!(history.next(), "");
+
+        history.reset();
+        assert_eq!(history.previous(), Some("one"));
+    }
+}
@@ -0,0 +1,188 @@
+use crate::buffer::{grapheme_width, Buffer, Location, Range};
+
+/// What a visual mode selection covers between its anchor and the cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    /// `v` selects every character from one end to the other.
+    Characters,
+    /// `V` selects whole lines.
+    Lines,
+    /// Ctrl-V selects a rectangle of display columns on each line.
+    Block,
+}
+
+/// The text selected in visual mode, from where the selection was started to the cursor.
+pub struct Selection {
+    pub anchor: Location,
+    pub cursor: Location,
+    pub shape: Shape,
+}
+
+impl Selection {
+    /// Returns the first and last lines the selection is on.
+    pub fn lines(&self) -> (usize, usize) {
+        (
+            self.anchor.y.min(self.cursor.y),
+            self.anchor.y.max(self.cursor.y),
+        )
+    }
+
+    /// Returns the range for an operator to act on. Blocks are treated as whole lines, so
+    /// operators that act on each row of a block use `rows` instead.
+    pub fn range(&self, buffer: &Buffer) -> Range {
+        let (first, last) = self.lines();
+
+        if self.shape != Shape::Characters {
+            return Range::new(Location::new(0, first), Location::new(0, last), true);
+        }
+
+        let start = self.anchor.clone().min(self.cursor.clone());
+        let mut end = self.anchor.clone().max(self.cursor.clone());
+
+        // Selecting past the end of a line selects its line break too
+        if end.x >= buffer.nth_line_len(end.y) && end.y + 1 < buffer.len() {
+            end = Location::new(0, end.y + 1);
+        } else {
+            end.right();
+        }
+        Range::new(start, end, false)
+    }
+
+    /// Returns the display columns of the left edge of a block and just past its right edge.
+    pub fn columns(&self, buffer: &Buffer) -> (usize, usize) {
+        let right_edge = |pos: &Location| {
+            let column = buffer.display_column(pos);
+            column
+                + buffer
+                    .grapheme(pos)
+                    .map_or(1, |g| grapheme_width(&g, column).max(1))
+        };
+
+        (
+            buffer
+                .display_column(&self.anchor)
+                .min(buffer.display_column(&self.cursor)),
+            right_edge(&self.anchor).max(right_edge(&self.cursor)),
+        )
+    }
+
+    /// Returns the graphemes selected on the nth line as a range of indices, or `None` if the
+    /// line isn't part of the selection.
+    pub fn span(&self, buffer: &Buffer, n: usize) -> Option<std::ops::Range<usize>> {
+        let (first, last) = self.lines();
+
+        if n < first || n > last {
+            return None;
+        }
+
+        let len = buffer.nth_line_len(n);
+        let range = self.range(buffer);
+
+        Some(match self.shape {
+            Shape::Characters => {
+                let start = if n == range.start.y { range.start.x } else { 0 };
+                let end = if n == range.end.y { range.end.x } else { len };
+                start.min(len)..end.min(len)
+            }
+            Shape::Lines => 0..len,
+            Shape::Block => {
+                let (left, right) = self.columns(buffer);
+                let start = buffer.column_to_grapheme(n, left);
+                let end = (buffer.column_to_grapheme(n, right - 1) + 1).min(len);
+                start..end.max(start)
+            }
+        })
+    }
+
+    /// Returns the part of each line inside a block selection, from the top down.
+    pub fn rows(&self, buffer: &Buffer) -> Vec<Range> {
+        let (first, last) = self.lines();
+
+        (first..=last)
+            .filter_map(|y| {
+                let span = self.span(buffer, y)?;
+                Some(Range::new(
+                    Location::new(span.start, y),
+                    Location::new(span.end, y),
+                    false,
+                ))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str) -> Buffer {
+        let lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        Buffer::new(&lines, String::new())
+    }
+
+    fn selection(anchor: (usize, usize), cursor: (usize, usize), shape: Shape) -> Selection {
+        Selection {
+            anchor: Location::new(anchor.0, anchor.1),
+            cursor: Location::new(cursor.0, cursor.1),
+            shape,
+        }
+    }
+
+    #[test]
+    fn characters() {
+        let buffer = buffer("one two\nthree");
+
+        // Both ends are included, whichever way round they are
+        let forward = selection((4, 0), (2, 1), Shape::Characters);
+        let backward = selection((2, 1), (4, 0), Shape::Characters);
+        assert_eq!(buffer.slice(&forward.range(&buffer)), "two\nthr");
+        assert_eq!(buffer.slice(&backward.range(&buffer)), "two\nthr");
+        assert_eq!(forward.span(&buffer, 0), Some(4..7));
+        assert_eq!(forward.span(&buffer, 1), Some(0..3));
+        assert_eq!(forward.span(&buffer, 2), None);
+
+        // Past the end of a line takes its line break
+        let past_end = selection((4, 0), (7, 0), Shape::Characters);
+        assert_eq!(buffer.slice(&past_end.range(&buffer)), "two\n");
+    }
+
+    #[test]
+    fn lines() {
+        let buffer = buffer("one\ntwo\nthree");
+        let selection = selection((2, 2), (1, 1), Shape::Lines);
+
+        assert_eq!(selection.lines(), (1, 2));
+        assert!(selection.range(&buffer).linewise);
+        assert_eq!(buffer.slice(&selection.range(&buffer)), "two\nthree");
+        assert_eq!(selection.span(&buffer, 2), Some(0..5));
+    }
+
+    #[test]
+    fn blocks() {
+        let buffer = buffer("abcdef\nx\n日本語");
+        let selection = selection((1, 0), (1, 2), Shape::Block);
+
+        // The wide character under the cursor widens the block to both its columns
+        assert_eq!(selection.columns(&buffer), (1, 4));
+        assert_eq!(selection.span(&buffer, 0), Some(1..4));
+        assert_eq!(selection.span(&buffer, 1), Some(1..1));
+        assert_eq!(selection.span(&buffer, 2), Some(0..2));
+
+        let rows: Vec<String> = selection
+            .rows(&buffer)
+            .iter()
+            .map(|row| buffer.slice(row))
+            .collect();
+        assert_eq!(rows, ["bcd", "", "日本"]);
+    }
+
+    #[test]
+    fn blocks_with_tabs() {
+        let buffer = buffer("\tx\nabcdefghij");
+        let selection = selection((0, 0), (9, 1), Shape::Block);
+
+        assert_eq!(selection.columns(&buffer), (0, 10));
+        assert_eq!(selection.span(&buffer, 0), Some(0..2));
+        assert_eq!(selection.span(&buffer, 1), Some(0..10));
+    }
+}
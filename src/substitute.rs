@@ -0,0 +1,271 @@
+use crate::buffer::{Buffer, Location, Range};
+use crate::search::Search;
+
+/// A `:s` command being carried out one match at a time, so each can be confirmed.
+pub struct Substitution {
+    pub search: Search,
+    /// The replacement as it was typed, to show when confirming.
+    pub replacement: String,
+    /// The replacement in the syntax `Captures::expand` takes.
+    expansion: String,
+    global: bool,
+    /// The line being searched and the char offset in it to search from.
+    line: usize,
+    from: usize,
+    /// The last line of the range, which moves as replacements add lines.
+    last: usize,
+    /// The match found by `next_match` that is waiting to be replaced or skipped, as a range
+    /// of char offsets into its line.
+    found: Option<std::ops::Range<usize>>,
+    /// How many matches have been replaced, and on how many lines.
+    pub replaced: usize,
+    pub lines: usize,
+    /// The line of the last replacement.
+    pub last_line: Option<usize>,
+}
+
+impl Substitution {
+    pub fn new(search: Search, replacement: &str, global: bool, first: usize, last: usize) -> Self {
+        Self {
+            search,
+            replacement: replacement.to_string(),
+            expansion: expansion(replacement),
+            global,
+            line: first,
+            from: 0,
+            last,
+            found: None,
+            replaced: 0,
+            lines: 0,
+            last_line: None,
+        }
+    }
+
+    /// Finds the next match to replace, returning where it starts, or `None` when there are no
+    /// more in the range.
+    pub fn next_match(&mut self, buffer: &Buffer) -> Option<Location> {
+        while self.line <= self.last && self.line < buffer.len() {
+            let text = line(buffer, self.line);
+            let found = byte_offset(&text, self.from)
+                .and_then(|start| self.search.regex.find_at(&text, start));
+
+            if let Some(found) = found {
+                let start = text[..found.start()].chars().count();
+                let end = start + found.as_str().chars().count();
+                let index = buffer.text().line_to_char(self.line) + start;
+
+                self.found = Some(start..end);
+                return Some(buffer.location_of(index));
+            }
+            self.line += 1;
+            self.from = 0;
+        }
+        None
+    }
+
+    /// Replaces the match found by `next_match`.
+    pub fn replace(&mut self, buffer: &mut Buffer) {
+        let Some(found) = self.found.take() else {
+            return;
+        };
+
+        let text = line(buffer, self.line);
+        let mut replacement = String::new();
+
+        if let Some(captures) = byte_offset(&text, found.start)
+            .and_then(|start| self.search.regex.captures_at(&text, start))
+        {
+            captures.expand(&self.expansion, &mut replacement);
+        }
+
+        let line_start = buffer.text().line_to_char(self.line);
+        let range = Range::new(
+            buffer.location_of(line_start + found.start),
+            buffer.location_of(line_start + found.end),
+            false,
+        );
+        buffer.transform(&range, |_| replacement.clone());
+
+        if self.last_line != Some(self.line) {
+            self.lines += 1;
+        }
+        self.replaced += 1;
+        self.last_line = Some(self.line);
+
+        // Carry on after the replacement, which may have split the line
+        let end = line_start + found.start + replacement.chars().count();
+        let added = replacement.matches('\n').count();
+        self.line += added;
+        self.last += added;
+        self.from = end - buffer.text().line_to_char(self.line);
+        self.advance(found.is_empty());
+    }
+
+    /// Leaves the match found by `next_match` as it is.
+    pub fn skip(&mut self) {
+        if let Some(found) = self.found.take() {
+            self.from = found.end;
+            self.advance(found.is_empty());
+        }
+    }
+
+    /// Moves on from a match, to the next line unless replacing every match on a line. An
+    /// empty match is stepped over so it isn't found again.
+    fn advance(&mut self, empty: bool) {
+        if !self.global {
+            self.line += 1;
+            self.from = 0;
+        } else if empty {
+            self.from += 1;
+        }
+    }
+}
+
+fn line(buffer: &Buffer, n: usize) -> String {
+    buffer
+        .line(n)
+        .map(|line| line.to_string())
+        .unwrap_or_default()
+}
+
+/// Converts a char offset into a line into a byte offset, or `None` if it's past the end.
+fn byte_offset(text: &str, chars: usize) -> Option<usize> {
+    text.char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .nth(chars)
+}
+
+/// Puts the previous replacement in place of each `~` in a replacement, as Vim does. An
+/// escaped `\~` is left for `expansion` to turn into a `~`.
+pub fn with_previous(replacement: &str, previous: &str) -> String {
+    let mut expanded = String::new();
+    let mut chars = replacement.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                expanded.push(c);
+                expanded.extend(chars.next());
+            }
+            '~' => expanded.push_str(previous),
+            c => expanded.push(c),
+        }
+    }
+    expanded
+}
+
+/// Converts a Vim style replacement into the syntax `Captures::expand` takes. `&` and `\0` to
+/// `\9` stand for the match and its groups, `\r` and `\n` for a line break, and any other
+/// escaped character for itself.
+fn expansion(replacement: &str) -> String {
+    let mut expansion = String::new();
+    let mut chars = replacement.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => expansion.push_str(&format!("${{{digit}}}")),
+                Some('r' | 'n') => expansion.push('\n'),
+                Some('t') => expansion.push('\t'),
+                Some('$') => expansion.push_str("$$"),
+                Some(c) => expansion.push(c),
+                None => expansion.push('\\'),
+            },
+            '&' => expansion.push_str("${0}"),
+            '$' => expansion.push_str("$$"),
+            c => expansion.push(c),
+        }
+    }
+    expansion
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replaces every match in the lines, returning the text that results.
+    fn substitute(lines: &[&str], pattern: &str, replacement: &str, global: bool) -> String {
+        let lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        let mut buffer = Buffer::new(&lines, String::new());
+        let search = Search::new(pattern.to_string(), true).unwrap();
+        let mut substitution = Substitution::new(search, replacement, global, 0, lines.len() - 1);
+
+        while substitution.next_match(&buffer).is_some() {
+            substitution.replace(&mut buffer);
+        }
+        buffer.text().to_string()
+    }
+
+    #[test]
+    fn first_or_every_match() {
+        assert_eq!(substitute(&["a a", "a"], "a", "b", false), "b a\nb");
+        assert_eq!(substitute(&["a a", "a"], "a", "b", true), "b b\nb");
+        assert_eq!(substitute(&["abc"], "x", "y", true), "abc");
+    }
+
+    #[test]
+    fn capture_groups() {
+        assert_eq!(
+            substitute(
+                &["John Smith"],
+                "\\(\\w\\+\\) \\(\\w\\+\\)",
+                "\\2, \\1",
+                false
+            ),
+            "Smith, John"
+        );
+        assert_eq!(
+            substitute(&["key=value"], "\\(\\w\\+\\)=", "\\0\\1", false),
+            "key=keyvalue"
+        );
+    }
+
+    #[test]
+    fn whole_match() {
+        assert_eq!(
+            substitute(&["cat dog"], "\\w\\+", "<&>", true),
+            "<cat> <dog>"
+        );
+        assert_eq!(substitute(&["cat"], "cat", "\\&", false), "&");
+    }
+
+    #[test]
+    fn special_characters() {
+        assert_eq!(substitute(&["a,b,c"], ",", "\\n", true), "a\nb\nc");
+        assert_eq!(substitute(&["a,b"], ",", "\\r", false), "a\nb");
+        assert_eq!(substitute(&["a b"], " ", "\\t", false), "a\tb");
+        assert_eq!(substitute(&["price"], "price", "$5 \\$1", false), "$5 $1");
+        assert_eq!(substitute(&["a"], "a", "x\\\\y", false), "x\\y");
+    }
+
+    #[test]
+    fn split_lines_are_searched_past() {
+        // The range grows with the lines the replacement adds, so the last line is still in it
+        assert_eq!(
+            substitute(&["a;a", "a"], "a", "b\\nb", true),
+            "b\nb;b\nb\nb\nb"
+        );
+    }
+
+    #[test]
+    fn empty_matches() {
+        assert_eq!(substitute(&["abc"], "^", "> ", false), "> abc");
+        assert_eq!(substitute(&["ab"], "x*", "-", true), "-a-b-");
+    }
+
+    #[test]
+    fn previous_replacement() {
+        assert_eq!(with_previous("~", "abc"), "abc");
+        assert_eq!(with_previous("x~y~", "ab"), "xabyab");
+        assert_eq!(with_previous("\\~", "ab"), "\\~");
+        assert_eq!(with_previous("\\\\~", "ab"), "\\\\ab");
+        assert_eq!(with_previous("~", ""), "");
+
+        // An escaped `~` is a `~` once expanded
+        assert_eq!(
+            substitute(&["a"], "a", &with_previous("\\~", "b"), false),
+            "~"
+        );
+    }
+}
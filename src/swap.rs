@@ -0,0 +1,215 @@
+use crate::undo_file::{state_dir, state_path};
+use ropey::Rope;
+use std::io::{Error, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// What a swap file starts with, followed by the id of the process that wrote it.
+const MAGIC: &str = "beditor swap 1\n";
+
+/// Returns where the swap file for a file goes.
+pub fn path(file: &str) -> Option<PathBuf> {
+    state_path("swap", file)
+}
+
+/// Returns a swap file for a buffer with no file of its own, numbered past those already
+/// there, which belong to other buffers or have text to recover. Swap files for files are
+/// named after their full path, so never clash with these.
+pub fn unnamed_path() -> Option<PathBuf> {
+    let dir = state_dir("swap")?;
+
+    (1..)
+        .map(|n| dir.join(format!("unnamed-{n}")))
+        .find(|path| !path.exists())
+}
+
+/// Writes the text being edited to a swap file, so it can be recovered if the editor dies
+/// before it is saved.
+pub fn write(path: &Path, text: &Rope) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(file, "{MAGIC}{}", std::process::id())?;
+    text.write_to(&mut file)?;
+    file.flush()
+}
+
+/// A swap file left behind by an editor that died, or that is still editing the file.
+pub struct Found {
+    pub path: PathBuf,
+    pub text: String,
+    /// The id of the process that wrote it, if that process is still running.
+    pub running: Option<u32>,
+}
+
+/// Looks for a swap file for a file. One left by an editor that died with the same text as
+/// the file is deleted, as there is nothing in it to recover.
+pub fn find(file: &str, text: &Rope) -> Option<Found> {
+    let path = path(file)?;
+    let contents = std::fs::read_to_string(&path).ok()?;
+    let (pid, text_in_swap) = contents.strip_prefix(MAGIC)?.split_once('\n')?;
+    let running = pid.parse().ok().filter(|pid| is_running(*pid));
+
+    if running.is_none() && *text == text_in_swap {
+        std::fs::remove_file(&path).ok();
+        return None;
+    }
+
+    Some(Found {
+        text: text_in_swap.to_string(),
+        path,
+        running,
+    })
+}
+
+/// Whether another process with the given id is running. One that belongs to another user
+/// counts too, even though this one isn't allowed to signal it.
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return false;
+    }
+
+    // Anything but being told there is no such process means it may still be running
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .env("LC_ALL", "C")
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_or(true, |output| {
+            output.status.success()
+                || !String::from_utf8_lossy(&output.stderr).contains("No such process")
+        })
+}
+
+/// There is no telling whether other processes are running here, so the one that wrote a
+/// swap file is assumed to be, which keeps its swap file from being deleted under it.
+#[cfg(not(unix))]
+fn is_running(pid: u32) -> bool {
+    pid != std::process::id()
+}
+
+/// Lists the lines that differ between the file and a swap file, numbered by their line in
+/// the file, with `-` for lines only in the file and `+` for lines only in the swap file.
+pub fn diff(file: &str, swap: &str) -> Vec<String> {
+    let old: Vec<&str> = file.split('\n').collect();
+    let new: Vec<&str> = swap.split('\n').collect();
+
+    // Only the part between the lines the two start and end with is compared
+    let start = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let end = old[start..]
+        .iter()
+        .rev()
+        .zip(new[start..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_part, new_part) = (&old[start..old.len() - end], &new[start..new.len() - end]);
+
+    // The length of the longest common subsequence of the lines after each pair of lines,
+    // which is too big to work out for parts that are very different, so those are listed
+    // as removed and then added
+    let width = new_part.len() + 1;
+    let size = (old_part.len() + 1).saturating_mul(width);
+
+    if size > 1 << 22 {
+        let removed = old_part
+            .iter()
+            .enumerate()
+            .map(|(i, text)| format!("- {}: {text}", start + i + 1));
+        let added = new_part
+            .iter()
+            .map(|text| format!("+ {}: {text}", start + old_part.len() + 1));
+
+        return removed.chain(added).collect();
+    }
+
+    let mut common = vec![0; size];
+
+    for i in (0..old_part.len()).rev() {
+        for j in (0..new_part.len()).rev() {
+            common[i * width + j] = if old_part[i] == new_part[j] {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                common[(i + 1) * width + j].max(common[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < old_part.len() || j < new_part.len() {
+        let line = start + i + 1;
+
+        if i < old_part.len() && j < new_part.len() && old_part[i] == new_part[j] {
+            i += 1;
+            j += 1;
+        } else if j == new_part.len()
+            || (i < old_part.len() && common[(i + 1) * width + j] >= common[i * width + j + 1])
+        {
+            lines.push(format!("- {line}: {}", old_part[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {line}: {}", new_part[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs() {
+        assert_eq!(diff("a\nb\nc", "a\nb\nc"), Vec::<String>::new());
+        assert_eq!(diff("a\nb\nc", "a\nB\nc"), ["- 2: b", "+ 3: B"]);
+        assert_eq!(diff("a\nc", "a\nb\nc"), ["+ 2: b"]);
+        assert_eq!(diff("a\nb\nc\nd", "a\nd"), ["- 2: b", "- 3: c"]);
+        assert_eq!(
+            diff("keep\nx\nsame\ny", "keep\nsame\nz"),
+            ["- 2: x", "- 4: y", "+ 5: z"]
+        );
+    }
+
+    #[test]
+    fn very_different_diffs() {
+        // Too many lines to compare one by one, so they are listed as removed then added
+        let old = vec!["old"; 3000].join("\n");
+        let new = vec!["new"; 3000].join("\n");
+        let lines = diff(&old, &new);
+
+        assert_eq!(lines.len(), 6000);
+        assert_eq!(lines[0], "- 1: old");
+        assert_eq!(lines[2999], "- 3000: old");
+        assert_eq!(lines[3000], "+ 3001: new");
+    }
+
+    #[test]
+    fn swap_files() {
+        let dir = std::env::temp_dir().join(format!("beditor-swap-test-{}", std::process::id()));
+        let path = dir.join("file.swp");
+
+        write(&path, &Rope::from("one\ntwo")).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, format!("{MAGIC}{}\none\ntwo", std::process::id()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn running_processes() {
+        // The editor's own swap files are never another session's
+        assert!(!is_running(std::process::id()));
+
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        assert!(is_running(child.id()));
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(!is_running(child.id()));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::view::View;
+use crate::window::{Window, Windows};
+use std::path::Path;
+
+/// A tab page the cursor isn't in, with its windows and what the window the cursor was in
+/// shows.
+pub struct TabPage {
+    pub windows: Windows,
+    pub window: Window,
+}
+
+/// The tab pages, each with its own windows. The editor holds the windows of the current one
+/// itself.
+pub struct TabPages {
+    /// The index of the tab page the cursor is in.
+    pub current: usize,
+    /// Every tab page in order, with `None` in place of the current one.
+    pages: Vec<Option<TabPage>>,
+}
+
+impl TabPages {
+    pub fn new() -> Self {
+        Self {
+            current: 0,
+            pages: vec![None],
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Adds a tab page after the current one and makes it current, keeping `page` as what
+    /// the one that was current has.
+    pub fn open(&mut self, page: TabPage) {
+        self.pages[self.current] = Some(page);
+        self.current += 1;
+        self.pages.insert(self.current, None);
+    }
+
+    /// Takes out what a tab page other than the current one has, for the cursor to move to
+    /// it.
+    pub fn take(&mut self, index: usize) -> Option<TabPage> {
+        self.pages.get_mut(index)?.take()
+    }
+
+    /// Keeps `page` as what the current tab page has, and makes the one at `index`, which has
+    /// been taken out, current.
+    pub fn leave(&mut self, page: TabPage, index: usize) {
+        self.pages[self.current] = Some(page);
+        self.current = index;
+    }
+
+    /// Closes the current tab page and makes the one after it current, or the one before it
+    /// if it was the last, returning what that one has. Returns `None` if it's the only tab
+    /// page, which can't be closed.
+    pub fn close(&mut self) -> Option<TabPage> {
+        if self.pages.len() == 1 {
+            return None;
+        }
+
+        self.pages.remove(self.current);
+        self.current = self.current.min(self.pages.len() - 1);
+        self.pages[self.current].take()
+    }
+
+    /// Closes every tab page but the current one.
+    pub fn only(&mut self) {
+        self.pages = vec![None];
+        self.current = 0;
+    }
+
+    /// Closes the windows showing a buffer in the tab pages besides the current one, along
+    /// with tab pages left without a window.
+    pub fn close_showing(&mut self, buffer: usize) {
+        for page in self.pages.iter_mut().flatten() {
+            for id in page.windows.showing(buffer) {
+                page.windows.close(id);
+            }
+
+            if page.window.buffer == buffer {
+                if let Some(window) = page.windows.close_active() {
+                    page.window = window;
+                }
+            }
+        }
+
+        // A tab page whose last window shows the buffer is closed with it
+        self.pages.retain(|page| {
+            page.as_ref()
+                .is_none_or(|page| page.window.buffer != buffer)
+        });
+        self.current = self.pages.iter().position(Option::is_none).unwrap_or(0);
+    }
+
+    /// Returns the label of each tab page for the tab line, given the windows of the current
+    /// one and the view of the window the cursor is in.
+    pub fn labels(&self, windows: &Windows, active: &View) -> Vec<String> {
+        self.pages
+            .iter()
+            .map(|page| match page {
+                Some(page) => label(&page.windows, &page.window.view),
+                None => label(windows, active),
+            })
+            .collect()
+    }
+}
+
+/// Labels a tab page with the name of the file in the window the cursor is in, after the
+/// number of windows if there are several and `+` if any shows unsaved changes.
+fn label(windows: &Windows, active: &View) -> String {
+    let file = active.file();
+    let name = Path::new(&file)
+        .file_name()
+        .map_or(file.clone(), |name| name.to_string_lossy().to_string());
+    let count = windows.count();
+    let modified = active.is_modified() || windows.views().any(View::is_modified);
+
+    let mut flags = if count > 1 {
+        count.to_string()
+    } else {
+        String::new()
+    };
+    if modified {
+        flags.push('+');
+    }
+
+    if flags.is_empty() {
+        format!(" {name} ")
+    } else {
+        format!(" {flags} {name} ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Location;
+    use crate::editor::Cursor;
+    use crate::view::{tests::view, Size, Wrap};
+
+    fn named(file: &str) -> View {
+        let mut view = view("", 80, 23, Wrap::Scroll);
+        view.buffer_mut().file = file.to_string();
+        view
+    }
+
+    fn window(buffer: usize, file: &str) -> Window {
+        Window {
+            buffer,
+            view: named(file),
+            cursor: Cursor::new(Location::new(0, 0)),
+        }
+    }
+
+    /// A tab page with the cursor in a window showing a buffer, and one window beside it
+    /// showing each of `others`.
+    fn page(buffer: usize, others: &[usize]) -> TabPage {
+        let mut windows = Windows::new();
+        let mut active = named("");
+
+        for other in others {
+            windows.arrange(
+                Size {
+                    width: 80,
+                    height: 25,
+                },
+                0,
+                &mut active,
+            );
+            windows.split(true, window(*other, "other.txt"));
+        }
+        TabPage {
+            windows,
+            window: window(buffer, &format!("dir/file{buffer}.txt")),
+        }
+    }
+
+    fn labels(pages: &TabPages) -> Vec<String> {
+        pages.labels(&Windows::new(), &named("current.txt"))
+    }
+
+    #[test]
+    fn opening_and_moving_between_pages() {
+        let mut pages = TabPages::new();
+        pages.open(page(1, &[]));
+        pages.open(page(2, &[]));
+        assert_eq!(pages.count(), 3);
+        assert_eq!(pages.current, 2);
+
+        let first = pages.take(0).unwrap();
+        assert_eq!(first.window.buffer, 1);
+        pages.leave(page(3, &[]), 0);
+        assert_eq!(pages.current, 0);
+        assert!(pages.take(0).is_none());
+        assert_eq!(
+            labels(&pages),
+            [" current.txt ", " file2.txt ", " file3.txt "]
+        );
+    }
+
+    #[test]
+    fn closing_pages() {
+        let mut pages = TabPages::new();
+        pages.open(page(1, &[]));
+        pages.open(page(2, &[]));
+
+        // Closing the last page goes to the one before it
+        assert_eq!(pages.close().unwrap().window.buffer, 2);
+        assert_eq!(pages.current, 1);
+        assert_eq!(pages.count(), 2);
+
+        pages.only();
+        assert_eq!(pages.count(), 1);
+        assert!(pages.close().is_none());
+    }
+
+    #[test]
+    fn closing_a_buffer_everywhere() {
+        let mut pages = TabPages::new();
+        pages.open(page(5, &[]));
+        pages.open(page(1, &[5]));
+        pages.open(page(5, &[2]));
+
+        pages.close_showing(5);
+
+        // The page only showing it is closed, and the others lose their windows showing it
+        assert_eq!(pages.count(), 3);
+        assert_eq!(pages.current, 2);
+        assert_eq!(
+            labels(&pages),
+            [" file1.txt ", " other.txt ", " current.txt "]
+        );
+    }
+
+    #[test]
+    fn labels_count_windows_and_changes() {
+        let mut pages = TabPages::new();
+        let mut modified = page(1, &[2, 3]);
+        modified
+            .window
+            .view
+            .buffer_mut()
+            .insert(&Location::new(0, 0), "x");
+        pages.open(modified);
+
+        assert_eq!(labels(&pages), [" 3+ file1.txt ", " current.txt "]);
+    }
+}
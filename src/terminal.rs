@@ -2,7 +2,6 @@ use crate::editor::Mode;
 use crossterm::cursor;
 use crossterm::queue;
 use crossterm::style::Print;
-use crossterm::terminal;
 use crossterm::terminal::Clear;
 use crossterm::terminal::ClearType;
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
@@ -12,38 +11,13 @@ use std::io::stdout;
 use std::io::Error;
 use std::io::Write;
 
+/// A cell on the terminal screen. Positions in the document are `buffer::Location`s.
 #[derive(Debug, Clone)]
 pub struct Position {
     pub x: u16,
     pub y: u16,
 }
 
-impl Position {
-    pub fn left(&mut self) {
-        if self.x != 0 {
-            self.x -= 1;
-        }
-    }
-
-    pub fn right(&mut self) {
-        if self.x != terminal::size().unwrap().0 {
-            self.x += 1;
-        }
-    }
-
-    pub fn up(&mut self) {
-        if self.y > 0 {
-            self.y -= 1;
-        }
-    }
-
-    pub fn down(&mut self) {
-        if self.y != terminal::size().unwrap().1 - 2 {
-            self.y += 1;
-        }
-    }
-}
-
 impl Position {
     pub const fn new(x: u16, y: u16) -> Self {
         Self { x, y }
@@ -103,7 +77,11 @@ pub fn show_cursor() -> Result<(), Error> {
 
 pub fn change_cursor_style(mode: &Mode) {
     match mode {
-        Mode::Normal => queue_command(cursor::SetCursorStyle::SteadyBlock).unwrap(),
-        Mode::Insert => queue_command(cursor::SetCursorStyle::BlinkingBar).unwrap(),
+        Mode::Normal | Mode::Visual(_) | Mode::Confirm | Mode::Recover => {
+            queue_command(cursor::SetCursorStyle::SteadyBlock).unwrap()
+        }
+        Mode::Insert | Mode::Command | Mode::Search(_) => {
+            queue_command(cursor::SetCursorStyle::BlinkingBar).unwrap();
+        }
     }
 }
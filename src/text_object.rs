@@ -0,0 +1,502 @@
+use crate::buffer::{Buffer, Location, Range};
+use crate::motion::{is_sentence_start, next, previous, word_class, Class, Lines};
+use ropey::Rope;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    /// `w`, or `W` for a WORD when the flag is set.
+    Word(bool),
+    Sentence,
+    Paragraph,
+    /// Text between a pair of `"`, `'` or `` ` `` on the cursor's line.
+    Quote(char),
+    /// Text between a matching pair of brackets, possibly spanning lines.
+    Bracket(char, char),
+    /// Text between a matching pair of XML or HTML tags.
+    Tag,
+}
+
+impl ObjectKind {
+    /// Returns the object for the key typed after `i` or `a`.
+    pub const fn from_key(key: char) -> Option<Self> {
+        match key {
+            'w' => Some(Self::Word(false)),
+            'W' => Some(Self::Word(true)),
+            's' => Some(Self::Sentence),
+            'p' => Some(Self::Paragraph),
+            '"' | '\'' | '`' => Some(Self::Quote(key)),
+            '(' | ')' | 'b' => Some(Self::Bracket('(', ')')),
+            '[' | ']' => Some(Self::Bracket('[', ']')),
+            '{' | '}' | 'B' => Some(Self::Bracket('{', '}')),
+            '<' | '>' => Some(Self::Bracket('<', '>')),
+            't' => Some(Self::Tag),
+            _ => None,
+        }
+    }
+}
+
+/// A span of text around the cursor selected with `i` (inner) or `a` (around), such as `iw`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextObject {
+    pub around: bool,
+    pub kind: ObjectKind,
+}
+
+impl TextObject {
+    /// Returns the range the object covers at `pos`, or `None` if there is no such object.
+    pub fn range(self, buffer: &Buffer, pos: &Location, count: usize) -> Option<Range> {
+        match self.kind {
+            ObjectKind::Word(big) => Some(self.word(buffer, pos, count, big)),
+            ObjectKind::Sentence => Some(self.sentence(buffer, pos, count)),
+            ObjectKind::Paragraph => Some(self.paragraph(buffer, pos, count)),
+            ObjectKind::Quote(quote) => self.quote(buffer, pos, quote),
+            ObjectKind::Bracket(open, close) => self.bracket(buffer, pos, count, open, close),
+            ObjectKind::Tag => self.tag(buffer, pos, count),
+        }
+    }
+
+    /// Selects `count` runs of same-class characters on the cursor's line. Around a word, the
+    /// whitespace after it is included too, or the whitespace before it if there is none after.
+    fn word(self, buffer: &Buffer, pos: &Location, count: usize, big: bool) -> Range {
+        let lines = Lines::new(buffer);
+        let class = |x: usize| word_class(&lines, &Location::new(x, pos.y), big);
+        let len = buffer.nth_line_len(pos.y);
+        let run_end = |x: usize| {
+            let kind = class(x);
+            let mut end = x;
+
+            while end < len && class(end) == kind {
+                end += 1;
+            }
+            end
+        };
+
+        let target = class(pos.x);
+        let mut start = pos.x;
+
+        while start > 0 && class(start - 1) == target {
+            start -= 1;
+        }
+
+        let mut end = (0..count).fold(start, |end, _| run_end(end));
+
+        if self.around {
+            if target == Class::Blank || (class(end) == Class::Blank && end < len) {
+                end = run_end(end);
+            } else {
+                while start > 0 && class(start - 1) == Class::Blank {
+                    start -= 1;
+                }
+            }
+        }
+
+        Range::new(
+            Location::new(start, pos.y),
+            Location::new(end, pos.y),
+            false,
+        )
+    }
+
+    /// Selects from the start of the cursor's sentence to the start of the `count`th sentence
+    /// after it. The inner object leaves out the whitespace before that.
+    fn sentence(self, buffer: &Buffer, pos: &Location, count: usize) -> Range {
+        let lines = Lines::new(buffer);
+        let mut start = pos.clone();
+
+        while !is_sentence_start(&lines, &start) {
+            match previous(&lines, &start) {
+                Some(pos) => start = pos,
+                None => break,
+            }
+        }
+
+        let mut end = start.clone();
+
+        for _ in 0..count {
+            while let Some(pos) = next(&lines, &end) {
+                end = pos;
+
+                if is_sentence_start(&lines, &end) {
+                    break;
+                }
+            }
+        }
+
+        if !self.around {
+            while let Some(pos) = previous(&lines, &end) {
+                if pos <= start || word_class(&lines, &pos, true) != Class::Blank {
+                    break;
+                }
+                end = pos;
+            }
+        }
+        Range::new(start, end, false)
+    }
+
+    /// Selects `count` runs of lines that are all either empty or non-empty. Around a
+    /// paragraph, the empty lines after it are included too, or those before it if there are
+    /// none after.
+    fn paragraph(self, buffer: &Buffer, pos: &Location, count: usize) -> Range {
+        let is_empty = |y: usize| buffer.nth_line_len(y) == 0;
+        let last_line = buffer.len() - 1;
+        let run_end = |y: usize| {
+            let mut end = y;
+
+            while end < last_line && is_empty(end + 1) == is_empty(y) {
+                end += 1;
+            }
+            end
+        };
+
+        let mut start = pos.y;
+
+        while start > 0 && is_empty(start - 1) == is_empty(pos.y) {
+            start -= 1;
+        }
+
+        let mut end = run_end(start);
+
+        for _ in 1..count {
+            if end < last_line {
+                end = run_end(end + 1);
+            }
+        }
+
+        if self.around {
+            if end < last_line {
+                end = run_end(end + 1);
+            } else {
+                while start > 0 && is_empty(start - 1) {
+                    start -= 1;
+                }
+            }
+        }
+
+        Range::new(Location::new(0, start), Location::new(0, end), true)
+    }
+
+    /// Selects the quoted text the cursor is in or on, or the first quoted text after it on
+    /// its line. Quotes are paired up from the start of the line.
+    fn quote(self, buffer: &Buffer, pos: &Location, quote: char) -> Option<Range> {
+        let graphemes = buffer.graphemes(pos.y);
+        let quote = quote.to_string();
+        let quotes: Vec<usize> = (0..graphemes.len())
+            .filter(|x| graphemes[*x] == quote)
+            .collect();
+
+        let (open, close) = quotes
+            .chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .find(|(_, close)| *close >= pos.x)?;
+
+        let (start, end) = if self.around {
+            let blank = |x: usize| graphemes.get(x).is_some_and(|g| g.trim().is_empty());
+            let mut start = open;
+            let mut end = close + 1;
+
+            if blank(end) {
+                while blank(end) {
+                    end += 1;
+                }
+            } else {
+                while start > 0 && blank(start - 1) {
+                    start -= 1;
+                }
+            }
+            (start, end)
+        } else {
+            (open + 1, close)
+        };
+
+        Some(Range::new(
+            Location::new(start, pos.y),
+            Location::new(end, pos.y),
+            false,
+        ))
+    }
+
+    /// Selects the text in the `count`th pair of brackets around the cursor. When the brackets
+    /// are on their own lines, the inner object is the whole lines between them.
+    fn bracket(
+        self,
+        buffer: &Buffer,
+        pos: &Location,
+        count: usize,
+        open: char,
+        close: char,
+    ) -> Option<Range> {
+        let text = buffer.text();
+        let cursor = buffer.index_of(pos);
+
+        // The cursor may be on one of the brackets itself
+        let mut start = if cursor < text.len_chars() && text.char(cursor) == open {
+            cursor
+        } else {
+            outward(text, cursor.checked_sub(1)?, open, close)?
+        };
+
+        for _ in 1..count {
+            start = outward(text, start.checked_sub(1)?, open, close)?;
+        }
+
+        let mut depth = 0;
+        let end = (start..text.len_chars()).find(|i| {
+            match text.char(*i) {
+                c if c == open => depth += 1,
+                c if c == close => depth -= 1,
+                _ => (),
+            }
+            depth == 0
+        })?;
+
+        if self.around {
+            return Some(Range::new(
+                buffer.location_of(start),
+                buffer.location_of(end + 1),
+                false,
+            ));
+        }
+
+        let first = buffer.location_of(start);
+        let last = buffer.location_of(end);
+
+        if last.y > first.y
+            && first.x + 1 == buffer.nth_line_len(first.y)
+            && buffer.indentation(last.y) == last.x
+        {
+            if last.y == first.y + 1 {
+                let inside = Location::new(0, last.y);
+                return Some(Range::new(inside.clone(), inside, false));
+            }
+            return Some(Range::new(
+                Location::new(0, first.y + 1),
+                Location::new(0, last.y - 1),
+                true,
+            ));
+        }
+
+        Some(Range::new(
+            buffer.location_of(start + 1),
+            buffer.location_of(end),
+            false,
+        ))
+    }
+
+    /// Selects the text between the `count`th pair of tags around the cursor.
+    fn tag(self, buffer: &Buffer, pos: &Location, count: usize) -> Option<Range> {
+        let text = buffer.text().to_string();
+        let cursor = buffer.text().char_to_byte(buffer.index_of(pos));
+        let mut open: Vec<(String, usize, usize)> = Vec::new();
+        let mut elements = Vec::new();
+
+        for (start, end, name, closing) in tags(&text) {
+            if closing {
+                if let Some(i) = open.iter().rposition(|(open, ..)| *open == name) {
+                    let (_, open_start, open_end) = open.remove(i);
+                    open.truncate(i);
+                    elements.push((open_start, open_end, start, end));
+                }
+            } else {
+                open.push((name, start, end));
+            }
+        }
+
+        // Elements are found in the order they close, so inner ones come first
+        let (open_start, open_end, close_start, close_end) = elements
+            .into_iter()
+            .filter(|(open_start, _, _, close_end)| *open_start <= cursor && cursor < *close_end)
+            .nth(count - 1)?;
+
+        let location = |byte: usize| buffer.location_of(buffer.text().byte_to_char(byte));
+
+        if self.around {
+            Some(Range::new(location(open_start), location(close_end), false))
+        } else {
+            Some(Range::new(location(open_end), location(close_start), false))
+        }
+    }
+}
+
+/// Searches backwards from `from` for an opening bracket that isn't closed before `from`.
+fn outward(text: &Rope, from: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+
+    (0..=from).rev().find(|i| {
+        match text.char(*i) {
+            c if c == close => depth += 1,
+            c if c == open && depth == 0 => return true,
+            c if c == open => depth -= 1,
+            _ => (),
+        }
+        false
+    })
+}
+
+/// Finds the opening and closing tags in some text, returning their byte ranges, names and
+/// whether they are closing tags. Self-closing tags, comments and declarations are skipped.
+fn tags(text: &str) -> Vec<(usize, usize, String, bool)> {
+    let mut tags = Vec::new();
+    let mut rest = 0;
+
+    while let Some(start) = text[rest..].find('<').map(|i| rest + i) {
+        let Some(end) = text[start..].find('>').map(|i| start + i + 1) else {
+            break;
+        };
+        let inside = &text[start + 1..end - 1];
+        let closing = inside.starts_with('/');
+        let name: String = inside
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'))
+            .collect();
+
+        if !name.is_empty() && !inside.ends_with('/') {
+            tags.push((start, end, name, closing));
+        }
+        rest = start + 1;
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the text an object such as `iw` covers at a location, or `None` if there is no
+    /// such object there.
+    fn selected(text: &str, pos: (usize, usize), keys: &str, count: usize) -> Option<String> {
+        let lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        let buffer = Buffer::new(&lines, String::new());
+        let mut keys = keys.chars();
+        let object = TextObject {
+            around: keys.next() == Some('a'),
+            kind: ObjectKind::from_key(keys.next().unwrap()).unwrap(),
+        };
+
+        object
+            .range(&buffer, &Location::new(pos.0, pos.1), count)
+            .map(|range| buffer.slice(&range))
+    }
+
+    #[test]
+    fn words() {
+        let text = "foo bar.baz  qux";
+
+        assert_eq!(selected(text, (5, 0), "iw", 1).as_deref(), Some("bar"));
+        assert_eq!(selected(text, (5, 0), "iW", 1).as_deref(), Some("bar.baz"));
+        assert_eq!(
+            selected(text, (5, 0), "aW", 1).as_deref(),
+            Some("bar.baz  ")
+        );
+        assert_eq!(selected(text, (0, 0), "aw", 1).as_deref(), Some("foo "));
+        assert_eq!(selected(text, (0, 0), "iw", 3).as_deref(), Some("foo bar"));
+        assert_eq!(selected(text, (11, 0), "iw", 1).as_deref(), Some("  "));
+        // With no whitespace after the last word, the whitespace before it is taken instead
+        assert_eq!(selected(text, (14, 0), "aw", 1).as_deref(), Some("  qux"));
+    }
+
+    #[test]
+    fn sentences() {
+        let text = "One. Two three.  Four";
+
+        assert_eq!(
+            selected(text, (7, 0), "is", 1).as_deref(),
+            Some("Two three.")
+        );
+        assert_eq!(
+            selected(text, (7, 0), "as", 1).as_deref(),
+            Some("Two three.  ")
+        );
+        assert_eq!(
+            selected(text, (0, 0), "as", 2).as_deref(),
+            Some("One. Two three.  ")
+        );
+    }
+
+    #[test]
+    fn paragraphs() {
+        let text = "a\nb\n\nc\n\n\nd";
+
+        assert_eq!(selected(text, (0, 1), "ip", 1).as_deref(), Some("a\nb"));
+        assert_eq!(selected(text, (0, 1), "ap", 1).as_deref(), Some("a\nb\n"));
+        assert_eq!(selected(text, (0, 4), "ip", 1).as_deref(), Some("\n"));
+        assert_eq!(
+            selected(text, (0, 0), "ip", 3).as_deref(),
+            Some("a\nb\n\nc")
+        );
+        // The last paragraph takes the empty lines before it
+        assert_eq!(selected(text, (0, 6), "ap", 1).as_deref(), Some("\n\nd"));
+    }
+
+    #[test]
+    fn quotes() {
+        let text = r#"say "hi there" and 'bye'"#;
+
+        assert_eq!(
+            selected(text, (6, 0), "i\"", 1).as_deref(),
+            Some("hi there")
+        );
+        assert_eq!(
+            selected(text, (6, 0), "a\"", 1).as_deref(),
+            Some("\"hi there\" ")
+        );
+        // Before any quotes, the first quoted text after the cursor is taken
+        assert_eq!(selected(text, (0, 0), "i'", 1).as_deref(), Some("bye"));
+        assert_eq!(selected(text, (21, 0), "a'", 1).as_deref(), Some(" 'bye'"));
+        assert_eq!(selected(text, (0, 0), "i`", 1), None);
+    }
+
+    #[test]
+    fn brackets() {
+        let text = "f(a, (b + c), d)";
+
+        assert_eq!(selected(text, (7, 0), "i(", 1).as_deref(), Some("b + c"));
+        assert_eq!(selected(text, (7, 0), "a)", 1).as_deref(), Some("(b + c)"));
+        assert_eq!(
+            selected(text, (7, 0), "ib", 2).as_deref(),
+            Some("a, (b + c), d")
+        );
+        assert_eq!(
+            selected(text, (1, 0), "i(", 1).as_deref(),
+            Some("a, (b + c), d")
+        );
+        assert_eq!(selected(text, (7, 0), "i(", 3), None);
+        assert_eq!(selected(text, (7, 0), "i[", 1), None);
+    }
+
+    #[test]
+    fn brackets_on_their_own_lines() {
+        let text = "fn f() {\n    one;\n    two;\n}";
+
+        // The inner object is the lines between the brackets
+        assert_eq!(
+            selected(text, (4, 1), "iB", 1).as_deref(),
+            Some("    one;\n    two;")
+        );
+        assert_eq!(
+            selected(text, (4, 1), "a{", 1).as_deref(),
+            Some("{\n    one;\n    two;\n}")
+        );
+        assert_eq!(selected("{\n}", (0, 0), "i{", 1).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn tags() {
+        let text = "<div><p>one <b>two</b></p><br/></div>";
+
+        assert_eq!(selected(text, (16, 0), "it", 1).as_deref(), Some("two"));
+        assert_eq!(
+            selected(text, (16, 0), "at", 1).as_deref(),
+            Some("<b>two</b>")
+        );
+        assert_eq!(
+            selected(text, (16, 0), "it", 2).as_deref(),
+            Some("one <b>two</b>")
+        );
+        assert_eq!(
+            selected(text, (28, 0), "it", 1).as_deref(),
+            Some("<p>one <b>two</b></p><br/>")
+        );
+        assert_eq!(selected("no tags", (0, 0), "it", 1), None);
+    }
+}
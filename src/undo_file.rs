@@ -0,0 +1,103 @@
+use crate::buffer::Location;
+use ropey::Rope;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Returns where to keep something about a file between sessions, such as its undo
+/// history: a file named after the file's full path, with `%` for each `/`, in
+/// `$XDG_STATE_HOME/beditor/{dir}/`.
+pub fn state_path(dir: &str, file: &str) -> Option<PathBuf> {
+    let dir = state_dir(dir)?;
+
+    // A file that hasn't been saved yet doesn't exist, but its directory should
+    let file = Path::new(file);
+    let file = std::fs::canonicalize(file).ok().or_else(|| {
+        let dir = file.parent().filter(|dir| !dir.as_os_str().is_empty());
+        let dir = std::fs::canonicalize(dir.unwrap_or(Path::new("."))).ok()?;
+        Some(dir.join(file.file_name()?))
+    })?;
+
+    Some(dir.join(file.to_string_lossy().replace('/', "%")))
+}
+
+/// Returns `$XDG_STATE_HOME/beditor/{dir}/`, where `state_path` puts things.
+pub fn state_dir(dir: &str) -> Option<PathBuf> {
+    let state = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+        })?;
+
+    Some(state.join("beditor").join(dir))
+}
+
+/// Hashes text with FNV-1a, which unlike the standard library's hasher gives the same hash
+/// in every build, to check that a history saved earlier still fits the file.
+pub fn hash(text: &Rope) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Encodes numbers, text and locations into bytes.
+#[derive(Default)]
+pub struct Writer {
+    pub bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn number(&mut self, n: usize) {
+        self.bytes.extend_from_slice(&(n as u64).to_le_bytes());
+    }
+
+    pub fn text(&mut self, text: &str) {
+        self.number(text.len());
+        self.bytes.extend_from_slice(text.as_bytes());
+    }
+
+    pub fn location(&mut self, pos: &Location) {
+        self.number(pos.x);
+        self.number(pos.y);
+    }
+}
+
+/// Decodes what a `Writer` encoded, in the same order.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub const fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if len > self.bytes.len() {
+            return Err(invalid());
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn number(&mut self) -> Result<usize, Error> {
+        let bytes = self.take(8)?.try_into().map_err(|_| invalid())?;
+        usize::try_from(u64::from_le_bytes(bytes)).map_err(|_| invalid())
+    }
+
+    pub fn text(&mut self) -> Result<String, Error> {
+        let len = self.number()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid())
+    }
+
+    pub fn location(&mut self) -> Result<Location, Error> {
+        Ok(Location::new(self.number()?, self.number()?))
+    }
+}
+
+/// The error for an undo file that is damaged or from something else.
+pub fn invalid() -> Error {
+    Error::new(ErrorKind::InvalidData, "Invalid undo file")
+}
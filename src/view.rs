@@ -1,10 +1,23 @@
-use crate::buffer::{Backspace, Buffer};
+use crate::buffer::{columns, Backspace, Buffer, Location, Step};
+use crate::encoding;
+use crate::search::Search;
+use crate::selection::Selection;
+use crate::swap;
+use crossterm::style::Stylize;
 use crossterm::terminal;
+use encoding_rs::Encoding;
+use std::cell::{Ref, RefCell, RefMut};
 use std::fmt::Display;
-use std::io::Error;
+use std::io::{Error, ErrorKind};
+use std::rc::Rc;
+use unicode_width::UnicodeWidthChar;
 
 use crate::terminal::{clear_line, move_cursor_to, print, Position};
 
+/// The status line shown under each window until `:set statusline` changes it.
+pub const STATUS_LINE: &str = " %f %m%r%=%M  %l:%c ";
+
+#[derive(Clone, Copy)]
 pub struct Size {
     pub width: u16,
     pub height: u16,
@@ -19,55 +32,159 @@ impl Size {
     }
 }
 
+/// How lines wider than the screen are displayed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Wrap {
+    /// Lines are cut off at the edge of the screen, which scrolls horizontally with the cursor.
+    Scroll,
+    /// Lines continue onto as many screen rows as they need.
+    Soft,
+}
+
+/// A window's view of a buffer, which other windows can also be showing.
 pub struct View {
-    buffer: Buffer,
+    buffer: Rc<RefCell<Buffer>>,
+    /// Where the window's top left corner is on the terminal, and the size of the part of it
+    /// the document is drawn in.
+    origin: Position,
     size: Size,
+    /// The last row of the terminal, where messages go.
+    bottom: u16,
+    /// The first line of the document shown on screen.
+    offset: usize,
+    /// The first display column shown on screen when scrolling horizontally.
+    left: usize,
+    pub wrap: Wrap,
     pub redraw: bool,
+    /// A swap file found when the file was opened, until the editor decides what to do with it.
+    pub swap: Option<swap::Found>,
+    /// Notes on how the file was opened to show once it is, such as `[New File]`.
+    pub notes: Vec<String>,
 }
 
 impl View {
-    pub fn new(file: &Option<String>) -> Self {
-        file.as_ref().map_or_else(
-            || Self {
-                buffer: Buffer::new(vec![String::new()], String::from("new.txt")),
-                redraw: true,
-                size: Size::default(),
-            },
-            |f| Self {
-                buffer: Buffer::new(
-                    std::fs::read_to_string(f)
-                        .expect("FATAL: Failed to read file")
-                        .lines()
-                        .collect::<Vec<_>>()
-                        .into_iter()
-                        .map(std::string::ToString::to_string)
-                        .collect(),
-                    f.to_string(),
-                ),
-                redraw: true,
-                size: Size::default(),
+    /// Opens a file, in the given encoding or else the one it looks to be in, or a new buffer
+    /// if no file is given. A file that doesn't exist yet is a new buffer named after it, and
+    /// one that can't be read is an empty one that can't be saved over it. A swap file found
+    /// for the file is kept in `swap` for the editor to ask what to do with; otherwise the
+    /// buffer starts keeping its own.
+    pub fn new(
+        file: &Option<String>,
+        encoding: Option<&'static Encoding>,
+        wrap: Wrap,
+    ) -> Result<Self, Error> {
+        let Some(f) = file else {
+            let mut buffer = Buffer::new(&[String::new()], String::from("new.txt"));
+            buffer.claim_unnamed_swap();
+            return Ok(Self::with_buffer(buffer, wrap));
+        };
+
+        let mut notes = Vec::new();
+        let mut buffer = match std::fs::read(f) {
+            Ok(bytes) => {
+                let mut buffer = Buffer::open(&bytes, encoding, f.to_string());
+                buffer.read_history();
+                buffer
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                notes.push(String::from("[New File]"));
+                Buffer::new(&[String::new()], f.to_string())
+            }
+            Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+                notes.push(String::from("[Permission denied]"));
+                let mut buffer = Buffer::new(&[String::new()], f.to_string());
+                buffer.readonly = true;
+                buffer
+            }
+            Err(err) => return Err(Error::new(err.kind(), format!("Can't open {f}: {err}"))),
+        };
+
+        if std::fs::metadata(f).is_ok_and(|metadata| metadata.permissions().readonly()) {
+            notes.push(String::from("[readonly]"));
+            buffer.readonly = true;
+        }
+
+        if buffer.binary {
+            notes.push(String::from("[binary]"));
+        } else if buffer.encoding != encoding_rs::UTF_8 {
+            notes.push(format!("[{}]", encoding::name(buffer.encoding)));
+        }
+
+        if buffer.malformed {
+            let name = encoding::name(buffer.encoding);
+            notes.push(format!("[invalid {name} bytes replaced]"));
+        }
+
+        let swap = swap::find(f, buffer.text());
+        if swap.is_none() {
+            buffer.claim_swap();
+        }
+
+        Ok(Self {
+            swap,
+            notes,
+            ..Self::with_buffer(buffer, wrap)
+        })
+    }
+
+    /// Shows a buffer that isn't a file being edited, such as the differences from a swap
+    /// file.
+    pub fn with_buffer(buffer: Buffer, wrap: Wrap) -> Self {
+        let screen = Size::default();
+
+        Self {
+            buffer: Rc::new(RefCell::new(buffer)),
+            redraw: true,
+            origin: Position::new(0, 0),
+            // Leave the last two rows for the status line and messages
+            size: Size {
+                width: screen.width,
+                height: screen.height.saturating_sub(2),
             },
-        )
+            bottom: screen.height.saturating_sub(1),
+            offset: 0,
+            left: 0,
+            wrap,
+            swap: None,
+            notes: Vec::new(),
+        }
+    }
+
+    /// Returns a view of the same buffer scrolled to the same place, for a new window.
+    pub fn split(&self) -> Self {
+        Self {
+            buffer: Rc::clone(&self.buffer),
+            origin: self.origin.clone(),
+            size: self.size,
+            bottom: self.bottom,
+            offset: self.offset,
+            left: self.left,
+            wrap: self.wrap,
+            redraw: true,
+            swap: None,
+            notes: Vec::new(),
+        }
     }
 
-    pub fn render(&self, pos: &Position) -> Result<(), Error> {
+    /// Draws the view in its window, leaving the terminal cursor on `pos`.
+    pub fn render(
+        &self,
+        pos: &Location,
+        selection: Option<&Selection>,
+        search: Option<&Search>,
+    ) -> Result<(), Error> {
         if self.redraw {
-            for row in 0..self.size.height - 1 {
-                move_cursor_to(&Position::new(0, row))?;
-                clear_line()?;
-
-                if row >= self.buffer.len() {
-                    print("~")?;
-                } else {
-                    print(
-                        self.buffer
-                            .lines
-                            .get(row as usize)
-                            .expect("FATAL: Couldn't get line"),
-                    )?;
-                }
+            let mut rows = self.screen_rows(selection, search).into_iter();
+
+            for row in 0..self.size.height {
+                let (text, width) = rows.next().unwrap_or_else(|| (String::from("~"), 1));
 
-                if self.buffer.is_empty() && row == terminal::size()?.1 / 3 {
+                // Rows are padded rather than cleared, which would clear any window beside this
+                move_cursor_to(&Position::new(self.origin.x, self.origin.y + row))?;
+                print(text)?;
+                print(" ".repeat(usize::from(self.size.width).saturating_sub(width)))?;
+
+                if self.buffer().is_empty() && row == self.size.height / 3 {
                     self.welcome_message(
                         (env!("CARGO_PKG_NAME").to_uppercase() + " " + env!("CARGO_PKG_VERSION"))
                             .as_str(),
@@ -75,12 +192,272 @@ impl View {
                     )?;
                 }
             }
-            move_cursor_to(pos)?;
+            move_cursor_to(&self.screen_position(pos))?;
         }
 
         Ok(())
     }
 
+    /// Returns the text of each screen row from the top of the viewport, up to the end of the
+    /// document or the bottom of the window, with the number of columns it takes up. Selected
+    /// text is shown in reverse video, and matches of a search being typed are highlighted.
+    fn screen_rows(
+        &self,
+        selection: Option<&Selection>,
+        search: Option<&Search>,
+    ) -> Vec<(String, usize)> {
+        let buffer = self.buffer();
+        let mut rows = Vec::new();
+        let mut line = self.offset;
+
+        while rows.len() < self.height() && line < buffer.len() {
+            let graphemes = buffer.graphemes(line);
+            let columns = columns(&graphemes);
+            let selected = selection.and_then(|selection| selection.span(&buffer, line));
+            let matches = search.map_or_else(Vec::new, |search| search.matches(&buffer, line));
+            // The row starts at display column `left`, before the first grapheme if one is cut
+            // off by the left edge
+            let row = |range: std::ops::Range<usize>, left: usize| -> (String, usize) {
+                let indent = columns[range.start] - left;
+                let width = columns[range.end] - left;
+                let text = range.fold(" ".repeat(indent), |mut text, i| {
+                    // Tabs are drawn as the spaces up to the next tab stop
+                    let shown = if graphemes[i] == "\t" {
+                        " ".repeat(columns[i + 1] - columns[i])
+                    } else {
+                        visible(&graphemes[i])
+                    };
+
+                    if selected
+                        .as_ref()
+                        .is_some_and(|selected| selected.contains(&i))
+                    {
+                        text.push_str(&shown.reverse().to_string());
+                    } else if matches.iter().any(|found| found.contains(&i)) {
+                        text.push_str(&shown.black().on_yellow().to_string());
+                    } else {
+                        text.push_str(&shown);
+                    }
+                    text
+                });
+                (text, width)
+            };
+
+            match self.wrap {
+                Wrap::Scroll => {
+                    let visible = self.visible_part(&columns);
+                    let left = self.left.min(columns[visible.start]);
+                    rows.push(row(visible, left));
+                }
+                Wrap::Soft => {
+                    let starts = self.row_starts(line);
+
+                    for (i, start) in starts.iter().enumerate() {
+                        let end = starts.get(i + 1).copied().unwrap_or(graphemes.len());
+                        rows.push(row(*start..end, columns[*start]));
+                    }
+                }
+            }
+            line += 1;
+        }
+        rows.truncate(self.height());
+        rows
+    }
+
+    /// Returns the indices of the graphemes of a line that fit between the left edge and the
+    /// width of the screen, given the display column each starts at.
+    fn visible_part(&self, columns: &[usize]) -> std::ops::Range<usize> {
+        let right = self.left + self.size.width as usize;
+        let len = columns.len() - 1;
+        let mut visible = len..len;
+
+        for i in 0..len {
+            if columns[i] >= self.left && columns[i + 1] <= right {
+                visible.start = visible.start.min(i);
+                visible.end = i + 1;
+            }
+
+            if columns[i + 1] >= right {
+                break;
+            }
+        }
+        visible
+    }
+
+    /// Returns the index of the first grapheme on each screen row the nth line is drawn on.
+    fn row_starts(&self, n: usize) -> Vec<usize> {
+        let mut starts = vec![0];
+
+        if self.wrap == Wrap::Soft {
+            let columns = columns(&self.buffer().graphemes(n));
+            let mut start = 0;
+
+            for i in 0..columns.len() - 1 {
+                if columns[i] > start && columns[i + 1] - start > self.size.width as usize {
+                    starts.push(i);
+                    start = columns[i];
+                }
+            }
+        }
+        starts
+    }
+
+    /// Returns the number of screen rows taken up by the nth line.
+    fn line_rows(&self, n: usize) -> usize {
+        self.row_starts(n).len()
+    }
+
+    /// Returns which of its line's screen rows a location is drawn on, and the grapheme index
+    /// that row starts at.
+    fn row_of(&self, pos: &Location) -> (usize, usize) {
+        let starts = self.row_starts(pos.y);
+        let row = starts
+            .iter()
+            .rposition(|start| *start <= pos.x)
+            .unwrap_or_default();
+
+        (row, starts[row])
+    }
+
+    /// Converts a location in the document into the terminal cell it is drawn at.
+    pub fn screen_position(&self, pos: &Location) -> Position {
+        let (x, y) = self.window_position(pos);
+
+        Position::new(
+            self.origin
+                .x
+                .saturating_add(x.try_into().unwrap_or(u16::MAX)),
+            self.origin
+                .y
+                .saturating_add(y.try_into().unwrap_or(u16::MAX)),
+        )
+    }
+
+    /// Returns the column and row of the window a location in the document is drawn at.
+    fn window_position(&self, pos: &Location) -> (usize, usize) {
+        let column = self.display_column(pos);
+
+        match self.wrap {
+            Wrap::Scroll => (
+                column.saturating_sub(self.left),
+                pos.y.saturating_sub(self.offset),
+            ),
+            Wrap::Soft => {
+                let (row, start) = self.row_of(pos);
+                let above: usize = (self.offset..pos.y).map(|n| self.line_rows(n)).sum();
+
+                (
+                    column - self.display_column(&Location::new(start, pos.y)),
+                    above + row,
+                )
+            }
+        }
+    }
+
+    /// Moves a location up or down one screen row, keeping its column within the row. Without
+    /// soft wrapping this is the same as moving by a line.
+    pub fn move_screen_row(&self, pos: &Location, down: bool) -> Location {
+        let (row, start) = self.row_of(pos);
+        let column = self.display_column(pos) - self.display_column(&Location::new(start, pos.y));
+
+        let (y, row) = if down {
+            if row + 1 < self.line_rows(pos.y) {
+                (pos.y, row + 1)
+            } else if pos.y + 1 < self.len() {
+                (pos.y + 1, 0)
+            } else {
+                return pos.clone();
+            }
+        } else if row > 0 {
+            (pos.y, row - 1)
+        } else if pos.y > 0 {
+            (pos.y - 1, self.line_rows(pos.y - 1) - 1)
+        } else {
+            return pos.clone();
+        };
+
+        let starts = self.row_starts(y);
+        let start = starts[row];
+        let end = starts
+            .get(row + 1)
+            .map_or(self.nth_line_len(y), |end| end - 1);
+        let start_column = self.display_column(&Location::new(start, y));
+        let x = self
+            .column_to_grapheme(y, start_column + column)
+            .clamp(start, end);
+
+        Location::new(x, y)
+    }
+
+    /// The number of rows of the window the document is drawn in.
+    pub fn height(&self) -> usize {
+        self.size.height.into()
+    }
+
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Scrolls the viewport by `delta` lines, keeping at least the last line on screen.
+    pub fn scroll(&mut self, delta: isize) {
+        self.offset = self.offset.saturating_add_signed(delta).min(self.len() - 1);
+        self.redraw = true;
+    }
+
+    /// Returns the last line of the document that is entirely on screen.
+    pub fn bottom(&self) -> usize {
+        let mut rows = 0;
+        let mut line = self.offset;
+
+        while line < self.len() {
+            rows += self.line_rows(line);
+
+            if rows > self.height() {
+                break;
+            }
+            line += 1;
+        }
+        line.saturating_sub(1).max(self.offset)
+    }
+
+    /// Scrolls so that `line` starts at the given screen row, or as close to it as possible.
+    pub fn scroll_to(&mut self, line: usize, row: usize) {
+        let mut remaining = row;
+        self.offset = line;
+
+        while self.offset > 0 && self.line_rows(self.offset - 1) <= remaining {
+            remaining -= self.line_rows(self.offset - 1);
+            self.offset -= 1;
+        }
+        self.redraw = true;
+    }
+
+    /// Scrolls the minimum amount needed to bring the cursor on screen.
+    pub fn follow(&mut self, pos: &Location) {
+        if pos.y < self.offset {
+            self.scroll_to(pos.y, 0);
+        } else if pos.y >= self.offset + self.height() {
+            // Every line takes up at least one row, so this is never too far
+            self.offset = pos.y + 1 - self.height();
+            self.redraw = true;
+        }
+
+        if self.wrap == Wrap::Soft {
+            while self.offset < pos.y && self.window_position(pos).1 >= self.height() {
+                self.offset += 1;
+            }
+        } else {
+            let column = self.display_column(pos);
+            let width = self.size.width as usize;
+
+            if column < self.left {
+                self.left = column;
+            } else if column >= self.left + width {
+                self.left = column + 1 - width;
+            }
+        }
+    }
+
     fn welcome_message(&self, message: &str, row: u16) -> Result<(), Error> {
         let try_message_start = self
             .size
@@ -89,12 +466,13 @@ impl View {
 
         if let Some(message_start) = try_message_start {
             if self.size.width >= message.len().try_into().unwrap() {
-                let start_pos = Position::new(message_start / 2, row);
+                let start_pos =
+                    Position::new(self.origin.x + message_start / 2, self.origin.y + row);
 
                 move_cursor_to(&start_pos)?;
                 print(message)?;
             } else {
-                move_cursor_to(&Position::new(row, 0))?;
+                move_cursor_to(&Position::new(self.origin.x, self.origin.y + row))?;
                 print(message)?;
             }
         }
@@ -102,61 +480,481 @@ impl View {
         Ok(())
     }
 
-    pub fn resize(&mut self, into: Size) {
-        self.size = into;
+    /// Draws the status line under the window from a format, given where the cursor is in
+    /// the window and, if it's the window the cursor is in, the mode, which has it in bold.
+    /// What comes after `%=` is put against the right edge.
+    pub fn draw_status(
+        &self,
+        format: &str,
+        pos: &Location,
+        mode: Option<&str>,
+    ) -> Result<(), Error> {
+        let row = self.origin.y + self.size.height;
+
+        // A screen too small for the window has no room for it
+        if row >= self.bottom {
+            return Ok(());
+        }
+
+        let (left, right) = self.status_text(format, pos, mode.unwrap_or(""));
+        let width = usize::from(self.size.width);
+        let (right, right_used) = cut_to_width(&right, width);
+        let (mut status, used) = cut_to_width(&left, width - right_used);
+        status.push_str(&" ".repeat(width - used - right_used));
+        status.push_str(&right);
+
+        move_cursor_to(&Position::new(self.origin.x, row))?;
+        if mode.is_some() {
+            print(status.reverse().bold())
+        } else {
+            print(status.reverse())
+        }
+    }
+
+    /// Fills in the items of a status line format, returning the text before `%=` and after
+    /// it. `%f` is the file, `%m` is `[+]` if it has unsaved changes, `%r` is `[RO]` if it is
+    /// read-only, `%M` is the mode, `%l` and `%c` are the line and column of the cursor, `%v`
+    /// is its display column, `%L` is the number of lines, `%p` is how far through them the
+    /// cursor is as a percentage and `%%` is `%`. Anything else is shown as it is.
+    fn status_text(&self, format: &str, pos: &Location, mode: &str) -> (String, String) {
+        let buffer = self.buffer();
+        let mut left = String::new();
+        let mut right = None;
+        let mut chars = format.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                right.as_mut().unwrap_or(&mut left).push(c);
+                continue;
+            }
+
+            let item = match chars.next() {
+                Some('f') => buffer.file.clone(),
+                Some('m') if buffer.is_modified() => String::from("[+]"),
+                Some('r') if buffer.readonly => String::from("[RO]"),
+                Some('m' | 'r') => String::new(),
+                Some('M') => mode.to_string(),
+                Some('l') => (pos.y + 1).to_string(),
+                Some('c') => (pos.x + 1).to_string(),
+                Some('v') => (self.display_column(pos) + 1).to_string(),
+                Some('L') => buffer.len().to_string(),
+                Some('p') => ((pos.y + 1) * 100 / buffer.len().max(1)).to_string(),
+                Some('=') => {
+                    right.get_or_insert_with(String::new);
+                    continue;
+                }
+                Some('%') => String::from("%"),
+                Some(other) => format!("%{other}"),
+                None => String::from("%"),
+            };
+            right.as_mut().unwrap_or(&mut left).push_str(&item);
+        }
+        (left, right.unwrap_or_default())
+    }
+
+    /// Draws the labels of the tab pages along the top row of the screen, the current one in
+    /// bold and the rest reversed like the space after them.
+    pub fn draw_tab_line(labels: &[String], current: usize, width: u16) -> Result<(), Error> {
+        let mut left = usize::from(width);
+        move_cursor_to(&Position::new(0, 0))?;
+
+        for (i, label) in labels.iter().enumerate() {
+            let (label, used) = cut_to_width(label, left);
+            left -= used;
+
+            if i == current {
+                print(label.bold())?;
+            } else {
+                print(label.reverse())?;
+            }
+        }
+        print(" ".repeat(left).reverse())
+    }
+
+    /// Moves the view to a window with its top left corner at `origin`, drawing the document
+    /// in `size`, with messages on the `bottom` row of the terminal.
+    pub fn place(&mut self, origin: Position, size: Size, bottom: u16) {
+        self.origin = origin;
+        self.size = size;
+        self.bottom = bottom;
+        self.redraw = true;
+    }
+
+    pub fn write(&mut self, pos: &Location, char: char) -> Location {
+        self.redraw = true;
+        self.buffer_mut().write(pos, char)
+    }
+
+    pub fn backspace(&mut self, pos: &Location) -> Backspace {
         self.redraw = true;
+        self.buffer_mut().backspace(pos)
     }
 
-    pub fn write(&mut self, pos: &Position, char: char) {
-        self.buffer.write(pos, char);
+    pub fn new_line(&mut self, pos: &Location) {
         self.redraw = true;
+        self.buffer_mut().new_line(pos);
     }
 
-    pub fn backspace(&mut self, pos: &Position) -> Backspace {
+    pub fn enter(&mut self, pos: &Location) {
         self.redraw = true;
-        self.buffer.backspace(pos)
+        self.buffer_mut().enter(pos);
     }
 
-    pub fn new_line(&mut self, pos: &Position) {
+    pub fn update_history(&mut self, pos: Location) {
         self.redraw = true;
-        self.buffer.new_line(pos);
+        self.buffer_mut().update_history(pos);
     }
 
-    pub fn enter(&mut self, pos: &Position) {
+    pub fn undo(&mut self) -> Option<Location> {
         self.redraw = true;
-        self.buffer.enter(pos);
+        self.buffer_mut().undo()
     }
 
-    pub fn update_history(&mut self, pos: Position) {
+    pub fn redo(&mut self) -> Option<Location> {
         self.redraw = true;
-        self.buffer.update_history(pos);
+        self.buffer_mut().redo()
     }
 
-    pub fn undo(&mut self) -> Option<Position> {
+    pub fn time_travel(&mut self, step: Step, forward: bool) -> Option<Location> {
         self.redraw = true;
-        self.buffer.undo()
+        self.buffer_mut().time_travel(step, forward)
     }
 
-    pub fn redo(&mut self) -> Option<Position> {
+    pub fn buffer(&self) -> Ref<'_, Buffer> {
+        self.buffer.borrow()
+    }
+
+    pub fn buffer_mut(&mut self) -> RefMut<'_, Buffer> {
         self.redraw = true;
-        self.buffer.redo()
+        self.buffer.borrow_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer().len()
     }
 
-    pub fn nth_line_len(&self, n: usize) -> u16 {
-        self.buffer.nth_line_len(n)
+    pub fn nth_line_len(&self, n: usize) -> usize {
+        self.buffer().nth_line_len(n)
+    }
+
+    pub fn display_column(&self, pos: &Location) -> usize {
+        self.buffer().display_column(pos)
+    }
+
+    pub fn column_to_grapheme(&self, n: usize, column: usize) -> usize {
+        self.buffer().column_to_grapheme(n, column)
     }
 
     pub fn save(&mut self) -> Result<(), std::io::Error> {
-        self.buffer.save()?;
-        self.draw_bottom_message(format!("Successfully saved to {}.", self.buffer.file))?;
-        Ok(())
+        let warnings = self.buffer_mut().save()?;
+        self.report_save(&self.file(), &warnings)
+    }
+
+    pub fn write_to(&mut self, path: &str) -> Result<(), std::io::Error> {
+        let warnings = self.buffer().write_to(path)?;
+        self.report_save(path, &warnings)
+    }
+
+    /// Says the file was saved, and what went wrong if it was only partly successful.
+    fn report_save(&mut self, path: &str, warnings: &[String]) -> Result<(), std::io::Error> {
+        if warnings.is_empty() {
+            self.draw_bottom_message(format!("Successfully saved to {path}."))
+        } else {
+            self.draw_bottom_message(format!("Saved to {path}, but {}.", warnings.join(", ")))
+        }
+    }
+
+    /// Takes the buffer of a view of the file just read again in place of this one's, so every
+    /// window showing this one's shows it too.
+    pub fn reload(&mut self, mut view: Self) {
+        std::mem::swap(&mut *self.buffer_mut(), &mut *view.buffer_mut());
+        self.swap = view.swap;
+        self.notes = view.notes;
+        self.offset = 0;
+        self.left = 0;
+    }
+
+    pub fn save_as(&mut self, path: String) -> Result<(), std::io::Error> {
+        {
+            let mut buffer = self.buffer_mut();
+            buffer.remove_swap();
+            buffer.file = path;
+            buffer.readonly = false;
+            buffer.claim_swap();
+        }
+        self.save()
+    }
+
+    pub fn file(&self) -> String {
+        self.buffer().file.clone()
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.buffer().is_modified()
     }
 
     pub fn draw_bottom_message(&mut self, message: impl Display) -> Result<(), std::io::Error> {
-        move_cursor_to(&Position::new(0, terminal::size().unwrap().1))?;
+        move_cursor_to(&self.bottom_row())?;
         clear_line()?;
         print(message)?;
         self.redraw = true;
         Ok(())
     }
+
+    /// The start of the last row of the terminal, where messages and the command line go.
+    pub const fn bottom_row(&self) -> Position {
+        Position::new(0, self.bottom)
+    }
+}
+
+/// Cuts text down to the characters that fit in `width` columns, returning them and the
+/// number of columns they take up.
+fn cut_to_width(text: &str, width: usize) -> (String, usize) {
+    let mut cut = String::new();
+    let mut used = 0;
+
+    for c in text.chars() {
+        let c_width = c.width().unwrap_or(0);
+        if used + c_width > width {
+            break;
+        }
+        used += c_width;
+        cut.push(c);
+    }
+    (cut, used)
+}
+
+/// Returns how a grapheme is shown. Control characters other than tabs, which are drawn as
+/// spaces, are shown as symbols for them, so they take up the one column they are counted as
+/// and don't act on the terminal.
+fn visible(grapheme: &str) -> String {
+    let symbol = match grapheme.chars().next() {
+        Some(c @ '\0'..='\x1f') if c != '\t' => char::from_u32(0x2400 + c as u32),
+        Some('\x7f') => Some('\u{2421}'),
+        Some(c) if c.is_control() => Some('\u{fffd}'),
+        _ => None,
+    };
+    symbol.map_or_else(|| grapheme.to_string(), String::from)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Returns a view of some text in a window of the given size, without asking the terminal
+    /// for its size as `with_buffer` does.
+    pub fn view(text: &str, width: u16, height: u16, wrap: Wrap) -> View {
+        let lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+
+        View {
+            buffer: Rc::new(RefCell::new(Buffer::new(&lines, String::from("test.txt")))),
+            origin: Position::new(0, 0),
+            size: Size { width, height },
+            bottom: height + 1,
+            offset: 0,
+            left: 0,
+            wrap,
+            redraw: true,
+            swap: None,
+            notes: Vec::new(),
+        }
+    }
+
+    fn numbered_lines(n: usize) -> String {
+        (1..=n)
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn following_the_cursor_down_and_up() {
+        let mut view = view(&numbered_lines(100), 20, 10, Wrap::Scroll);
+
+        view.follow(&Location::new(0, 9));
+        assert_eq!(view.offset(), 0);
+        view.follow(&Location::new(0, 10));
+        assert_eq!(view.offset(), 1);
+        view.follow(&Location::new(0, 50));
+        assert_eq!(view.offset(), 41);
+        assert_eq!(view.bottom(), 50);
+        view.follow(&Location::new(0, 45));
+        assert_eq!(view.offset(), 41);
+        view.follow(&Location::new(0, 3));
+        assert_eq!(view.offset(), 3);
+    }
+
+    #[test]
+    fn scrolling() {
+        let mut view = view(&numbered_lines(20), 20, 10, Wrap::Scroll);
+
+        view.scroll(5);
+        assert_eq!(view.offset(), 5);
+        view.scroll(-10);
+        assert_eq!(view.offset(), 0);
+        // The last line stays on screen
+        view.scroll(100);
+        assert_eq!(view.offset(), 19);
+        assert_eq!(view.bottom(), 19);
+
+        // `zz` puts a line in the middle of the window
+        view.scroll_to(15, 5);
+        assert_eq!(view.offset(), 10);
+        view.scroll_to(2, 5);
+        assert_eq!(view.offset(), 0);
+    }
+
+    #[test]
+    fn rows_below_the_viewport_are_not_drawn() {
+        let mut view = view(&numbered_lines(20), 20, 3, Wrap::Scroll);
+        view.scroll(18);
+
+        let rows: Vec<String> = view
+            .screen_rows(None, None)
+            .into_iter()
+            .map(|(text, _)| text)
+            .collect();
+        assert_eq!(rows, ["19", "20"]);
+        assert_eq!(view.window_position(&Location::new(1, 19)), (1, 1));
+    }
+
+    fn rows(view: &View) -> Vec<(String, usize)> {
+        view.screen_rows(None, None)
+    }
+
+    #[test]
+    fn tabs_and_control_characters() {
+        let view = view("\tx\nab\tc\n\x1b[m", 20, 5, Wrap::Scroll);
+
+        assert_eq!(
+            rows(&view),
+            [
+                (String::from("        x"), 9),
+                (String::from("ab      c"), 9),
+                (String::from("\u{241b}[m"), 3),
+            ]
+        );
+        assert_eq!(view.window_position(&Location::new(1, 0)), (8, 0));
+    }
+
+    #[test]
+    fn horizontal_scrolling() {
+        let mut view = view("abcdefghijklmnopqrstuvwxyz\nshort", 10, 5, Wrap::Scroll);
+
+        view.follow(&Location::new(15, 0));
+        assert_eq!(view.left, 6);
+        assert_eq!(view.window_position(&Location::new(15, 0)), (9, 0));
+        assert_eq!(
+            rows(&view),
+            [(String::from("ghijklmnop"), 10), (String::new(), 0)]
+        );
+
+        view.follow(&Location::new(2, 1));
+        assert_eq!(view.left, 2);
+    }
+
+    #[test]
+    fn wide_characters_cut_off_by_the_edges() {
+        let mut view = view("日本語日本語", 5, 5, Wrap::Scroll);
+        view.left = 3;
+
+        // Half a character at either edge is left blank
+        assert_eq!(rows(&view), [(String::from(" 語日"), 5)]);
+    }
+
+    #[test]
+    fn soft_wrapping() {
+        let view = view("abcdefghijkl\nab日本語", 5, 10, Wrap::Soft);
+
+        assert_eq!(view.row_starts(0), [0, 5, 10]);
+        // A wide character that doesn't fit goes on the next row
+        assert_eq!(view.row_starts(1), [0, 3]);
+        assert_eq!(
+            rows(&view),
+            [
+                (String::from("abcde"), 5),
+                (String::from("fghij"), 5),
+                (String::from("kl"), 2),
+                (String::from("ab日"), 4),
+                (String::from("本語"), 4),
+            ]
+        );
+        assert_eq!(view.window_position(&Location::new(7, 0)), (2, 1));
+        assert_eq!(view.window_position(&Location::new(4, 1)), (2, 4));
+    }
+
+    #[test]
+    fn moving_by_screen_rows() {
+        let wrapped = view("abcdefghijkl\nxy", 5, 10, Wrap::Soft);
+
+        assert_eq!(
+            wrapped.move_screen_row(&Location::new(2, 0), true),
+            Location::new(7, 0)
+        );
+        // The last row reaches to the end of the line, where insert mode can put the cursor
+        assert_eq!(
+            wrapped.move_screen_row(&Location::new(7, 0), true),
+            Location::new(12, 0)
+        );
+        assert_eq!(
+            wrapped.move_screen_row(&Location::new(11, 0), true),
+            Location::new(1, 1)
+        );
+        assert_eq!(
+            wrapped.move_screen_row(&Location::new(1, 1), false),
+            Location::new(11, 0)
+        );
+        assert_eq!(
+            wrapped.move_screen_row(&Location::new(1, 1), true),
+            Location::new(1, 1)
+        );
+
+        // Without wrapping it moves by lines
+        let unwrapped = view("abcdefghijkl\nxy", 5, 10, Wrap::Scroll);
+        assert_eq!(
+            unwrapped.move_screen_row(&Location::new(1, 0), true),
+            Location::new(1, 1)
+        );
+    }
+
+    #[test]
+    fn following_wrapped_lines() {
+        let mut view = view("aaaaaaaaaa\nb\nc", 5, 3, Wrap::Soft);
+
+        view.follow(&Location::new(0, 1));
+        assert_eq!(view.offset(), 0);
+        assert_eq!(view.bottom(), 1);
+        view.follow(&Location::new(0, 2));
+        assert_eq!(view.offset(), 1);
+    }
+
+    #[test]
+    fn status_lines() {
+        let mut view = view("one\ntwo\nthree\nfour", 20, 5, Wrap::Scroll);
+        let pos = Location::new(1, 1);
+
+        assert_eq!(
+            view.status_text(STATUS_LINE, &pos, "NORMAL"),
+            (String::from(" test.txt "), String::from("NORMAL  2:2 "))
+        );
+
+        view.buffer_mut().insert(&Location::new(0, 0), "x");
+        view.buffer_mut().readonly = true;
+        assert_eq!(
+            view.status_text(STATUS_LINE, &pos, "").0,
+            " test.txt [+][RO]"
+        );
+        assert_eq!(
+            view.status_text("%L lines, %p%% %v %q", &pos, ""),
+            (String::from("4 lines, 50% 2 %q"), String::new())
+        );
+    }
+
+    #[test]
+    fn cutting_to_width() {
+        assert_eq!(cut_to_width("abcdef", 4), (String::from("abcd"), 4));
+        // A wide character that doesn't fit is left out whole
+        assert_eq!(cut_to_width("ab日本", 3), (String::from("ab"), 2));
+        assert_eq!(cut_to_width("ab", 10), (String::from("ab"), 2));
+    }
 }
@@ -0,0 +1,736 @@
+use crate::buffer::Location;
+use crate::editor::Cursor;
+use crate::terminal::{move_cursor_to, print, Position};
+use crate::view::{Size, View};
+use std::io::Error;
+
+/// A window the cursor isn't in, with the number of the buffer it shows, its view of it and
+/// where its cursor was left. The editor holds the window the cursor is in itself.
+pub struct Window {
+    pub buffer: usize,
+    pub view: View,
+    pub cursor: Cursor,
+}
+
+/// How the screen is divided between windows. Each part of a split keeps the number of rows
+/// or columns it had when the windows were last arranged, and the parts are scaled to fit
+/// when the screen changes size.
+enum Layout {
+    Window(usize),
+    /// Parts side by side if vertical, or else one above another.
+    Split {
+        vertical: bool,
+        children: Vec<(Layout, u16)>,
+    },
+}
+
+impl Layout {
+    /// Returns the way to a window from here, as the index of the part it is in at each split.
+    fn path(&self, id: usize) -> Option<Vec<usize>> {
+        match self {
+            Self::Window(window) => (*window == id).then(Vec::new),
+            Self::Split { children, .. } => {
+                children.iter().enumerate().find_map(|(i, (child, _))| {
+                    let mut path = child.path(id)?;
+                    path.insert(0, i);
+                    Some(path)
+                })
+            }
+        }
+    }
+
+    fn get_mut(&mut self, path: &[usize]) -> &mut Self {
+        match (self, path.split_first()) {
+            (Self::Split { children, .. }, Some((i, rest))) => children[*i].0.get_mut(rest),
+            (layout, _) => layout,
+        }
+    }
+
+    /// Returns the ids of the windows from the top left, in the order they are numbered.
+    fn windows(&self) -> Vec<usize> {
+        match self {
+            Self::Window(id) => vec![*id],
+            Self::Split { children, .. } => children
+                .iter()
+                .flat_map(|(child, _)| child.windows())
+                .collect(),
+        }
+    }
+
+    /// The fewest columns, if vertical, or rows the windows can be squeezed into, leaving
+    /// each a row for its text and one for its status line.
+    fn minimum(&self, vertical: bool) -> u16 {
+        match self {
+            Self::Window(_) => 2 - u16::from(vertical),
+            Self::Split {
+                vertical: side_by_side,
+                children,
+            } => {
+                let minimums = children.iter().map(|(child, _)| child.minimum(vertical));
+
+                if *side_by_side != vertical {
+                    minimums.max().unwrap_or(0)
+                } else if vertical {
+                    minimums.map(|minimum| minimum + 1).sum::<u16>() - 1
+                } else {
+                    minimums.sum()
+                }
+            }
+        }
+    }
+
+    /// Gives every part of every split the same share of it.
+    fn equalize(&mut self) {
+        if let Self::Split { children, .. } = self {
+            for (child, size) in children {
+                *size = 1;
+                child.equalize();
+            }
+        }
+    }
+
+    /// Works out the area of each window within `area`, and where the lines between windows
+    /// side by side go.
+    fn arrange(&mut self, area: Rect, windows: &mut Vec<(usize, Rect)>, lines: &mut Vec<Rect>) {
+        match self {
+            Self::Window(id) => windows.push((*id, area)),
+            Self::Split { vertical, children } => {
+                let vertical = *vertical;
+                let (start, length) = if vertical {
+                    (area.x, area.width)
+                } else {
+                    (area.y, area.height)
+                };
+                // Windows side by side have a line between each of them
+                let lines_between = if vertical { children.len() - 1 } else { 0 };
+                let weights: Vec<u16> = children.iter().map(|(_, size)| *size).collect();
+                let minimums: Vec<u16> = children
+                    .iter()
+                    .map(|(child, _)| child.minimum(vertical))
+                    .collect();
+                let sizes = fit(
+                    length.saturating_sub(lines_between.try_into().unwrap_or(u16::MAX)),
+                    &weights,
+                    &minimums,
+                );
+                let mut start = start;
+
+                for ((child, size), fitted) in children.iter_mut().zip(sizes) {
+                    *size = fitted;
+
+                    if vertical {
+                        child.arrange(
+                            Rect {
+                                x: start,
+                                width: fitted,
+                                ..area
+                            },
+                            windows,
+                            lines,
+                        );
+                    } else {
+                        child.arrange(
+                            Rect {
+                                y: start,
+                                height: fitted,
+                                ..area
+                            },
+                            windows,
+                            lines,
+                        );
+                    }
+                    start += fitted;
+
+                    if vertical && start < area.x + area.width {
+                        lines.push(Rect {
+                            x: start,
+                            width: 1,
+                            ..area
+                        });
+                        start += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Shares out `length` rows or columns in proportion to `weights`, giving each part at least
+/// its minimum while there is room for it.
+fn fit(length: u16, weights: &[u16], minimums: &[u16]) -> Vec<u16> {
+    let total = weights
+        .iter()
+        .map(|weight| u32::from(*weight))
+        .sum::<u32>()
+        .max(1);
+    let mut sizes: Vec<u16> = weights
+        .iter()
+        .map(|weight| {
+            (u32::from(length) * u32::from(*weight) / total)
+                .try_into()
+                .unwrap_or(u16::MAX)
+        })
+        .collect();
+
+    // What rounding down leaves over goes to the last parts, one each
+    let mut left_over = length.saturating_sub(sizes.iter().sum());
+    for size in sizes.iter_mut().rev() {
+        if left_over == 0 {
+            break;
+        }
+        *size += 1;
+        left_over -= 1;
+    }
+
+    // Parts below their minimum take rows from whichever part has most to spare
+    for i in 0..sizes.len() {
+        while sizes[i] < minimums[i] {
+            let spare = (0..sizes.len())
+                .filter(|j| sizes[*j] > minimums[*j])
+                .max_by_key(|j| sizes[*j] - minimums[*j]);
+
+            let Some(j) = spare else {
+                break;
+            };
+            sizes[j] -= 1;
+            sizes[i] += 1;
+        }
+    }
+    sizes
+}
+
+/// An area of the screen.
+#[derive(Clone, Copy)]
+struct Rect {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+}
+
+/// The windows the screen is split into.
+pub struct Windows {
+    /// The id of the window the cursor is in.
+    pub active: usize,
+    /// The windows besides the one the cursor is in.
+    others: Vec<(usize, Window)>,
+    layout: Layout,
+    next_id: usize,
+    /// Where each window was put when they were last arranged, including its status line.
+    areas: Vec<(usize, Rect)>,
+    /// The lines drawn between windows side by side.
+    lines: Vec<Rect>,
+}
+
+impl Windows {
+    pub const fn new() -> Self {
+        Self {
+            active: 1,
+            others: Vec::new(),
+            layout: Layout::Window(1),
+            next_id: 2,
+            areas: Vec::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.others.len() + 1
+    }
+
+    /// Splits the window the cursor is in, putting `window` in a new window above it, or to
+    /// its left if vertical. Returns the id of the new window, or `None` if there isn't room.
+    pub fn split(&mut self, vertical: bool, window: Window) -> Option<usize> {
+        let area = self.area(self.active)?;
+        let path = self.layout.path(self.active)?;
+
+        // Either half needs a row for its text and one for its status line, or a column with
+        // a line between the two
+        let (new, old) = if vertical {
+            let width = area.width.checked_sub(1).filter(|width| *width >= 2)?;
+            (width / 2, width - width / 2)
+        } else {
+            let height = area.height;
+            (height >= 4).then_some((height / 2, height - height / 2))?
+        };
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let halves = [
+            (Layout::Window(id), new),
+            (Layout::Window(self.active), old),
+        ];
+
+        // Splitting the same way as the split the window is in adds a part to that split
+        match path.split_last() {
+            Some((i, parent)) => match self.layout.get_mut(parent) {
+                Layout::Split {
+                    vertical: side_by_side,
+                    children,
+                } if *side_by_side == vertical => {
+                    children.splice(*i..=*i, halves);
+                }
+                layout => {
+                    *layout.get_mut(&[*i]) = Layout::Split {
+                        vertical,
+                        children: halves.into(),
+                    };
+                }
+            },
+            None => {
+                self.layout = Layout::Split {
+                    vertical,
+                    children: halves.into(),
+                };
+            }
+        }
+
+        self.others.push((id, window));
+        Some(id)
+    }
+
+    /// Closes a window, giving its space to the window before it, or after it if it's the
+    /// first in its split. Returns the id of the window that got the space, or `None` if
+    /// it's the only window, which can't be closed.
+    pub fn close(&mut self, id: usize) -> Option<usize> {
+        let path = self.layout.path(id)?;
+        let (i, parent) = path.split_last()?;
+        let Layout::Split { vertical, children } = self.layout.get_mut(parent) else {
+            return None;
+        };
+
+        let (_, size) = children.remove(*i);
+        let (neighbour, neighbour_size) = &mut children[i.saturating_sub(1)];
+        *neighbour_size += size + u16::from(*vertical);
+        let next = if *i > 0 {
+            neighbour.windows().last().copied()
+        } else {
+            neighbour.windows().first().copied()
+        };
+
+        // A split with one part left is replaced by it, and a split in that part joins the
+        // split around it, which divides the same way
+        if children.len() == 1 {
+            let (child, _) = children.remove(0);
+
+            match (parent.split_last(), child) {
+                (
+                    Some((j, grandparent)),
+                    Layout::Split {
+                        children: parts, ..
+                    },
+                ) => {
+                    if let Layout::Split { children, .. } = self.layout.get_mut(grandparent) {
+                        children.splice(*j..=*j, parts);
+                    }
+                }
+                (_, child) => *self.layout.get_mut(parent) = child,
+            }
+        }
+
+        self.others.retain(|(other, _)| *other != id);
+        next
+    }
+
+    /// Closes the window the cursor is in, making the window that gets its space active and
+    /// returning its contents, or `None` if it's the only window.
+    pub fn close_active(&mut self) -> Option<Window> {
+        let id = self.close(self.active)?;
+        let window = self.take(id)?;
+        self.active = id;
+        Some(window)
+    }
+
+    /// Closes every window but the one the cursor is in.
+    pub fn only(&mut self) {
+        self.others.clear();
+        self.layout = Layout::Window(self.active);
+    }
+
+    /// Makes every window the same size, as far as the screen divides evenly.
+    pub fn equalize(&mut self) {
+        self.layout.equalize();
+    }
+
+    /// Makes the window the cursor is in `size` rows tall including its status line, or
+    /// `size` columns wide if vertical, taking from or giving to the windows after it and
+    /// then before it. Nothing changes if there are no windows to take from or give to.
+    pub fn resize(&mut self, vertical: bool, size: u16) {
+        let Some(path) = self.layout.path(self.active) else {
+            return;
+        };
+
+        // The nearest split the window is in that divides the screen the right way
+        for depth in (0..path.len()).rev() {
+            let Layout::Split {
+                vertical: side_by_side,
+                children,
+            } = self.layout.get_mut(&path[..depth])
+            else {
+                continue;
+            };
+            if *side_by_side != vertical {
+                continue;
+            }
+
+            let i = path[depth];
+            let minimums: Vec<u16> = children
+                .iter()
+                .map(|(child, _)| child.minimum(vertical))
+                .collect();
+            let total: u16 = children.iter().map(|(_, size)| size).sum();
+            let others: u16 = minimums.iter().sum::<u16>() - minimums[i];
+            let size = size.min(total.saturating_sub(others)).max(minimums[i]);
+
+            let current = children[i].1;
+            let (mut taking, mut giving) =
+                (size.saturating_sub(current), current.saturating_sub(size));
+            children[i].1 = size;
+
+            for j in (i + 1..children.len()).chain((0..i).rev()) {
+                let spare = children[j].1.saturating_sub(minimums[j]);
+                let taken = taking.min(spare);
+                children[j].1 = children[j].1 - taken + giving;
+                taking -= taken;
+                giving = 0;
+            }
+            return;
+        }
+    }
+
+    /// Returns the number of rows, or columns if vertical, the window the cursor is in takes
+    /// up, including its status line.
+    pub fn size(&self, vertical: bool) -> u16 {
+        self.area(self.active)
+            .map_or(0, |area| if vertical { area.width } else { area.height })
+    }
+
+    fn area(&self, id: usize) -> Option<Rect> {
+        self.areas
+            .iter()
+            .find(|(window, _)| *window == id)
+            .map(|(_, area)| *area)
+    }
+
+    /// Works out where each window goes on a screen of the given size, below the first `top`
+    /// rows, and moves their views there, including `active`, the view of the window the
+    /// cursor is in. The last row is left for messages, and each window has a status line.
+    pub fn arrange(&mut self, screen: Size, top: u16, active: &mut View) {
+        let bottom = screen.height.saturating_sub(1);
+        let area = Rect {
+            x: 0,
+            y: top,
+            width: screen.width,
+            height: bottom.saturating_sub(top),
+        };
+
+        self.areas.clear();
+        self.lines.clear();
+        self.layout.arrange(area, &mut self.areas, &mut self.lines);
+
+        for (id, area) in &self.areas {
+            let view = if *id == self.active {
+                &mut *active
+            } else if let Some((_, window)) = self.others.iter_mut().find(|(other, _)| other == id)
+            {
+                &mut window.view
+            } else {
+                continue;
+            };
+
+            view.place(
+                Position::new(area.x, area.y),
+                Size {
+                    width: area.width,
+                    height: area.height.saturating_sub(1),
+                },
+                bottom,
+            );
+        }
+    }
+
+    /// Draws the windows the cursor isn't in, the status lines of every window and the lines
+    /// between them, the status lines in the given format. `active` is the view of the window
+    /// the cursor is in, which the editor draws itself, with the cursor at `pos` in `mode`.
+    pub fn render(
+        &mut self,
+        active: &View,
+        pos: &Location,
+        format: &str,
+        mode: &str,
+    ) -> Result<(), Error> {
+        for (_, window) in &mut self.others {
+            // The text may have been changed in another window
+            window.cursor.clamp(&window.view);
+            window.view.follow(window.cursor.position());
+            window.view.render(window.cursor.position(), None, None)?;
+            window
+                .view
+                .draw_status(format, window.cursor.position(), None)?;
+        }
+        active.draw_status(format, pos, Some(mode))?;
+
+        for line in &self.lines {
+            for row in line.y..line.y + line.height {
+                move_cursor_to(&Position::new(line.x, row))?;
+                print('│')?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the id of the window `count` windows away from the one the cursor is in, in the
+    /// direction of `h`, `j`, `k` or `l`, going to the window beside the cell `from` each
+    /// time, or the nearest to it. Returns `None` if there is no window that way.
+    pub fn neighbour(&self, direction: char, from: &Position, count: usize) -> Option<usize> {
+        let mut found = None;
+        let mut current = self.area(self.active)?;
+
+        for _ in 0..count {
+            let beside = self.areas.iter().filter(|(_, area)| match direction {
+                'h' => area.x + area.width + 1 == current.x && overlaps_rows(area, &current),
+                'l' => current.x + current.width + 1 == area.x && overlaps_rows(area, &current),
+                'k' => area.y + area.height == current.y && overlaps_columns(area, &current),
+                'j' => current.y + current.height == area.y && overlaps_columns(area, &current),
+                _ => false,
+            });
+            let Some((id, area)) = beside.min_by_key(|(_, area)| {
+                if matches!(direction, 'h' | 'l') {
+                    distance(from.y, area.y, area.height)
+                } else {
+                    distance(from.x, area.x, area.width)
+                }
+            }) else {
+                break;
+            };
+
+            found = Some(*id);
+            current = *area;
+        }
+        found
+    }
+
+    /// Returns the id of the window after the one the cursor is in, or before it, wrapping
+    /// around, or of the window numbered `count` if one is given.
+    pub fn cycle(&self, count: Option<usize>, forward: bool) -> usize {
+        let ids = self.layout.windows();
+        let i = ids.iter().position(|id| *id == self.active).unwrap_or(0);
+
+        match count {
+            Some(count) => ids[count.clamp(1, ids.len()) - 1],
+            None if forward => ids[(i + 1) % ids.len()],
+            None => ids[(i + ids.len() - 1) % ids.len()],
+        }
+    }
+
+    /// Returns the views of the windows the cursor isn't in.
+    pub fn views(&self) -> impl Iterator<Item = &View> {
+        self.others.iter().map(|(_, window)| &window.view)
+    }
+
+    /// Returns the ids of the windows the cursor isn't in that show a buffer.
+    pub fn showing(&self, buffer: usize) -> Vec<usize> {
+        self.others
+            .iter()
+            .filter(|(_, window)| window.buffer == buffer)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Takes out the contents of a window for the cursor to move to it.
+    pub fn take(&mut self, id: usize) -> Option<Window> {
+        let i = self.others.iter().position(|(other, _)| *other == id)?;
+        Some(self.others.remove(i).1)
+    }
+
+    /// Puts back the contents of the window the cursor is leaving.
+    pub fn put(&mut self, id: usize, window: Window) {
+        self.others.push((id, window));
+    }
+}
+
+fn overlaps_rows(a: &Rect, b: &Rect) -> bool {
+    a.y < b.y + b.height && b.y < a.y + a.height
+}
+
+fn overlaps_columns(a: &Rect, b: &Rect) -> bool {
+    a.x < b.x + b.width && b.x < a.x + a.width
+}
+
+/// How far a row or column is from a span of them.
+const fn distance(from: u16, start: u16, length: u16) -> u16 {
+    if from < start {
+        start - from
+    } else {
+        from.saturating_sub(start + length - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::view::{tests::view, Wrap};
+
+    fn window(buffer: usize) -> Window {
+        Window {
+            buffer,
+            view: view("", 80, 23, Wrap::Scroll),
+            cursor: Cursor::new(Location::new(0, 0)),
+        }
+    }
+
+    /// Returns each window's id with its column, row, width and height, from the top left.
+    fn areas(windows: &mut Windows, width: u16, height: u16) -> Vec<(usize, u16, u16, u16, u16)> {
+        let mut active = view("", width, height, Wrap::Scroll);
+        windows.arrange(Size { width, height }, 0, &mut active);
+        windows
+            .areas
+            .iter()
+            .map(|(id, area)| (*id, area.x, area.y, area.width, area.height))
+            .collect()
+    }
+
+    #[test]
+    fn fitting() {
+        assert_eq!(fit(10, &[1, 1, 1], &[2, 2, 2]), [3, 3, 4]);
+        assert_eq!(fit(12, &[1, 2], &[2, 2]), [4, 8]);
+        // A part below its minimum takes from the others
+        assert_eq!(fit(5, &[8, 1], &[2, 2]), [3, 2]);
+        // Without room for the minimums, the parts get what there is
+        assert_eq!(fit(3, &[1, 1], &[2, 2]), [1, 2]);
+    }
+
+    #[test]
+    fn splitting() {
+        let mut windows = Windows::new();
+        areas(&mut windows, 80, 25);
+
+        assert_eq!(windows.split(false, window(1)), Some(2));
+        assert_eq!(windows.count(), 2);
+        assert_eq!(
+            areas(&mut windows, 80, 25),
+            [(2, 0, 0, 80, 12), (1, 0, 12, 80, 12)]
+        );
+
+        // The line between windows side by side takes a column
+        assert_eq!(windows.split(true, window(1)), Some(3));
+        assert_eq!(
+            areas(&mut windows, 80, 25),
+            [(2, 0, 0, 80, 12), (3, 0, 12, 39, 12), (1, 40, 12, 40, 12)]
+        );
+        assert_eq!(windows.lines.len(), 1);
+        assert_eq!(windows.size(true), 40);
+        assert_eq!(windows.size(false), 12);
+    }
+
+    #[test]
+    fn splitting_the_same_way_adds_a_part() {
+        let mut windows = Windows::new();
+        areas(&mut windows, 80, 25);
+        windows.split(false, window(1));
+        areas(&mut windows, 80, 25);
+        windows.split(false, window(1));
+
+        assert_eq!(
+            areas(&mut windows, 80, 25),
+            [(2, 0, 0, 80, 12), (3, 0, 12, 80, 6), (1, 0, 18, 80, 6)]
+        );
+        windows.equalize();
+        assert_eq!(
+            areas(&mut windows, 80, 25),
+            [(2, 0, 0, 80, 8), (3, 0, 8, 80, 8), (1, 0, 16, 80, 8)]
+        );
+    }
+
+    #[test]
+    fn no_room_to_split() {
+        let mut windows = Windows::new();
+        areas(&mut windows, 3, 5);
+
+        assert_eq!(windows.split(false, window(1)), Some(2));
+        areas(&mut windows, 3, 5);
+        assert_eq!(windows.split(false, window(1)), None);
+        assert_eq!(windows.split(true, window(1)), Some(3));
+        areas(&mut windows, 3, 5);
+        assert_eq!(windows.split(true, window(1)), None);
+    }
+
+    #[test]
+    fn closing() {
+        let mut windows = Windows::new();
+        areas(&mut windows, 80, 25);
+        windows.split(true, window(1));
+        areas(&mut windows, 80, 25);
+        windows.split(false, window(1));
+
+        // The space goes to the window before, or after for the first one
+        assert_eq!(windows.close(3), Some(1));
+        assert_eq!(
+            areas(&mut windows, 80, 25),
+            [(2, 0, 0, 39, 24), (1, 40, 0, 40, 24)]
+        );
+
+        let window = windows.close_active().unwrap();
+        assert_eq!(window.buffer, 1);
+        assert_eq!(windows.active, 2);
+        assert_eq!(areas(&mut windows, 80, 25), [(2, 0, 0, 80, 24)]);
+        assert!(windows.close_active().is_none());
+    }
+
+    #[test]
+    fn only() {
+        let mut windows = Windows::new();
+        areas(&mut windows, 80, 25);
+        windows.split(true, window(4));
+        windows.only();
+
+        assert_eq!(windows.count(), 1);
+        assert_eq!(windows.showing(4), Vec::<usize>::new());
+        assert_eq!(areas(&mut windows, 80, 25), [(1, 0, 0, 80, 24)]);
+    }
+
+    #[test]
+    fn resizing() {
+        let mut windows = Windows::new();
+        areas(&mut windows, 80, 25);
+        windows.split(false, window(1));
+        areas(&mut windows, 80, 25);
+
+        windows.resize(false, 5);
+        assert_eq!(
+            areas(&mut windows, 80, 25),
+            [(2, 0, 0, 80, 19), (1, 0, 19, 80, 5)]
+        );
+        // The other window keeps room for its text and status line
+        windows.resize(false, 100);
+        assert_eq!(
+            areas(&mut windows, 80, 25),
+            [(2, 0, 0, 80, 2), (1, 0, 2, 80, 22)]
+        );
+        // There is nothing side by side to take columns from
+        windows.resize(true, 10);
+        assert_eq!(windows.size(true), 80);
+    }
+
+    #[test]
+    fn moving_between_windows() {
+        let mut windows = Windows::new();
+        areas(&mut windows, 80, 25);
+        windows.split(true, window(1));
+        areas(&mut windows, 80, 25);
+        windows.split(false, window(1));
+        areas(&mut windows, 80, 25);
+
+        // 2 is on the left, with 3 above 1 on the right
+        let from = Position::new(50, 20);
+        assert_eq!(windows.neighbour('h', &from, 1), Some(2));
+        assert_eq!(windows.neighbour('k', &from, 1), Some(3));
+        assert_eq!(windows.neighbour('l', &from, 1), None);
+        assert_eq!(windows.neighbour('j', &from, 1), None);
+
+        assert_eq!(windows.cycle(None, true), 2);
+        assert_eq!(windows.cycle(None, false), 3);
+        assert_eq!(windows.cycle(Some(2), true), 3);
+        assert_eq!(windows.cycle(Some(9), true), 1);
+    }
+}